@@ -0,0 +1,14 @@
+-- Connections with a digest interval are buffered and posted periodically
+-- instead of being relayed in real time (NULL interval = real time).
+ALTER TABLE "Connections" ADD COLUMN "digest_interval" INTEGER;
+ALTER TABLE "Connections" ADD COLUMN "next_digest"     INTEGER;
+
+CREATE TABLE IF NOT EXISTS "DigestBuffer" (
+  "id"          INTEGER PRIMARY KEY NOT NULL,
+  "connection"  INTEGER             NOT NULL,
+  "author"      TEXT                NOT NULL,
+  "content"     TEXT                NOT NULL,
+  "link"        TEXT                NOT NULL,
+  "timestamp"   INTEGER             NOT NULL,
+  FOREIGN KEY ("connection") REFERENCES "Connections"("id") ON DELETE CASCADE
+);
@@ -0,0 +1,13 @@
+-- Per connection formatting of relayed messages, connections without a row
+-- use the default template.
+CREATE TABLE IF NOT EXISTS "Templates" (
+  "connection"  INTEGER PRIMARY KEY NOT NULL,
+  "embed"       BOOLEAN             NOT NULL DEFAULT true,
+  "color"       INTEGER             NOT NULL,
+  "title"       TEXT,
+  "body"        TEXT                NOT NULL,
+  "footer"      TEXT,
+  "timestamp"   BOOLEAN             NOT NULL DEFAULT false,
+  "mentions"    TEXT                NOT NULL DEFAULT 'above',
+  FOREIGN KEY ("connection") REFERENCES "Connections"("id") ON DELETE CASCADE
+);
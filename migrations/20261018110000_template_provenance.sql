@@ -0,0 +1,3 @@
+-- Show where a relayed message came from (source server/channel and a jump
+-- link to the original message).
+ALTER TABLE "Templates" ADD COLUMN "provenance" BOOLEAN NOT NULL DEFAULT true;
@@ -0,0 +1,44 @@
+-- Rows removed by destructive commands are kept for a while so that the
+-- user can restore them with /undo.
+CREATE TABLE IF NOT EXISTS "TrashActions" (
+  "id"          INTEGER PRIMARY KEY NOT NULL,
+  "user"        INTEGER             NOT NULL,
+  "command"     TEXT                NOT NULL,
+  "created"     INTEGER             NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS "TrashConnections" (
+  "action"          INTEGER NOT NULL,
+  "id"              INTEGER NOT NULL,
+  "source"          INTEGER NOT NULL,
+  "target"          INTEGER NOT NULL,
+  "user"            INTEGER NOT NULL,
+  "webhook"         INTEGER NOT NULL,
+  "digest_interval" INTEGER,
+  "next_digest"     INTEGER,
+  FOREIGN KEY ("action") REFERENCES "TrashActions"("id") ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS "TrashTemplates" (
+  "action"      INTEGER NOT NULL,
+  "connection"  INTEGER NOT NULL,
+  "embed"       BOOLEAN NOT NULL,
+  "color"       INTEGER NOT NULL,
+  "title"       TEXT,
+  "body"        TEXT    NOT NULL,
+  "footer"      TEXT,
+  "timestamp"   BOOLEAN NOT NULL,
+  "mentions"    TEXT    NOT NULL,
+  "provenance"  BOOLEAN NOT NULL,
+  FOREIGN KEY ("action") REFERENCES "TrashActions"("id") ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS "TrashMentions" (
+  "action"      INTEGER NOT NULL,
+  "id"          INTEGER NOT NULL,
+  "source"      INTEGER,
+  "target"      INTEGER NOT NULL,
+  "mention"     TEXT    NOT NULL,
+  "user"        INTEGER NOT NULL,
+  FOREIGN KEY ("action") REFERENCES "TrashActions"("id") ON DELETE CASCADE
+);
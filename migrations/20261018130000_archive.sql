@@ -0,0 +1,23 @@
+-- Every message handled for a connection, kept for auditing until it falls
+-- outside of the retention period. Not tied to the connection so that the
+-- record outlives a disconnect.
+CREATE TABLE IF NOT EXISTS "Archive" (
+  "id"           INTEGER PRIMARY KEY NOT NULL,
+  "message"      INTEGER             NOT NULL,
+  "connection"   INTEGER             NOT NULL,
+  "author"       INTEGER             NOT NULL,
+  "author_name"  TEXT                NOT NULL,
+  "guild"        INTEGER,
+  "source"       INTEGER             NOT NULL,
+  "target"       INTEGER             NOT NULL,
+  "content"      TEXT                NOT NULL,
+  "attachments"  TEXT                NOT NULL,
+  "created"      INTEGER             NOT NULL,
+  "edited"       INTEGER,
+  "relayed"      INTEGER             NOT NULL,
+  "status"       TEXT                NOT NULL,
+  "error"        TEXT
+);
+
+CREATE INDEX IF NOT EXISTS "ArchiveRelayed" ON "Archive" ("relayed");
+CREATE INDEX IF NOT EXISTS "ArchiveMessage" ON "Archive" ("message");
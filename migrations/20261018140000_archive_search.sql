@@ -0,0 +1,22 @@
+-- Full-text index of the archived messages, kept in sync with the archive
+-- by the triggers below.
+CREATE VIRTUAL TABLE IF NOT EXISTS "ArchiveSearch" USING fts5(
+  "content",
+  "author_name",
+  content='Archive',
+  content_rowid='id'
+);
+
+CREATE TRIGGER IF NOT EXISTS "ArchiveSearchInsert" AFTER INSERT ON "Archive" BEGIN
+  INSERT INTO "ArchiveSearch" (rowid, "content", "author_name")
+  VALUES (new."id", new."content", new."author_name");
+END;
+
+CREATE TRIGGER IF NOT EXISTS "ArchiveSearchDelete" AFTER DELETE ON "Archive" BEGIN
+  INSERT INTO "ArchiveSearch" ("ArchiveSearch", rowid, "content", "author_name")
+  VALUES ('delete', old."id", old."content", old."author_name");
+END;
+
+INSERT INTO "ArchiveSearch" ("ArchiveSearch") VALUES ('rebuild');
+
+CREATE INDEX IF NOT EXISTS "ArchiveCreated" ON "Archive" ("created");
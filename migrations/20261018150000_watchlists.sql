@@ -0,0 +1,18 @@
+-- Connections with a watchlist only relay messages mentioning one of its
+-- symbols (no rows = everything is relayed).
+CREATE TABLE IF NOT EXISTS "Watchlists" (
+  "connection"  INTEGER NOT NULL,
+  "symbol"      TEXT    NOT NULL,
+  PRIMARY KEY ("connection", "symbol"),
+  FOREIGN KEY ("connection") REFERENCES "Connections"("id") ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS "TrashWatchlists" (
+  "action"      INTEGER NOT NULL,
+  "connection"  INTEGER NOT NULL,
+  "symbol"      TEXT    NOT NULL,
+  FOREIGN KEY ("action") REFERENCES "TrashActions"("id") ON DELETE CASCADE
+);
+
+-- Tickers found in the archived message, comma separated.
+ALTER TABLE "Archive" ADD COLUMN "tickers" TEXT NOT NULL DEFAULT '';
@@ -0,0 +1,7 @@
+-- Mention rules with a condition (keyword, regex, ticker or author) are only
+-- attached to relayed messages that match it (NULL = always attached).
+ALTER TABLE "Mentions" ADD COLUMN "condition_kind" TEXT;
+ALTER TABLE "Mentions" ADD COLUMN "condition"      TEXT;
+
+ALTER TABLE "TrashMentions" ADD COLUMN "condition_kind" TEXT;
+ALTER TABLE "TrashMentions" ADD COLUMN "condition"      TEXT;
@@ -0,0 +1,9 @@
+-- Personal alerts, users get a direct message when a relayed message matches
+-- one of their patterns. Kinds are the same as for mention conditions.
+CREATE TABLE IF NOT EXISTS "Alerts" (
+  "id"          INTEGER PRIMARY KEY NOT NULL,
+  "user"        INTEGER             NOT NULL,
+  "kind"        TEXT                NOT NULL,
+  "pattern"     TEXT                NOT NULL,
+  UNIQUE ("user", "kind", "pattern")
+);
@@ -0,0 +1,11 @@
+-- Messages per minute relayed through a connection (NULL = no limit of its
+-- own) and what happens to the messages over it: drop, queue or collapse.
+ALTER TABLE "Connections" ADD COLUMN "rate_limit" INTEGER;
+ALTER TABLE "Connections" ADD COLUMN "overflow"   TEXT    NOT NULL DEFAULT 'queue';
+
+ALTER TABLE "TrashConnections" ADD COLUMN "rate_limit" INTEGER;
+ALTER TABLE "TrashConnections" ADD COLUMN "overflow"   TEXT    NOT NULL DEFAULT 'queue';
+
+-- Messages per minute sent through the webhook of a target channel, shared by
+-- every connection into it (NULL = the default of 30).
+ALTER TABLE "Channels" ADD COLUMN "rate_limit" INTEGER;
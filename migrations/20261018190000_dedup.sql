@@ -0,0 +1,5 @@
+-- Messages with the same content relayed into a target channel within the
+-- window (in seconds) are only posted once (NULL = no de-duplication). With
+-- "dedup_annotate" the posted copy lists the sources of the duplicates.
+ALTER TABLE "Channels" ADD COLUMN "dedup_window"   INTEGER;
+ALTER TABLE "Channels" ADD COLUMN "dedup_annotate" INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,19 @@
+-- Regex replace rules applied to the content relayed through a connection,
+-- in the order they were added.
+CREATE TABLE IF NOT EXISTS "Transforms" (
+  "id"          INTEGER PRIMARY KEY NOT NULL,
+  "connection"  INTEGER             NOT NULL,
+  "pattern"     TEXT                NOT NULL,
+  "replacement" TEXT                NOT NULL,
+  UNIQUE ("connection", "pattern"),
+  FOREIGN KEY ("connection") REFERENCES "Connections"("id") ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS "TrashTransforms" (
+  "action"      INTEGER NOT NULL,
+  "connection"  INTEGER NOT NULL,
+  "id"          INTEGER NOT NULL,
+  "pattern"     TEXT    NOT NULL,
+  "replacement" TEXT    NOT NULL,
+  FOREIGN KEY ("action") REFERENCES "TrashActions"("id") ON DELETE CASCADE
+);
@@ -0,0 +1,32 @@
+-- Kind of the mapped channel: text, news or forum.
+ALTER TABLE "Channels" ADD COLUMN "kind" TEXT NOT NULL DEFAULT 'text';
+
+-- Threads and forum posts of the mapped channels. They can't have webhooks of
+-- their own, messages are posted through the webhook of the parent.
+CREATE TABLE IF NOT EXISTS "Threads" (
+  "id"          INTEGER PRIMARY KEY NOT NULL,
+  "name"        TEXT                NOT NULL,
+  "parent"      INTEGER             NOT NULL,
+  FOREIGN KEY ("parent") REFERENCES "Channels"("id") ON DELETE CASCADE
+);
+
+-- Thread of the target channel a connection posts in (NULL = the channel).
+ALTER TABLE "Connections" ADD COLUMN "thread" INTEGER;
+ALTER TABLE "TrashConnections" ADD COLUMN "thread" INTEGER;
+
+-- The target thread messages of a source thread are relayed into.
+CREATE TABLE IF NOT EXISTS "ThreadMirrors" (
+  "connection"  INTEGER NOT NULL,
+  "source"      INTEGER NOT NULL,
+  "target"      INTEGER NOT NULL,
+  PRIMARY KEY ("connection", "source"),
+  FOREIGN KEY ("connection") REFERENCES "Connections"("id") ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS "TrashThreadMirrors" (
+  "action"      INTEGER NOT NULL,
+  "connection"  INTEGER NOT NULL,
+  "source"      INTEGER NOT NULL,
+  "target"      INTEGER NOT NULL,
+  FOREIGN KEY ("action") REFERENCES "TrashActions"("id") ON DELETE CASCADE
+);
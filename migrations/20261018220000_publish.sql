@@ -0,0 +1,3 @@
+-- Publish relayed messages to the followers of a news (announcement) target.
+ALTER TABLE "Connections" ADD COLUMN "publish" INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE "TrashConnections" ADD COLUMN "publish" INTEGER NOT NULL DEFAULT 0;
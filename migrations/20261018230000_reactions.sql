@@ -0,0 +1,9 @@
+-- The copy a delivered message was relayed as: its id, the channel (or thread)
+-- it was posted in and the content of its first part, so it can be edited.
+ALTER TABLE "Archive" ADD COLUMN "copy"         INTEGER;
+ALTER TABLE "Archive" ADD COLUMN "copy_channel" INTEGER;
+ALTER TABLE "Archive" ADD COLUMN "copy_content" TEXT;
+
+-- Reactions on the source message are shown on the relayed copies.
+ALTER TABLE "Connections" ADD COLUMN "mirror_reactions" INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE "TrashConnections" ADD COLUMN "mirror_reactions" INTEGER NOT NULL DEFAULT 0;
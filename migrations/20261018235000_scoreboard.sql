@@ -0,0 +1,4 @@
+-- Reactions received on the relayed copy, for the scoreboard of the authors.
+ALTER TABLE "Archive" ADD COLUMN "reactions" INTEGER NOT NULL DEFAULT 0;
+
+CREATE INDEX IF NOT EXISTS "ArchiveCopy" ON "Archive" ("copy");
@@ -0,0 +1,9 @@
+-- Messages held back by the rate limit of their connection, so that they are
+-- still relayed after a restart.
+CREATE TABLE IF NOT EXISTS "RelayQueue" (
+  "connection"  INTEGER NOT NULL,
+  "message"     INTEGER NOT NULL,
+  "payload"     TEXT    NOT NULL,
+  PRIMARY KEY ("connection", "message"),
+  FOREIGN KEY ("connection") REFERENCES "Connections"("id") ON DELETE CASCADE
+);
@@ -0,0 +1,12 @@
+-- Alerts belong to the server they were added in, they only match messages
+-- from that server or relayed into it. Alerts from before can't be assigned
+-- to a server and are dropped.
+DROP TABLE IF EXISTS "Alerts";
+CREATE TABLE IF NOT EXISTS "Alerts" (
+  "id"          INTEGER PRIMARY KEY NOT NULL,
+  "user"        INTEGER             NOT NULL,
+  "guild"       INTEGER             NOT NULL,
+  "kind"        TEXT                NOT NULL,
+  "pattern"     TEXT                NOT NULL,
+  UNIQUE ("user", "guild", "kind", "pattern")
+);
@@ -0,0 +1,5 @@
+-- What's shown below the content of a relayed copy: the note on where else
+-- it was posted and the footer with the reactions on the source message.
+-- Kept so that updating one doesn't wipe the other.
+ALTER TABLE "Archive" ADD COLUMN "copy_note"   TEXT;
+ALTER TABLE "Archive" ADD COLUMN "copy_footer" TEXT;
@@ -0,0 +1,30 @@
+-- Scoreboard totals per day, connection, author and source server (0 if
+-- unknown). The connection scopes them to its owner. Kept apart from the archive so that pruning it doesn't reset them.
+-- A message only counts in `messages` for the first target it reached.
+CREATE TABLE IF NOT EXISTS "Scores" (
+  "day"         INTEGER NOT NULL,
+  "connection"  INTEGER NOT NULL,
+  "author"      INTEGER NOT NULL,
+  "author_name" TEXT    NOT NULL,
+  "guild"       INTEGER NOT NULL,
+  "target"      INTEGER NOT NULL,
+  "messages"    INTEGER NOT NULL DEFAULT 0,
+  "reactions"   INTEGER NOT NULL DEFAULT 0,
+  PRIMARY KEY ("day", "connection", "author", "guild")
+);
+
+INSERT OR IGNORE INTO "Scores" (day, connection, author, author_name, guild, target, messages, reactions)
+SELECT
+  relayed / 86400,
+  connection,
+  author,
+  MAX(author_name),
+  COALESCE(guild, 0),
+  target,
+  SUM(id = (SELECT MIN(first.id) FROM "Archive" first
+            WHERE first.message = "Archive".message
+            AND first.status IN ('delivered', 'buffered'))),
+  SUM(reactions)
+FROM "Archive"
+WHERE status IN ('delivered', 'buffered')
+GROUP BY relayed / 86400, connection, author, COALESCE(guild, 0);
@@ -0,0 +1,184 @@
+use anyhow::{anyhow, bail, Error, Result};
+use serenity::model::id::{ChannelId, GuildId, UserId};
+use sqlx::SqlitePool;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{condition::MentionCondition, dedup::content_hash};
+
+// Most alerts a single user can have.
+pub const MAX_ALERTS: usize = 25;
+
+// A user gets at most this many alerts per window, the rest is dropped.
+const RATE_LIMIT: usize = 5;
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+// The same content (e.g. cross-posted in several sources) is only sent once per window.
+const DEDUP_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+/// An alert as it is shown to its user and picked in `/alert-remove`,
+/// e.g. `keyword: btc`.
+pub fn alert_name(condition: &MentionCondition) -> String {
+    format!("{}: {}", condition.kind(), condition.value())
+}
+
+/// Parse the name of an alert, plain patterns are taken as keywords.
+pub fn parse_alert(s: &str) -> Result<MentionCondition> {
+    match s.split_once(':') {
+        Some((kind @ ("keyword" | "regex" | "ticker"), pattern)) => {
+            MentionCondition::parse(kind, pattern)
+        }
+        _ => MentionCondition::parse("keyword", s),
+    }
+}
+
+/// The alerts of the user in the server.
+pub async fn get_alerts(
+    db: &SqlitePool,
+    user: &UserId,
+    guild: &GuildId,
+) -> Result<Vec<MentionCondition>> {
+    let user = user.0 as i64;
+    let guild = guild.0 as i64;
+    let rows = sqlx::query!(
+        "SELECT kind, pattern FROM Alerts WHERE user = ? AND guild = ? ORDER BY kind, pattern",
+        user,
+        guild
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| Error::new(e).context("Failed to retrieve alerts from the database"))?;
+
+    rows.into_iter()
+        .map(|row| MentionCondition::parse(&row.kind, &row.pattern))
+        .collect()
+}
+
+/// The alerts that apply to messages of the source channel: those added in
+/// its server and in the servers it's relayed into.
+pub async fn source_alerts(
+    db: &SqlitePool,
+    source: &ChannelId,
+    guild: &GuildId,
+) -> Result<Vec<(UserId, MentionCondition)>> {
+    let source = source.0 as i64;
+    let guild = guild.0 as i64;
+    let rows = sqlx::query!(
+        "
+        SELECT user as \"user: i64\", kind, pattern FROM Alerts\n\
+        WHERE guild = ? OR guild IN\n\
+        (SELECT Channels.guild FROM Connections\n\
+        JOIN Channels ON Channels.id = Connections.target\n\
+        WHERE Connections.source = ?)\n\
+        ORDER BY id
+        ",
+        guild,
+        source
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| Error::new(e).context("Failed to retrieve alerts from the database"))?;
+
+    let mut alerts = Vec::new();
+    for row in rows {
+        match MentionCondition::parse(&row.kind, &row.pattern) {
+            Ok(condition) => alerts.push((UserId(row.user as u64), condition)),
+            Err(e) => println!("Skipping broken alert of {}: {:?}", row.user, e),
+        }
+    }
+    Ok(alerts)
+}
+
+/// Returns false if the user already has the alert.
+pub async fn add_alert(
+    db: &SqlitePool,
+    user: &UserId,
+    guild: &GuildId,
+    condition: &MentionCondition,
+) -> Result<bool> {
+    let existing = get_alerts(db, user, guild).await?;
+    if existing.contains(condition) {
+        return Ok(false);
+    }
+    if existing.len() >= MAX_ALERTS {
+        bail!("You can have at most {MAX_ALERTS} alerts");
+    }
+
+    let user = user.0 as i64;
+    let guild = guild.0 as i64;
+    let kind = condition.kind();
+    let pattern = condition.value();
+    sqlx::query!(
+        "INSERT INTO Alerts (user, guild, kind, pattern) VALUES (?, ?, ?, ?)",
+        user,
+        guild,
+        kind,
+        pattern
+    )
+    .execute(db)
+    .await
+    .map_err(|e| Error::new(e).context("Failed to insert alert into the database"))?;
+
+    Ok(true)
+}
+
+pub async fn remove_alert(
+    db: &SqlitePool,
+    user: &UserId,
+    guild: &GuildId,
+    condition: &MentionCondition,
+) -> Result<()> {
+    let user = user.0 as i64;
+    let guild = guild.0 as i64;
+    let kind = condition.kind();
+    let pattern = condition.value();
+    let rows = sqlx::query!(
+        "DELETE FROM Alerts WHERE user = ? AND guild = ? AND kind = ? AND pattern = ?",
+        user,
+        guild,
+        kind,
+        pattern
+    )
+    .execute(db)
+    .await
+    .map_err(|e| Error::new(e).context("Failed to delete alert in the database"))?
+    .rows_affected();
+
+    match rows {
+        0 => Err(anyhow!("No such alert: `{}`", alert_name(condition))),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Default)]
+struct Recent {
+    sent: VecDeque<Instant>,
+    seen: VecDeque<(u64, Instant)>,
+}
+
+/// Alerts recently sent to each user, to rate limit and de-duplicate them.
+#[derive(Clone, Default)]
+pub struct AlertLimiter(Arc<Mutex<HashMap<UserId, Recent>>>);
+
+impl AlertLimiter {
+    /// Whether an alert with the content can be sent to the user now, and
+    /// if so count it as sent.
+    pub fn admit(&self, user: UserId, content: &str) -> bool {
+        let hash = content_hash(content);
+        let mut map = self.0.lock().unwrap();
+        let recent = map.entry(user).or_default();
+        recent.sent.retain(|t| t.elapsed() < RATE_WINDOW);
+        recent.seen.retain(|(_, t)| t.elapsed() < DEDUP_WINDOW);
+
+        if recent.seen.iter().any(|(h, _)| *h == hash) || recent.sent.len() >= RATE_LIMIT {
+            return false;
+        }
+        let now = Instant::now();
+        recent.sent.push_back(now);
+        recent.seen.push_back((hash, now));
+        true
+    }
+}
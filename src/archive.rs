@@ -0,0 +1,247 @@
+use anyhow::{anyhow, Error, Result};
+use regex::Regex;
+use serde_json::json;
+use serenity::model::{channel::Message, id::ChannelId};
+use sqlx::SqlitePool;
+use std::time::Duration;
+
+use crate::{digest::unix_now, ticker, transport::Posted};
+
+// How often the background task removes archived messages past the retention.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+const DAY: i64 = 24 * 60 * 60;
+
+// Archived messages are kept for 90 days unless configured otherwise.
+const DEFAULT_RETENTION_DAYS: i64 = 90;
+
+/// What happened to a message handled for a connection.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Delivery {
+    /// Posted in the target channel.
+    Delivered,
+    /// Posting in the target channel failed.
+    Failed,
+    /// Held back for the digest of the connection.
+    Buffered,
+    /// Over the rate limit and not relayed.
+    Dropped,
+    /// Over the rate limit and relayed later.
+    Queued,
+    /// Over the rate limit and counted in a "N more messages" summary.
+    Collapsed,
+    /// Already relayed into the target channel from another source.
+    Duplicate,
+}
+
+impl Delivery {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Delivery::Delivered => "delivered",
+            Delivery::Failed => "failed",
+            Delivery::Buffered => "buffered",
+            Delivery::Dropped => "dropped",
+            Delivery::Queued => "queued",
+            Delivery::Collapsed => "collapsed",
+            Delivery::Duplicate => "duplicate",
+        }
+    }
+}
+
+/// How long archived messages are kept, set with `ARCHIVE_RETENTION_DAYS=N`
+/// in the ".env" file (0 keeps them forever).
+#[derive(Clone, Copy)]
+pub struct Retention(Option<i64>);
+
+impl Retention {
+    pub fn parse(s: &str) -> Result<Self> {
+        match s.trim().parse::<i64>() {
+            Ok(0) => Ok(Retention(None)),
+            Ok(days) if days > 0 => Ok(Retention(Some(days))),
+            _ => Err(anyhow!(
+                "Invalid archive retention (expected a number of days): {s}"
+            )),
+        }
+    }
+
+    pub async fn from_env() -> Self {
+        let content = tokio::fs::read_to_string(".env").await.unwrap_or_default();
+        let re = Regex::new(r"ARCHIVE_RETENTION_DAYS=(?P<days>\S+)").unwrap();
+        match re.captures(&content).map(|caps| Self::parse(&caps["days"])) {
+            Some(Ok(retention)) => retention,
+            Some(Err(e)) => {
+                println!(
+                    "{:?}, keeping archived messages for {DEFAULT_RETENTION_DAYS} days",
+                    e
+                );
+                Self::default()
+            }
+            None => Self::default(),
+        }
+    }
+
+    /// Messages relayed before this time are pruned.
+    fn cutoff(&self, now: i64) -> Option<i64> {
+        self.0.map(|days| now - days * DAY)
+    }
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Retention(Some(DEFAULT_RETENTION_DAYS))
+    }
+}
+
+/// Name, size, type and url of the attachments as a JSON array.
+fn attachments_metadata(msg: &Message) -> String {
+    let attachments: Vec<_> = msg
+        .attachments
+        .iter()
+        .map(|a| {
+            json!({
+                "filename": a.filename,
+                "size": a.size,
+                "content_type": a.content_type,
+                "url": a.url,
+            })
+        })
+        .collect();
+    serde_json::Value::from(attachments).to_string()
+}
+
+/// Record a message handled for the connection, `error` is the reason a
+/// failed delivery failed.
+pub async fn record(
+    db: &SqlitePool,
+    msg: &Message,
+    connection: i64,
+    target: &ChannelId,
+    delivery: Delivery,
+    error: Option<String>,
+) -> Result<()> {
+    let message = msg.id.0 as i64;
+    let author = msg.author.id.0 as i64;
+    let guild = msg.guild_id.map(|g| g.0 as i64);
+    let source = msg.channel_id.0 as i64;
+    let target = target.0 as i64;
+    let attachments = attachments_metadata(msg);
+    let tickers = ticker::extract(&msg.content)
+        .into_iter()
+        .map(|t| t.symbol)
+        .collect::<Vec<String>>()
+        .join(",");
+    let created = msg.timestamp.timestamp();
+    let edited = msg.edited_timestamp.map(|t| t.timestamp());
+    let relayed = unix_now();
+    let status = delivery.as_str();
+    sqlx::query!(
+        "
+        INSERT INTO Archive\n\
+        (message, connection, author, author_name, guild, source, target, content,\n\
+        attachments, tickers, created, edited, relayed, status, error)\n\
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        ",
+        message,
+        connection,
+        author,
+        msg.author.name,
+        guild,
+        source,
+        target,
+        msg.content,
+        attachments,
+        tickers,
+        created,
+        edited,
+        relayed,
+        status,
+        error
+    )
+    .execute(db)
+    .await
+    .map_err(|e| Error::new(e).context("Failed to archive relayed message in the database"))?;
+
+    Ok(())
+}
+
+/// Record the copy a delivered message was relayed as, `content` is the
+/// content of its first part.
+pub async fn record_copy(
+    db: &SqlitePool,
+    msg: &Message,
+    connection: i64,
+    posted: &Posted,
+    content: &str,
+) -> Result<()> {
+    let message = msg.id.0 as i64;
+    let copy = posted.id.0 as i64;
+    let channel = posted.channel.0 as i64;
+    sqlx::query!(
+        "
+        UPDATE Archive SET copy = ?, copy_channel = ?, copy_content = ?\n\
+        WHERE id = (SELECT MAX(id) FROM Archive WHERE message = ? AND connection = ?)
+        ",
+        copy,
+        channel,
+        content,
+        message,
+        connection
+    )
+    .execute(db)
+    .await
+    .map_err(|e| Error::new(e).context("Failed to archive relayed copy in the database"))?;
+
+    Ok(())
+}
+
+/// Set the note on where else a relayed copy was posted, returns the footer
+/// with the reactions shown below it, if any.
+pub async fn set_copy_note(db: &SqlitePool, copy: &Posted, note: &str) -> Result<Option<String>> {
+    let copy = copy.id.0 as i64;
+    sqlx::query!(
+        "UPDATE Archive SET copy_note = ? WHERE copy = ?",
+        note,
+        copy
+    )
+    .execute(db)
+    .await
+    .map_err(|e| Error::new(e).context("Failed to archive note of the copy in the database"))?;
+    let row = sqlx::query!(
+        "SELECT copy_footer FROM Archive WHERE copy = ? AND copy_footer IS NOT NULL LIMIT 1",
+        copy
+    )
+    .fetch_optional(db)
+    .await
+    .map_err(|e| {
+        Error::new(e).context("Failed to retrieve footer of the copy from the database")
+    })?;
+
+    Ok(row.and_then(|row| row.copy_footer))
+}
+
+/// Remove the archived messages that are past the retention period.
+pub async fn prune(db: &SqlitePool, retention: Retention, now: i64) -> Result<u64> {
+    let cutoff = match retention.cutoff(now) {
+        Some(cutoff) => cutoff,
+        None => return Ok(0),
+    };
+    let pruned = sqlx::query!("DELETE FROM Archive WHERE relayed < ?", cutoff)
+        .execute(db)
+        .await
+        .map_err(|e| Error::new(e).context("Failed to prune the archive in the database"))?
+        .rows_affected();
+
+    Ok(pruned)
+}
+
+pub async fn run_prune_task(db: SqlitePool, retention: Retention) {
+    let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+    loop {
+        interval.tick().await;
+        match prune(&db, retention, unix_now()).await {
+            Ok(0) => (),
+            Ok(pruned) => println!("Pruned {pruned} archived messages"),
+            Err(e) => println!("{:?}", e),
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use serenity::{
+    http::Http,
+    model::{
+        channel::Message,
+        id::{ChannelId, MessageId, UserId},
+    },
+};
+
+// Most messages a single backfill relays.
+pub const MAX_MESSAGES: u64 = 500;
+
+// Stop looking further back after this many messages of the channel.
+const MAX_SCANNED: usize = 10_000;
+
+// Discord returns at most 100 messages per request.
+const PAGE_SIZE: u64 = 100;
+
+// The progress is reported after this many messages.
+pub const PROGRESS_INTERVAL: usize = 10;
+
+pub enum Backfill {
+    /// The last N messages of the connection's user.
+    Last(u64),
+    /// All messages of the connection's user since the date.
+    Since(DateTime<Utc>),
+}
+
+impl Backfill {
+    /// Parse either a number of messages or a date in the `YYYY-MM-DD` format.
+    pub fn parse(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if let Ok(count) = s.parse::<u64>() {
+            if count == 0 || count > MAX_MESSAGES {
+                bail!("Backfill has to be between 1 and {MAX_MESSAGES} messages, got: {count}");
+            }
+            return Ok(Backfill::Last(count));
+        }
+        let date = NaiveDate::parse_from_str(s, "%Y-%m-%d")
+            .map_err(|_| anyhow!("Invalid backfill (expected a number or YYYY-MM-DD): {s}"))?;
+        Ok(Backfill::Since(DateTime::from_utc(
+            date.and_hms(0, 0, 0),
+            Utc,
+        )))
+    }
+}
+
+impl std::fmt::Display for Backfill {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Backfill::Last(count) => write!(f, "Last {count} messages"),
+            Backfill::Since(date) => write!(f, "Since {}", date.format("%Y-%m-%d")),
+        }
+    }
+}
+
+/// Fetch the messages of `author` in the channel that the backfill covers,
+/// oldest first.
+pub async fn fetch_history(
+    http: &Http,
+    channel: ChannelId,
+    author: UserId,
+    backfill: &Backfill,
+) -> Result<Vec<Message>> {
+    // Messages fetched over HTTP don't carry the guild, it is needed for the jump links.
+    let guild = channel
+        .to_channel(http)
+        .await
+        .context(format!(
+            "Failed to retrieve channel from Discord: {channel}"
+        ))?
+        .guild()
+        .map(|c| c.guild_id);
+
+    let mut messages = Vec::new();
+    let mut before: Option<MessageId> = None;
+    let mut scanned = 0;
+    'pages: while scanned < MAX_SCANNED {
+        let page = channel
+            .messages(http, |r| {
+                r.limit(PAGE_SIZE);
+                if let Some(id) = before {
+                    r.before(id);
+                }
+                r
+            })
+            .await
+            .context(format!("Failed to retrieve message history of: {channel}"))?;
+        // Pages are sorted newest first.
+        before = match page.last() {
+            Some(msg) => Some(msg.id),
+            None => break,
+        };
+        scanned += page.len();
+
+        for mut msg in page {
+            if let Backfill::Since(date) = backfill {
+                if msg.timestamp < *date {
+                    break 'pages;
+                }
+            }
+            if msg.author.id != author || msg.author.bot {
+                continue;
+            }
+            msg.guild_id = msg.guild_id.or(guild);
+            messages.push(msg);
+            if let Backfill::Last(count) = backfill {
+                if messages.len() as u64 >= *count {
+                    break 'pages;
+                }
+            }
+        }
+    }
+
+    messages.reverse();
+    Ok(messages)
+}
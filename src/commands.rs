@@ -0,0 +1,2986 @@
+use anyhow::{anyhow, bail, Error, Result};
+use chrono::Utc;
+use futures::TryFutureExt;
+use serde_json::json;
+use serenity::{
+    async_trait,
+    builder::{
+        CreateApplicationCommand, CreateApplicationCommandOption, CreateApplicationCommands,
+    },
+    client::Context as ClientContext,
+    model::{
+        id::ChannelId,
+        interactions::{
+            application_command::{
+                ApplicationCommandInteraction, ApplicationCommandOptionType,
+                ApplicationCommandType, ResolvedTarget,
+            },
+            autocomplete::AutocompleteInteraction,
+        },
+    },
+};
+use sqlx::SqlitePool;
+use std::{cmp, collections::HashMap, sync::Arc, time::Duration};
+use sublime_fuzzy::best_match;
+
+use crate::{
+    alert,
+    backfill::Backfill,
+    condition::{MentionCondition, Subject},
+    confirm::PendingAction,
+    dedup,
+    digest::{self, DigestSchedule},
+    ratelimit::{self, Overflow},
+    reaction,
+    relay::{self, Relays, RELAY_COMMAND},
+    scoreboard,
+    search::{self, SearchQuery, SearchScope},
+    template::{self, MentionPlacement, Placeholders},
+    thread::{self, ChannelKind},
+    ticker, transform,
+    transport::DiscordTransport,
+    trash, AutocompleteResponse, CommandResponse, RelayContext, RelayState,
+};
+use crate::{
+    get_channel_webhook, get_connection_id, get_mention_rules, get_mentions, maybe_add_connection,
+    name_to_ids, run_backfill,
+};
+
+pub mod options;
+
+use options::{
+    alert_guild, closest_names, connect_target_channel_autocomplete,
+    connect_target_server_autocomplete, connection_from_options,
+    disconnect_target_channel_autocomplete, find_param, get_bool_opt, get_channel_opt, get_int_opt,
+    get_string_opt, get_user_opt, mention_condition_opt, optional_template_text,
+    parse_target_channel, per_minute_opt,
+};
+
+/// Option names, shared by the command declarations and their handlers.
+pub mod opt {
+    pub const ALERT: &str = "alert";
+    pub const ANNOTATE: &str = "annotate";
+    pub const AUTHOR: &str = "author";
+    pub const BACKFILL: &str = "backfill";
+    pub const BODY: &str = "body";
+    pub const BY: &str = "by";
+    pub const COLOR: &str = "color";
+    pub const DIGEST: &str = "digest";
+    pub const DIGEST_HOUR: &str = "digest_hour";
+    pub const ENABLED: &str = "enabled";
+    pub const FOOTER: &str = "footer";
+    pub const FROM: &str = "from";
+    pub const KEYWORD: &str = "keyword";
+    pub const KEYWORDS: &str = "keywords";
+    pub const KIND: &str = "kind";
+    pub const MENTIONS: &str = "mentions";
+    pub const OVERFLOW: &str = "overflow";
+    pub const PATTERN: &str = "pattern";
+    pub const PER_MINUTE: &str = "per_minute";
+    pub const PERIOD: &str = "period";
+    pub const PROVENANCE: &str = "provenance";
+    pub const PUBLIC: &str = "public";
+    pub const REGEX: &str = "regex";
+    pub const REPLACEMENT: &str = "replacement";
+    pub const RESET: &str = "reset";
+    pub const SERVER: &str = "server";
+    pub const SOURCE: &str = "source";
+    pub const STYLE: &str = "style";
+    pub const SYMBOLS: &str = "symbols";
+    pub const TARGET_CHANNEL: &str = "target_channel";
+    pub const TARGET_SERVER: &str = "target_server";
+    pub const THREAD: &str = "thread";
+    pub const TICKER: &str = "ticker";
+    pub const TIMESTAMP: &str = "timestamp";
+    pub const TITLE: &str = "title";
+    pub const TO: &str = "to";
+    pub const WINDOW: &str = "window";
+}
+
+/// Shared state the command handlers can use.
+pub struct CommandContext<'a> {
+    pub db: &'a SqlitePool,
+    pub ctx: &'a ClientContext,
+    pub relays: &'a Relays,
+    pub transport: &'a Arc<DiscordTransport>,
+    pub state: &'a RelayState,
+}
+
+pub enum CommandTiming {
+    /// Answered directly, within Discord's 3 second window.
+    Immediate,
+    /// Acknowledged straight away and answered by editing the original
+    /// response once done, or when the timeout is reached.
+    Deferred(Duration),
+}
+
+/// A slash or context menu command. Its options, how it is run and how its
+/// options are autocompleted are all declared in one place.
+#[async_trait]
+pub trait Command: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Description and options of the command, the name is already set.
+    fn create<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand;
+
+    /// Commands that run several queries or call Discord may not make it in time.
+    fn timing(&self) -> CommandTiming {
+        CommandTiming::Immediate
+    }
+
+    async fn run(
+        &self,
+        cx: &CommandContext<'_>,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResponse>;
+
+    async fn autocomplete(
+        &self,
+        _db: &SqlitePool,
+        _autocomplete: &AutocompleteInteraction,
+    ) -> Result<AutocompleteResponse> {
+        Err(anyhow!("Unhandled autocomplete:\n{}", self.name()))
+    }
+}
+
+trait ChannelOption {
+    /// Text, news (announcement) and forum channels.
+    fn connectable_channel_types(&mut self) -> &mut Self;
+}
+
+impl ChannelOption for CreateApplicationCommandOption {
+    fn connectable_channel_types(&mut self) -> &mut Self {
+        // serenity 0.10 predates forum channels (type 15), so the types are
+        // set directly.
+        self.0.insert("channel_types", json!([0, 5, 15]));
+        self
+    }
+}
+
+/// Every command of the bot, looked up by name when an interaction comes in.
+pub struct Registry(Vec<Box<dyn Command>>);
+
+impl Default for Registry {
+    fn default() -> Self {
+        Registry(vec![
+            Box::new(Connect),
+            Box::new(Disconnect),
+            Box::new(DisconnectAll),
+            Box::new(ListConnections),
+            Box::new(WipeConnections),
+            Box::new(WipeMentions),
+            Box::new(MentionAdd),
+            Box::new(ListMentions),
+            Box::new(Undo),
+            Box::new(Template),
+            Box::new(Search),
+            Box::new(WatchlistAdd),
+            Box::new(WatchlistRemove),
+            Box::new(WatchlistList),
+            Box::new(AlertAdd),
+            Box::new(AlertRemove),
+            Box::new(AlertList),
+            Box::new(RateLimit),
+            Box::new(RateLimitTarget),
+            Box::new(Dedup),
+            Box::new(TransformAdd),
+            Box::new(TransformRemove),
+            Box::new(TransformList),
+            Box::new(TargetThread),
+            Box::new(AutoPublish),
+            Box::new(ReactionMirroring),
+            Box::new(Scoreboard),
+            Box::new(RelayMessage),
+        ])
+    }
+}
+
+impl Registry {
+    pub fn get(&self, name: &str) -> Option<&dyn Command> {
+        self.0.iter().find(|c| c.name() == name).map(|c| c.as_ref())
+    }
+
+    pub fn create_commands<'a>(
+        &self,
+        commands: &'a mut CreateApplicationCommands,
+    ) -> &'a mut CreateApplicationCommands {
+        for c in &self.0 {
+            commands.create_application_command(|command| c.create(command.name(c.name())));
+        }
+        commands
+    }
+}
+
+pub struct Connect;
+
+#[async_trait]
+impl Command for Connect {
+    fn name(&self) -> &'static str {
+        "connect"
+    }
+
+    fn create<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Connect a source channel to a target channel")
+            .create_option(|option| {
+                option
+                    .name(opt::SOURCE)
+                    .description("Source channel")
+                    .kind(ApplicationCommandOptionType::Channel)
+                    .required(true)
+                    .connectable_channel_types()
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::TARGET_SERVER)
+                    .description("Target server")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+                    .set_autocomplete(true)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::TARGET_CHANNEL)
+                    .description("Target channel")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+                    .set_autocomplete(true)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::DIGEST)
+                    .description("If set then messages are batched and posted as a periodic digest")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(false)
+                    .add_string_choice("Hourly", "hourly")
+                    .add_string_choice("Daily", "daily")
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::DIGEST_HOUR)
+                    .description("Hour of the day (UTC) when the daily digest is posted")
+                    .kind(ApplicationCommandOptionType::Integer)
+                    .required(false)
+                    .min_int_value(0)
+                    .max_int_value(23)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::BACKFILL)
+                    .description("Relay past messages: the last N or since a date (YYYY-MM-DD)")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(false)
+            })
+    }
+
+    fn timing(&self) -> CommandTiming {
+        CommandTiming::Deferred(Duration::from_secs(30))
+    }
+
+    async fn run(
+        &self,
+        cx: &CommandContext<'_>,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResponse> {
+        let db = cx.db;
+        let ctx = cx.ctx;
+        let transport = cx.transport;
+        let state = cx.state;
+        let options = &command.data.options;
+        let source = get_channel_opt(opt::SOURCE, options)?;
+        let target_server_name = get_string_opt(opt::TARGET_SERVER, options)?;
+        let target_channel_name = get_string_opt(opt::TARGET_CHANNEL, options)?;
+        let digest = match get_string_opt(opt::DIGEST, options) {
+            Ok(kind) => {
+                let hour = get_int_opt(opt::DIGEST_HOUR, options).ok();
+                Some(DigestSchedule::parse(kind, hour)?)
+            }
+            Err(_) => None,
+        };
+        let backfill = match get_string_opt(opt::BACKFILL, options) {
+            Ok(s) => Some(Backfill::parse(s)?),
+            Err(_) => None,
+        };
+        let (_target_server_id, target_channel_id) =
+            name_to_ids(db, target_server_name, target_channel_name).await?;
+
+        let webhook_id = get_channel_webhook(db, &target_channel_id).await?;
+
+        let result = maybe_add_connection(
+            db,
+            &source.id,
+            &target_channel_id,
+            &command.user.id,
+            &webhook_id,
+            digest.as_ref(),
+        )
+        .await?;
+
+        match result {
+            true => {
+                let title = "Connection created".to_owned();
+                let mode = match &digest {
+                    Some(schedule) => format!("\nDigest: {schedule}"),
+                    None => "".to_owned(),
+                };
+                let mode = match backfill {
+                    Some(backfill) => {
+                        let connection =
+                            get_connection_id(db, &source.id, &target_channel_id, &command.user.id)
+                                .await?;
+                        let msg = format!("{mode}\nBackfill: {backfill}");
+                        let (db, ctx, command) = (db.clone(), ctx.clone(), command.clone());
+                        let (transport, state, source) =
+                            (transport.clone(), state.clone(), source.id);
+                        tokio::spawn(async move {
+                            let cx = RelayContext {
+                                db: &db,
+                                sender: transport.as_ref(),
+                                resolver: transport.as_ref(),
+                                state: &state,
+                            };
+                            run_backfill(&cx, &ctx, &command, connection, source, &backfill).await;
+                        });
+                        msg
+                    }
+                    None => mode,
+                };
+                let msg = format!(
+                    "Source: <#{}>\nTarget server: __**{}**__\nTarget channel: <#{}>{}",
+                    source.id,
+                    target_server_name,
+                    target_channel_id.as_u64(),
+                    mode
+                );
+                Ok(CommandResponse {
+                    title,
+                    msg,
+                    ..Default::default()
+                })
+            }
+            false => Err(anyhow!("Connection already exists")),
+        }
+    }
+
+    async fn autocomplete(
+        &self,
+        db: &SqlitePool,
+        autocomplete: &AutocompleteInteraction,
+    ) -> Result<AutocompleteResponse> {
+        target_autocomplete(db, autocomplete).await
+    }
+}
+
+pub struct Disconnect;
+
+#[async_trait]
+impl Command for Disconnect {
+    fn name(&self) -> &'static str {
+        "disconnect"
+    }
+
+    fn create<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Disconnect one target channel from a source channel")
+            .create_option(|option| {
+                option
+                    .name(opt::SOURCE)
+                    .description("Source channel")
+                    .kind(ApplicationCommandOptionType::Channel)
+                    .required(true)
+                    .connectable_channel_types()
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::TARGET_CHANNEL)
+                    .description("Target channel")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+                    .set_autocomplete(true)
+            })
+    }
+
+    async fn run(
+        &self,
+        cx: &CommandContext<'_>,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResponse> {
+        let db = cx.db;
+        let options = &command.data.options;
+        let source_channel = get_channel_opt(opt::SOURCE, options)?;
+        let combined = get_string_opt(opt::TARGET_CHANNEL, options)?;
+        let (target_server_name, target_channel_name) = parse_target_channel(combined)?;
+
+        let (_target_server_id, target_channel_id) =
+            name_to_ids(db, &target_server_name, &target_channel_name).await?;
+
+        let source = source_channel.id.0 as i64;
+        let target = target_channel_id.0 as i64;
+        let user = command.user.id.0 as i64;
+
+        let ids: Vec<i64> = sqlx::query!(
+            "SELECT id FROM Connections WHERE source = ? AND target = ? AND user = ?",
+            source,
+            target,
+            user
+        )
+        .fetch_all(db)
+        .await
+        .map_err(|e| Error::new(e).context("Failed to retrieve connection from the database"))?
+        .into_iter()
+        .map(|row| row.id)
+        .collect();
+
+        trash::delete_connections(db, &command.user.id, "disconnect", &ids).await?;
+
+        let title = "Disconnected".to_owned();
+        let msg = format!(
+            "Source: <#{}>\nServer: {target_server_name}\nTarget: <#{}>\n\nUse /undo to restore it",
+            source, target,
+        );
+        Ok(CommandResponse {
+            title,
+            msg,
+            ..Default::default()
+        })
+    }
+
+    async fn autocomplete(
+        &self,
+        db: &SqlitePool,
+        autocomplete: &AutocompleteInteraction,
+    ) -> Result<AutocompleteResponse> {
+        connection_autocomplete(db, autocomplete).await
+    }
+}
+
+pub struct DisconnectAll;
+
+#[async_trait]
+impl Command for DisconnectAll {
+    fn name(&self) -> &'static str {
+        "disconnect-all"
+    }
+
+    fn create<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Disconnect all target channels from a source channel")
+            .create_option(|option| {
+                option
+                    .name(opt::SOURCE)
+                    .description("Source channel")
+                    .kind(ApplicationCommandOptionType::Channel)
+                    .required(true)
+                    .connectable_channel_types()
+            })
+    }
+
+    fn timing(&self) -> CommandTiming {
+        CommandTiming::Deferred(Duration::from_secs(60))
+    }
+
+    async fn run(
+        &self,
+        cx: &CommandContext<'_>,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResponse> {
+        let db = cx.db;
+        let options = &command.data.options;
+        let source_channel = get_channel_opt(opt::SOURCE, options)?;
+        let source = source_channel.id.0 as i64;
+        let user = command.user.id.0 as i64;
+
+        let ids: Vec<i64> = sqlx::query!(
+            "SELECT id FROM Connections WHERE source = ? AND user = ?",
+            source,
+            user
+        )
+        .fetch_all(db)
+        .await
+        .map_err(|e| Error::new(e).context("Failed to retrieve connections from the database"))?
+        .into_iter()
+        .map(|row| row.id)
+        .collect();
+
+        let count = trash::delete_connections(db, &command.user.id, "disconnect-all", &ids).await?;
+
+        let title = "Disconnected All".to_owned();
+        let msg = format!(
+            "Source: <#{}>\nRemoved {count} connection(s)\n\nUse /undo to restore them",
+            source,
+        );
+        Ok(CommandResponse {
+            title,
+            msg,
+            ..Default::default()
+        })
+    }
+}
+
+pub struct ListConnections;
+
+#[async_trait]
+impl Command for ListConnections {
+    fn name(&self) -> &'static str {
+        "list-connections"
+    }
+
+    fn create<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("List all the active connections between all servers")
+            .create_option(|option| {
+                option
+                    .name(opt::PUBLIC)
+                    .description("Show the list to everyone in the channel")
+                    .kind(ApplicationCommandOptionType::Boolean)
+                    .required(false)
+            })
+    }
+
+    fn timing(&self) -> CommandTiming {
+        CommandTiming::Deferred(Duration::from_secs(30))
+    }
+
+    async fn run(
+        &self,
+        cx: &CommandContext<'_>,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResponse> {
+        let db = cx.db;
+        struct Connection {
+            source: i64,
+            target: i64,
+            source_guild: String,
+            target_guild: String,
+        }
+
+        impl From<Connection> for String {
+            fn from(c: Connection) -> Self {
+                format!(
+                    "> <#{}> => <#{}> **({})**",
+                    c.source, c.target, c.target_guild
+                )
+            }
+        }
+
+        let user = command.user.id.0 as i64;
+        let connections: Vec<Connection> = sqlx::query!(
+            "
+            SELECT\n\
+            user as \"user: i64\",\n\
+            source as \"source: i64\",\n\
+            target as \"target: i64\",\n\
+            source_guild.name as source_guild,\n\
+            target_guild.name as target_guild\n\
+            FROM Connections\n\
+            JOIN Channels source_channel\n\
+            ON Connections.source = source_channel.id\n\
+            JOIN Guilds source_guild\n\
+            ON source_guild.id = source_channel.guild\n\
+            JOIN Channels target_channel\n\
+            ON Connections.target = target_channel.id\n\
+            JOIN Guilds target_guild\n\
+            ON target_guild.id = target_channel.guild\n\
+            WHERE user = ?
+            ",
+            user
+        )
+        .fetch_all(db)
+        .and_then(|records| async {
+            Ok(records
+                .into_iter()
+                .map(|record| Connection {
+                    source: record.source,
+                    target: record.target,
+                    source_guild: record.source_guild,
+                    target_guild: record.target_guild,
+                })
+                .collect::<Vec<Connection>>())
+        })
+        .await
+        .map_err(|e| {
+            anyhow!(e).context("Failed to retrieve connections for server from the database")
+        })?;
+
+        let grouped = {
+            let mut grouped: HashMap<String, Vec<Connection>> = HashMap::default();
+            for c in connections {
+                match grouped.get_mut(&c.source_guild) {
+                    Some(val) => val.push(c),
+                    None => {
+                        let _ = grouped.insert(c.source_guild.clone(), vec![c]);
+                    }
+                };
+            }
+            grouped
+        };
+
+        let msg = grouped
+            .into_iter()
+            .map(|(k, cs)| {
+                let s = cs
+                    .into_iter()
+                    .map(String::from)
+                    .collect::<Vec<String>>()
+                    .join("\n");
+                format!("__**{}**__\n{}", k, s)
+            })
+            .collect::<Vec<String>>()
+            .join("\n\n");
+
+        Ok(CommandResponse {
+            title: "Connection List".to_owned(),
+            msg,
+            public: get_bool_opt(opt::PUBLIC, &command.data.options).unwrap_or(false),
+            ..Default::default()
+        })
+    }
+}
+
+pub struct WipeConnections;
+
+#[async_trait]
+impl Command for WipeConnections {
+    fn name(&self) -> &'static str {
+        "wipe-connections"
+    }
+
+    fn create<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("[WARNING] Will remove ALL connections to/from the selected server")
+            .create_option(|option| {
+                option
+                    .name(opt::SERVER)
+                    .description("Server name")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+                    .set_autocomplete(true)
+            })
+    }
+
+    fn timing(&self) -> CommandTiming {
+        CommandTiming::Deferred(Duration::from_secs(60))
+    }
+
+    async fn run(
+        &self,
+        cx: &CommandContext<'_>,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResponse> {
+        let db = cx.db;
+        let options = &command.data.options;
+        let server_name = get_string_opt(opt::SERVER, options)?;
+        let user = command.user.id.0 as i64;
+
+        let rows = sqlx::query!(
+            "
+            SELECT\n\
+            source_guild.name as guild_name,\n\
+            source_channel.name as channel_name,\n\
+            COUNT(1) as \"count!: i32\"\n\
+            FROM Connections\n\
+            JOIN Channels source_channel\n\
+            ON Connections.source = source_channel.id\n\
+            JOIN Guilds source_guild\n\
+            ON source_guild.id = source_channel.guild\n\
+            JOIN Channels target_channel\n\
+            ON Connections.target = target_channel.id\n\
+            JOIN Guilds target_guild\n\
+            ON target_guild.id = target_channel.guild\n\
+            WHERE (source_guild.name = ? OR target_guild.name = ?) AND user = ?\n\
+            GROUP BY Connections.source\n\
+            ORDER BY guild_name, channel_name
+            ",
+            server_name,
+            server_name,
+            user
+        )
+        .fetch_all(db)
+        .await
+        .map_err(|e| {
+            Error::new(e).context("Failed to count connections for server in the database")
+        })?;
+
+        let (total, summary) = count_summary(
+            rows.into_iter()
+                .map(|row| (row.guild_name, row.channel_name, row.count))
+                .collect(),
+            "connection(s) from this source channel",
+        );
+        if total == 0 {
+            bail!("No connections to/from: __**{server_name}**__");
+        }
+
+        Ok(CommandResponse {
+            title: "Wipe Connections?".to_owned(),
+            msg: format!(
+                "This will remove **{total}** connection(s) to/from __**{server_name}**__:\n{summary}"
+            ),
+            confirm: Some(PendingAction::WipeConnections {
+                server_name: server_name.clone(),
+            }),
+            ..Default::default()
+        })
+    }
+
+    async fn autocomplete(
+        &self,
+        db: &SqlitePool,
+        autocomplete: &AutocompleteInteraction,
+    ) -> Result<AutocompleteResponse> {
+        server_autocomplete(db, autocomplete).await
+    }
+}
+
+pub struct WipeMentions;
+
+#[async_trait]
+impl Command for WipeMentions {
+    fn name(&self) -> &'static str {
+        "wipe-mentions"
+    }
+
+    fn create<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description(
+                "[WARNING] Will remove ALL mentions to/from channels in the selected server",
+            )
+            .create_option(|option| {
+                option
+                    .name(opt::SERVER)
+                    .description("Server name")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+                    .set_autocomplete(true)
+            })
+    }
+
+    fn timing(&self) -> CommandTiming {
+        CommandTiming::Deferred(Duration::from_secs(60))
+    }
+
+    async fn run(
+        &self,
+        cx: &CommandContext<'_>,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResponse> {
+        let db = cx.db;
+        let options = &command.data.options;
+        let server_name = get_string_opt(opt::SERVER, options)?;
+        let user = command.user.id.0 as i64;
+
+        let rows = sqlx::query!(
+            "
+            SELECT\n\
+            target_guild.name as guild_name,\n\
+            target_channel.name as channel_name,\n\
+            COUNT(1) as \"count!: i32\"\n\
+            FROM Mentions\n\
+            JOIN Channels target_channel\n\
+            ON Mentions.target = target_channel.id\n\
+            JOIN Guilds target_guild\n\
+            ON target_guild.id = target_channel.guild\n\
+            WHERE (\n\
+                target_guild.name = ? OR Mentions.source IN (\n\
+                    SELECT Channels.id FROM Channels\n\
+                    JOIN Guilds ON Guilds.id = Channels.guild\n\
+                    WHERE Guilds.name = ?\n\
+                )\n\
+            ) AND user = ?\n\
+            GROUP BY Mentions.target\n\
+            ORDER BY guild_name, channel_name
+            ",
+            server_name,
+            server_name,
+            user,
+        )
+        .fetch_all(db)
+        .await
+        .map_err(|e| {
+            Error::new(e).context("Failed to count mentions for server in the database")
+        })?;
+
+        let (total, summary) = count_summary(
+            rows.into_iter()
+                .map(|row| (row.guild_name, row.channel_name, row.count))
+                .collect(),
+            "mention(s) in this target channel",
+        );
+        if total == 0 {
+            bail!("No mentions to/from: __**{server_name}**__");
+        }
+
+        Ok(CommandResponse {
+            title: "Wipe Mentions?".to_owned(),
+            msg: format!(
+                "This will remove **{total}** mention(s) to/from __**{server_name}**__:\n{summary}"
+            ),
+            confirm: Some(PendingAction::WipeMentions {
+                server_name: server_name.clone(),
+            }),
+            ..Default::default()
+        })
+    }
+
+    async fn autocomplete(
+        &self,
+        db: &SqlitePool,
+        autocomplete: &AutocompleteInteraction,
+    ) -> Result<AutocompleteResponse> {
+        server_autocomplete(db, autocomplete).await
+    }
+}
+
+pub struct MentionAdd;
+
+#[async_trait]
+impl Command for MentionAdd {
+    fn name(&self) -> &'static str {
+        "mention-add"
+    }
+
+    fn create<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Add mentions to the target channel")
+            .create_option(|option| {
+                option
+                    .name(opt::TARGET_SERVER)
+                    .description("Target server")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+                    .set_autocomplete(true)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::TARGET_CHANNEL)
+                    .description("Target channel")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+                    .set_autocomplete(true)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::MENTIONS)
+                    .description("One or more mentions separated by whitespace")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::SOURCE)
+                    .description("If set then only messages from this channel are mentioned")
+                    .kind(ApplicationCommandOptionType::Channel)
+                    .required(false)
+                    .connectable_channel_types()
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::KEYWORD)
+                    .description("If set then only messages containing this keyword are mentioned")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::REGEX)
+                    .description("If set then only messages matching this regex are mentioned")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::TICKER)
+                    .description("If set then only messages mentioning this ticker are mentioned")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::AUTHOR)
+                    .description("If set then only messages posted by this user are mentioned")
+                    .kind(ApplicationCommandOptionType::User)
+                    .required(false)
+            })
+    }
+
+    async fn run(
+        &self,
+        cx: &CommandContext<'_>,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResponse> {
+        let db = cx.db;
+        let options = &command.data.options;
+        let source = get_channel_opt(opt::SOURCE, options);
+        let target_server = get_string_opt(opt::TARGET_SERVER, options)?;
+        let target_channel = get_string_opt(opt::TARGET_CHANNEL, options)?;
+        let mentions: Vec<&str> = get_string_opt(opt::MENTIONS, options)?.split(' ').collect();
+        let condition = mention_condition_opt(options)?;
+        let kind = condition.as_ref().map(|c| c.kind());
+        let value = condition.as_ref().map(|c| c.value());
+
+        let (_target_server_id, target_channel_id) =
+            name_to_ids(db, target_server, target_channel).await?;
+
+        for m in &mentions {
+            let user = command.user.id.0 as i64;
+            let target = target_channel_id.0 as i64;
+
+            if let Ok(ch) = source {
+                let source = ch.id.0 as i64;
+                let exists =
+                    mention_exists(db, &ch.id, &target_channel_id, m, condition.as_ref()).await?;
+                if !exists {
+                    let result = sqlx::query!(
+                        "
+                        INSERT INTO Mentions (source, target, mention, user, condition_kind, condition)\n\
+                        VALUES (?, ?, ?, ?, ?, ?)
+                        ",
+                        source,
+                        target,
+                        m,
+                        user,
+                        kind,
+                        value
+                    )
+                    .execute(db)
+                    .await
+                    .map_err(|e| Error::new(e).context(format!("Failed to insert mention {m}")));
+                    match result {
+                        Ok(_) => (),
+                        Err(e) => println!("{e}"),
+                    };
+                }
+            } else {
+                // No source channel provided.
+                let exists =
+                    mention_exists_no_source(db, &target_channel_id, m, condition.as_ref()).await?;
+                if !exists {
+                    let result = sqlx::query!(
+                        "
+                        INSERT INTO Mentions (source, target, mention, user, condition_kind, condition)\n\
+                        VALUES (NULL, ?, ?, ?, ?, ?)
+                        ",
+                        target,
+                        m,
+                        user,
+                        kind,
+                        value
+                    )
+                    .execute(db)
+                    .await
+                    .map_err(|e| Error::new(e).context(format!("Failed to insert mention {m}")));
+                    match result {
+                        Ok(_) => (),
+                        Err(e) => println!("{e}"),
+                    };
+                }
+            }
+        }
+
+        let from_source = if let Ok(ch) = source {
+            format!("\nSource channel: <#{}>", ch.id)
+        } else {
+            "".to_owned()
+        };
+        let when = match &condition {
+            Some(c) => format!("\nOnly when the message {c}"),
+            None => "".to_owned(),
+        };
+
+        Ok(CommandResponse {
+            title: "Added Mentions".to_owned(),
+            msg: format!(
+                "Mentions:\n{}\n\nTarget server: __**{}**__\nTarget channel <#{}>{}{}",
+                mentions.join("\n"),
+                target_server,
+                target_channel_id,
+                from_source,
+                when
+            ),
+            ..Default::default()
+        })
+    }
+
+    async fn autocomplete(
+        &self,
+        db: &SqlitePool,
+        autocomplete: &AutocompleteInteraction,
+    ) -> Result<AutocompleteResponse> {
+        target_autocomplete(db, autocomplete).await
+    }
+}
+
+pub struct ListMentions;
+
+#[async_trait]
+impl Command for ListMentions {
+    fn name(&self) -> &'static str {
+        "list-mentions"
+    }
+
+    fn create<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("List all mentions for channels in the target server")
+            .create_option(|option| {
+                option
+                    .name(opt::TARGET_SERVER)
+                    .description("Target server")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+                //.set_autocomplete(true)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::PUBLIC)
+                    .description("Show the list to everyone in the channel")
+                    .kind(ApplicationCommandOptionType::Boolean)
+                    .required(false)
+            })
+    }
+
+    fn timing(&self) -> CommandTiming {
+        CommandTiming::Deferred(Duration::from_secs(30))
+    }
+
+    async fn run(
+        &self,
+        cx: &CommandContext<'_>,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResponse> {
+        let db = cx.db;
+        struct Mentions {
+            source: Option<i64>,
+            target: i64,
+            mentions: Vec<String>,
+        }
+
+        impl From<Mentions> for String {
+            fn from(c: Mentions) -> Self {
+                match c.source {
+                    Some(source) => {
+                        format!(
+                            "(**Boll's Server**) <#{}> => <#{}>\n> {}",
+                            source,
+                            c.target,
+                            c.mentions.join("\n> ")
+                        )
+                    }
+                    None => {
+                        format!(
+                            "(**ALL**) => <#{}>\n> {}",
+                            c.target,
+                            c.mentions.join("\n> ")
+                        )
+                    }
+                }
+            }
+        }
+
+        let test_source = ChannelId(945744069596971021);
+        let test_target = ChannelId(948272822441091144);
+        let test_user = command.user.id;
+        let mentions = get_mention_rules(db, &test_target, &test_source, &test_user)
+            .await?
+            .into_iter()
+            .map(|(mention, condition)| match condition {
+                Some(c) => format!("{mention} (when the message {c})"),
+                None => mention,
+            })
+            .collect();
+
+        let m = Mentions {
+            source: None, //Some(test_source.0 as i64),
+            target: test_target.0 as i64,
+            mentions,
+        };
+
+        // async fn get_mentions(
+        //     db: &SqlitePool,
+        //     target: &ChannelId,
+        //     source: &ChannelId,
+        //     user: &UserId,
+        // ) -> Result<Vec<String>> {
+
+        Ok(CommandResponse {
+            title: "Mention List for \"Boll's Server\"".to_owned(),
+            msg: m.into(),
+            public: get_bool_opt(opt::PUBLIC, &command.data.options).unwrap_or(false),
+            ..Default::default()
+        })
+    }
+}
+
+pub struct Undo;
+
+#[async_trait]
+impl Command for Undo {
+    fn name(&self) -> &'static str {
+        "undo"
+    }
+
+    fn create<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command.description("Restore what your last disconnect/wipe command removed")
+    }
+
+    fn timing(&self) -> CommandTiming {
+        CommandTiming::Deferred(Duration::from_secs(60))
+    }
+
+    async fn run(
+        &self,
+        cx: &CommandContext<'_>,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResponse> {
+        let db = cx.db;
+        let restored = trash::undo(db, &command.user.id).await?;
+
+        Ok(CommandResponse {
+            title: "Undone".to_owned(),
+            msg: format!(
+                "Restored what **/{}** removed <t:{}:R>\nConnections: {}\nMentions: {}",
+                restored.command, restored.created, restored.connections, restored.mentions
+            ),
+            ..Default::default()
+        })
+    }
+}
+
+pub struct Template;
+
+#[async_trait]
+impl Command for Template {
+    fn name(&self) -> &'static str {
+        "template"
+    }
+
+    fn create<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Change how relayed messages are formatted for a connection")
+            .create_option(|option| {
+                option
+                    .name(opt::SOURCE)
+                    .description("Source channel")
+                    .kind(ApplicationCommandOptionType::Channel)
+                    .required(true)
+                    .connectable_channel_types()
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::TARGET_CHANNEL)
+                    .description("Target channel")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+                    .set_autocomplete(true)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::STYLE)
+                    .description("Post as an embed or as plain text")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(false)
+                    .add_string_choice("Embed", "embed")
+                    .add_string_choice("Plain text", "text")
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::COLOR)
+                    .description("Embed colour (#RRGGBB)")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::TITLE)
+                    .description("Title, supports placeholders (\"-\" removes it)")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::BODY)
+                    .description("Body ({content} {author} {source_channel} {source_guild} {jump_url} {mentions})")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::FOOTER)
+                    .description("Footer, supports placeholders (\"-\" removes it)")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::TIMESTAMP)
+                    .description("Show the time of the original message")
+                    .kind(ApplicationCommandOptionType::Boolean)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::MENTIONS)
+                    .description("Where the mentions are placed")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(false)
+                    .add_string_choice("Above the message", "above")
+                    .add_string_choice("Below the message", "below")
+                    .add_string_choice("At the {mentions} placeholder (embeds also ping above)", "inline")
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::PROVENANCE)
+                    .description("Show the source server/channel and a link to the original message")
+                    .kind(ApplicationCommandOptionType::Boolean)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::RESET)
+                    .description("Start from the default template")
+                    .kind(ApplicationCommandOptionType::Boolean)
+                    .required(false)
+            })
+    }
+
+    async fn run(
+        &self,
+        cx: &CommandContext<'_>,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResponse> {
+        let db = cx.db;
+        let options = &command.data.options;
+        let source = get_channel_opt(opt::SOURCE, options)?;
+        let combined = get_string_opt(opt::TARGET_CHANNEL, options)?;
+        let (target_server_name, target_channel_name) = parse_target_channel(combined)?;
+        let (_target_server_id, target_channel_id) =
+            name_to_ids(db, &target_server_name, &target_channel_name).await?;
+        let connection =
+            get_connection_id(db, &source.id, &target_channel_id, &command.user.id).await?;
+
+        let mut template = match get_bool_opt(opt::RESET, options) {
+            Ok(true) => template::Template::default(),
+            _ => template::get_template(db, connection).await?,
+        };
+        if let Ok(style) = get_string_opt(opt::STYLE, options) {
+            template.embed = style == "embed";
+        }
+        if let Ok(color) = get_string_opt(opt::COLOR, options) {
+            template.color = template::parse_color(color)?;
+        }
+        if let Ok(title) = get_string_opt(opt::TITLE, options) {
+            template.title = optional_template_text(title);
+        }
+        if let Ok(body) = get_string_opt(opt::BODY, options) {
+            template.body = optional_template_text(body).unwrap_or_default();
+        }
+        if let Ok(footer) = get_string_opt(opt::FOOTER, options) {
+            template.footer = optional_template_text(footer);
+        }
+        if let Ok(timestamp) = get_bool_opt(opt::TIMESTAMP, options) {
+            template.timestamp = timestamp;
+        }
+        if let Ok(mentions) = get_string_opt(opt::MENTIONS, options) {
+            template.mentions = MentionPlacement::parse(mentions)?;
+        }
+        if let Ok(provenance) = get_bool_opt(opt::PROVENANCE, options) {
+            template.provenance = provenance;
+        }
+        template::set_template(db, connection, &template).await?;
+
+        let (source_guild, source_channel) = template::channel_names(db, &source.id).await?;
+        let content = "This is how relayed messages will look.";
+        let subject = Subject::new(content, command.user.id);
+        let mentions = get_mentions(
+            db,
+            &target_channel_id,
+            &source.id,
+            &command.user.id,
+            &subject,
+        )
+        .await?;
+        let guild = command.guild_id.map(|id| id.0).unwrap_or_default();
+        let preview = template.render(&Placeholders {
+            author: command.user.name.clone(),
+            source_channel,
+            source_guild,
+            content: content.to_owned(),
+            jump_url: format!("https://discord.com/channels/{}/{}", guild, source.id),
+            mentions,
+            tickers: Vec::new(),
+            timestamp: Utc::now(),
+        });
+
+        Ok(CommandResponse {
+            title: "Template Updated".to_owned(),
+            msg: format!(
+                "Source: <#{}>\nTarget server: __**{}**__\nTarget channel: <#{}>\n\nPreview:",
+                source.id, target_server_name, target_channel_id
+            ),
+            preview: Some(preview),
+            ..Default::default()
+        })
+    }
+
+    async fn autocomplete(
+        &self,
+        db: &SqlitePool,
+        autocomplete: &AutocompleteInteraction,
+    ) -> Result<AutocompleteResponse> {
+        connection_autocomplete(db, autocomplete).await
+    }
+}
+
+pub struct Search;
+
+#[async_trait]
+impl Command for Search {
+    fn name(&self) -> &'static str {
+        "search"
+    }
+
+    fn create<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Search the relayed messages")
+            .create_option(|option| {
+                option
+                    .name(opt::KEYWORDS)
+                    .description("Words that all have to appear in the message")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::AUTHOR)
+                    .description("Author of the message")
+                    .kind(ApplicationCommandOptionType::User)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::SERVER)
+                    .description("Source server")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(false)
+                    .set_autocomplete(true)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::FROM)
+                    .description("Posted on or after this date (YYYY-MM-DD)")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::TO)
+                    .description("Posted on or before this date (YYYY-MM-DD)")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::PUBLIC)
+                    .description("Show the results to everyone in the channel")
+                    .kind(ApplicationCommandOptionType::Boolean)
+                    .required(false)
+            })
+    }
+
+    fn timing(&self) -> CommandTiming {
+        CommandTiming::Deferred(Duration::from_secs(30))
+    }
+
+    async fn run(
+        &self,
+        cx: &CommandContext<'_>,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResponse> {
+        let db = cx.db;
+        let options = &command.data.options;
+        let guild = match get_string_opt(opt::SERVER, options) {
+            Ok(server_name) => Some(get_guild_id(db, server_name).await?),
+            Err(_) => None,
+        };
+        let query = SearchQuery {
+            keywords: get_string_opt(opt::KEYWORDS, options)
+                .ok()
+                .and_then(|k| search::parse_keywords(k)),
+            author: get_user_opt(opt::AUTHOR, options).ok(),
+            guild,
+            from: match get_string_opt(opt::FROM, options) {
+                Ok(date) => Some(search::parse_date(date, false)?.timestamp()),
+                Err(_) => None,
+            },
+            to: match get_string_opt(opt::TO, options) {
+                Ok(date) => Some(search::parse_date(date, true)?.timestamp()),
+                Err(_) => None,
+            },
+        };
+        if query.is_empty() {
+            bail!("Give at least one of keywords, author, server, from or to");
+        }
+
+        let scope = SearchScope {
+            user: command.user.id,
+            guild: command.guild_id.map(|g| g.0 as i64),
+        };
+        let results = search::search(db, &scope, &query).await?;
+        if results.is_empty() {
+            bail!("No relayed messages match the search");
+        }
+
+        let title = match results.len() as i64 {
+            search::MAX_RESULTS => format!("Search Results (newest {})", search::MAX_RESULTS),
+            count => format!("Search Results ({count})"),
+        };
+        let msg = results
+            .iter()
+            .map(String::from)
+            .collect::<Vec<String>>()
+            .join("\n\n");
+
+        Ok(CommandResponse {
+            title,
+            msg,
+            public: get_bool_opt(opt::PUBLIC, options).unwrap_or(false),
+            ..Default::default()
+        })
+    }
+
+    async fn autocomplete(
+        &self,
+        db: &SqlitePool,
+        autocomplete: &AutocompleteInteraction,
+    ) -> Result<AutocompleteResponse> {
+        server_autocomplete(db, autocomplete).await
+    }
+}
+
+pub struct WatchlistAdd;
+
+#[async_trait]
+impl Command for WatchlistAdd {
+    fn name(&self) -> &'static str {
+        "watchlist-add"
+    }
+
+    fn create<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Only relay messages of a connection that mention these symbols")
+            .create_option(|option| {
+                option
+                    .name(opt::SOURCE)
+                    .description("Source channel")
+                    .kind(ApplicationCommandOptionType::Channel)
+                    .required(true)
+                    .connectable_channel_types()
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::TARGET_CHANNEL)
+                    .description("Target channel")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+                    .set_autocomplete(true)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::SYMBOLS)
+                    .description("Symbols, e.g. \"AAPL BTC\" (separate with commas when they contain spaces)")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+            })
+    }
+
+    async fn run(
+        &self,
+        cx: &CommandContext<'_>,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResponse> {
+        let db = cx.db;
+        let symbols = ticker::parse_symbols(get_string_opt(opt::SYMBOLS, &command.data.options)?)?;
+        let (connection, source, target) = connection_from_options(db, command).await?;
+        let added = ticker::add_to_watchlist(db, connection, &symbols).await?;
+        if added.is_empty() {
+            bail!("Already on the watchlist: {}", watchlist_tags(&symbols));
+        }
+        let watchlist = ticker::get_watchlist(db, connection).await?;
+
+        Ok(CommandResponse {
+            title: "Watchlist Updated".to_owned(),
+            msg: format!(
+                "<#{}> => <#{}>\nAdded: {}\n\nOnly messages mentioning these are relayed:\n> {}",
+                source,
+                target,
+                watchlist_tags(&added),
+                watchlist_tags(&watchlist)
+            ),
+            ..Default::default()
+        })
+    }
+
+    async fn autocomplete(
+        &self,
+        db: &SqlitePool,
+        autocomplete: &AutocompleteInteraction,
+    ) -> Result<AutocompleteResponse> {
+        connection_autocomplete(db, autocomplete).await
+    }
+}
+
+pub struct WatchlistRemove;
+
+#[async_trait]
+impl Command for WatchlistRemove {
+    fn name(&self) -> &'static str {
+        "watchlist-remove"
+    }
+
+    fn create<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Remove symbols from the watchlist of a connection")
+            .create_option(|option| {
+                option
+                    .name(opt::SOURCE)
+                    .description("Source channel")
+                    .kind(ApplicationCommandOptionType::Channel)
+                    .required(true)
+                    .connectable_channel_types()
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::TARGET_CHANNEL)
+                    .description("Target channel")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+                    .set_autocomplete(true)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::SYMBOLS)
+                    .description(
+                        "Symbols to remove (separate with commas when they contain spaces)",
+                    )
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+            })
+    }
+
+    async fn run(
+        &self,
+        cx: &CommandContext<'_>,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResponse> {
+        let db = cx.db;
+        let symbols = ticker::parse_symbols(get_string_opt(opt::SYMBOLS, &command.data.options)?)?;
+        let (connection, source, target) = connection_from_options(db, command).await?;
+        let removed = ticker::remove_from_watchlist(db, connection, &symbols).await?;
+        if removed.is_empty() {
+            bail!("Not on the watchlist: {}", watchlist_tags(&symbols));
+        }
+        let watchlist = ticker::get_watchlist(db, connection).await?;
+
+        Ok(CommandResponse {
+            title: "Watchlist Updated".to_owned(),
+            msg: format!(
+                "<#{}> => <#{}>\nRemoved: {}\n\nWatchlist:\n> {}",
+                source,
+                target,
+                watchlist_tags(&removed),
+                watchlist_tags(&watchlist)
+            ),
+            ..Default::default()
+        })
+    }
+
+    async fn autocomplete(
+        &self,
+        db: &SqlitePool,
+        autocomplete: &AutocompleteInteraction,
+    ) -> Result<AutocompleteResponse> {
+        connection_autocomplete(db, autocomplete).await
+    }
+}
+
+pub struct WatchlistList;
+
+#[async_trait]
+impl Command for WatchlistList {
+    fn name(&self) -> &'static str {
+        "watchlist-list"
+    }
+
+    fn create<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("List the watchlists of your connections")
+            .create_option(|option| {
+                option
+                    .name(opt::PUBLIC)
+                    .description("Show the list to everyone in the channel")
+                    .kind(ApplicationCommandOptionType::Boolean)
+                    .required(false)
+            })
+    }
+
+    async fn run(
+        &self,
+        cx: &CommandContext<'_>,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResponse> {
+        let db = cx.db;
+        let user = command.user.id.0 as i64;
+        let rows = sqlx::query!(
+            "
+            SELECT\n\
+            Connections.source as \"source: i64\",\n\
+            Connections.target as \"target: i64\",\n\
+            Watchlists.symbol\n\
+            FROM Watchlists\n\
+            JOIN Connections\n\
+            ON Watchlists.connection = Connections.id\n\
+            WHERE Connections.user = ?\n\
+            ORDER BY Connections.id, Watchlists.symbol
+            ",
+            user
+        )
+        .fetch_all(db)
+        .await
+        .map_err(|e| Error::new(e).context("Failed to retrieve watchlists from the database"))?;
+
+        let mut watchlists: Vec<((i64, i64), Vec<String>)> = Vec::new();
+        for row in rows {
+            let key = (row.source, row.target);
+            match watchlists.last_mut() {
+                Some((k, symbols)) if *k == key => symbols.push(row.symbol),
+                _ => watchlists.push((key, vec![row.symbol])),
+            }
+        }
+        if watchlists.is_empty() {
+            bail!("None of your connections have a watchlist");
+        }
+
+        let msg = watchlists
+            .into_iter()
+            .map(|((source, target), symbols)| {
+                format!(
+                    "<#{}> => <#{}>\n> {}",
+                    source,
+                    target,
+                    watchlist_tags(&symbols)
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n\n");
+
+        Ok(CommandResponse {
+            title: "Watchlists".to_owned(),
+            msg,
+            public: get_bool_opt(opt::PUBLIC, &command.data.options).unwrap_or(false),
+            ..Default::default()
+        })
+    }
+}
+
+pub struct AlertAdd;
+
+#[async_trait]
+impl Command for AlertAdd {
+    fn name(&self) -> &'static str {
+        "alert-add"
+    }
+
+    fn create<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Get a direct message when a relayed message matches a pattern")
+            .create_option(|option| {
+                option
+                    .name(opt::PATTERN)
+                    .description("Keyword, regex or ticker to look for")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::KIND)
+                    .description("How the pattern is matched (keyword by default)")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(false)
+                    .add_string_choice("Keyword", "keyword")
+                    .add_string_choice("Regex", "regex")
+                    .add_string_choice("Ticker", "ticker")
+            })
+    }
+
+    async fn run(
+        &self,
+        cx: &CommandContext<'_>,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResponse> {
+        let db = cx.db;
+        let options = &command.data.options;
+        let pattern = get_string_opt(opt::PATTERN, options)?;
+        let kind = get_string_opt(opt::KIND, options)
+            .map(|k| k.as_str())
+            .unwrap_or("keyword");
+        let condition = MentionCondition::parse(kind, pattern)?;
+        let guild = alert_guild(command.guild_id)?;
+        if !alert::add_alert(db, &command.user.id, &guild, &condition).await? {
+            bail!(
+                "You already have the alert `{}`",
+                alert::alert_name(&condition)
+            );
+        }
+
+        Ok(CommandResponse {
+            title: "Alert Added".to_owned(),
+            msg: format!(
+                "`{}`\n\nYou get a direct message when a message in or relayed into this server {}",
+                alert::alert_name(&condition),
+                condition
+            ),
+            ..Default::default()
+        })
+    }
+}
+
+pub struct AlertRemove;
+
+#[async_trait]
+impl Command for AlertRemove {
+    fn name(&self) -> &'static str {
+        "alert-remove"
+    }
+
+    fn create<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Remove one of your alerts")
+            .create_option(|option| {
+                option
+                    .name(opt::ALERT)
+                    .description("Alert to remove")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+                    .set_autocomplete(true)
+            })
+    }
+
+    async fn run(
+        &self,
+        cx: &CommandContext<'_>,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResponse> {
+        let db = cx.db;
+        let condition = alert::parse_alert(get_string_opt(opt::ALERT, &command.data.options)?)?;
+        let guild = alert_guild(command.guild_id)?;
+        alert::remove_alert(db, &command.user.id, &guild, &condition).await?;
+
+        Ok(CommandResponse {
+            title: "Alert Removed".to_owned(),
+            msg: format!("`{}`", alert::alert_name(&condition)),
+            ..Default::default()
+        })
+    }
+
+    async fn autocomplete(
+        &self,
+        db: &SqlitePool,
+        autocomplete: &AutocompleteInteraction,
+    ) -> Result<AutocompleteResponse> {
+        let param_alert = find_param(opt::ALERT, autocomplete)?;
+
+        let input = match &param_alert.value {
+            Some(serde_json::Value::String(input)) => input.clone(),
+            Some(val) => bail!("Unexpected parameter type (expected string):\n{:#?}", val),
+            None => bail!("No parameter value found"),
+        };
+
+        let guild = alert_guild(autocomplete.guild_id)?;
+        let mut matching: Vec<(isize, String)> =
+            alert::get_alerts(db, &autocomplete.user.id, &guild)
+                .await?
+                .iter()
+                .map(|c| {
+                    let name = alert::alert_name(c);
+                    match best_match(input.as_str(), name.as_str()) {
+                        Some(m) => (100 - m.score(), name),
+                        None => (100, name),
+                    }
+                })
+                .collect();
+
+        matching.sort();
+        matching.drain(cmp::min(25, matching.len())..);
+
+        Ok(AutocompleteResponse {
+            options: matching.into_iter().map(|(_score, name)| name).collect(),
+        })
+    }
+}
+
+pub struct AlertList;
+
+#[async_trait]
+impl Command for AlertList {
+    fn name(&self) -> &'static str {
+        "alert-list"
+    }
+
+    fn create<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command.description("List your alerts")
+    }
+
+    async fn run(
+        &self,
+        cx: &CommandContext<'_>,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResponse> {
+        let db = cx.db;
+        let guild = alert_guild(command.guild_id)?;
+        let alerts = alert::get_alerts(db, &command.user.id, &guild).await?;
+        if alerts.is_empty() {
+            bail!("You don't have any alerts in this server, add one with /alert-add");
+        }
+
+        let msg = alerts
+            .iter()
+            .map(|c| format!("> `{}` ({})", alert::alert_name(c), c))
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        Ok(CommandResponse {
+            title: format!("Your Alerts ({}/{})", alerts.len(), alert::MAX_ALERTS),
+            msg,
+            ..Default::default()
+        })
+    }
+}
+
+pub struct RateLimit;
+
+#[async_trait]
+impl Command for RateLimit {
+    fn name(&self) -> &'static str {
+        "rate-limit"
+    }
+
+    fn create<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Limit how many messages per minute a connection relays")
+            .create_option(|option| {
+                option
+                    .name(opt::SOURCE)
+                    .description("Source channel")
+                    .kind(ApplicationCommandOptionType::Channel)
+                    .required(true)
+                    .connectable_channel_types()
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::TARGET_CHANNEL)
+                    .description("Target channel")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+                    .set_autocomplete(true)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::PER_MINUTE)
+                    .description("Messages per minute (0 removes the limit)")
+                    .kind(ApplicationCommandOptionType::Integer)
+                    .required(true)
+                    .min_int_value(0)
+                    .max_int_value(600)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::OVERFLOW)
+                    .description("What happens to messages over the limit (queued by default)")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(false)
+                    .add_string_choice("Drop", "drop")
+                    .add_string_choice("Queue", "queue")
+                    .add_string_choice("Collapse into \"N more messages\"", "collapse")
+            })
+    }
+
+    async fn run(
+        &self,
+        cx: &CommandContext<'_>,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResponse> {
+        let db = cx.db;
+        let options = &command.data.options;
+        let per_minute = per_minute_opt(options)?;
+        let overflow = match get_string_opt(opt::OVERFLOW, options) {
+            Ok(s) => Some(Overflow::parse(s)?),
+            Err(_) => None,
+        };
+        let (connection, source, target) = connection_from_options(db, command).await?;
+        let overflow =
+            ratelimit::set_connection_limit(db, connection, per_minute, overflow).await?;
+
+        let msg = match per_minute {
+            Some(n) => format!(
+                "<#{}> => <#{}>\nAt most {} message(s) per minute are relayed, the rest is {}.",
+                source,
+                target,
+                n,
+                match overflow {
+                    Overflow::Drop => "dropped",
+                    Overflow::Queue => "queued",
+                    Overflow::Collapse => "collapsed into a \"N more messages\" summary",
+                }
+            ),
+            None => format!(
+                "<#{}> => <#{}>\nThe connection has no limit of its own anymore.",
+                source, target
+            ),
+        };
+        Ok(CommandResponse {
+            title: "Rate Limit Updated".to_owned(),
+            msg,
+            ..Default::default()
+        })
+    }
+
+    async fn autocomplete(
+        &self,
+        db: &SqlitePool,
+        autocomplete: &AutocompleteInteraction,
+    ) -> Result<AutocompleteResponse> {
+        connection_autocomplete(db, autocomplete).await
+    }
+}
+
+pub struct RateLimitTarget;
+
+#[async_trait]
+impl Command for RateLimitTarget {
+    fn name(&self) -> &'static str {
+        "rate-limit-target"
+    }
+
+    fn create<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Limit how many messages per minute are relayed into a target channel")
+            .create_option(|option| {
+                option
+                    .name(opt::TARGET_SERVER)
+                    .description("Target server")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+                    .set_autocomplete(true)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::TARGET_CHANNEL)
+                    .description("Target channel")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+                    .set_autocomplete(true)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::PER_MINUTE)
+                    .description("Messages per minute shared by all connections (0 goes back to the default)")
+                    .kind(ApplicationCommandOptionType::Integer)
+                    .required(true)
+                    .min_int_value(0)
+                    .max_int_value(600)
+            })
+    }
+
+    async fn run(
+        &self,
+        cx: &CommandContext<'_>,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResponse> {
+        let db = cx.db;
+        let options = &command.data.options;
+        let per_minute = per_minute_opt(options)?;
+        let target_server = get_string_opt(opt::TARGET_SERVER, options)?;
+        let target_channel = get_string_opt(opt::TARGET_CHANNEL, options)?;
+        let (_target_server_id, target_channel_id) =
+            name_to_ids(db, target_server, target_channel).await?;
+        ratelimit::set_webhook_limit(db, &target_channel_id, per_minute).await?;
+
+        Ok(CommandResponse {
+            title: "Rate Limit Updated".to_owned(),
+            msg: format!(
+                "At most {} message(s) per minute are relayed into <#{}>, shared by all connections.",
+                per_minute.unwrap_or(ratelimit::DEFAULT_WEBHOOK_LIMIT),
+                target_channel_id
+            ),
+            ..Default::default()
+        })
+    }
+
+    async fn autocomplete(
+        &self,
+        db: &SqlitePool,
+        autocomplete: &AutocompleteInteraction,
+    ) -> Result<AutocompleteResponse> {
+        target_autocomplete(db, autocomplete).await
+    }
+}
+
+pub struct Dedup;
+
+#[async_trait]
+impl Command for Dedup {
+    fn name(&self) -> &'static str {
+        "dedup"
+    }
+
+    fn create<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description(
+                "Only relay the same message into a target channel once within a time window",
+            )
+            .create_option(|option| {
+                option
+                    .name(opt::TARGET_SERVER)
+                    .description("Target server")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+                    .set_autocomplete(true)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::TARGET_CHANNEL)
+                    .description("Target channel")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+                    .set_autocomplete(true)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::WINDOW)
+                    .description(
+                        "Minutes a message counts as a duplicate (0 turns de-duplication off)",
+                    )
+                    .kind(ApplicationCommandOptionType::Integer)
+                    .required(true)
+                    .min_int_value(0)
+                    .max_int_value(1440)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::ANNOTATE)
+                    .description("Add \"also posted in …\" to the relayed copy")
+                    .kind(ApplicationCommandOptionType::Boolean)
+                    .required(false)
+            })
+    }
+
+    async fn run(
+        &self,
+        cx: &CommandContext<'_>,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResponse> {
+        let db = cx.db;
+        let options = &command.data.options;
+        let window = get_int_opt(opt::WINDOW, options)?;
+        let annotate = get_bool_opt(opt::ANNOTATE, options).unwrap_or(false);
+        let target_server = get_string_opt(opt::TARGET_SERVER, options)?;
+        let target_channel = get_string_opt(opt::TARGET_CHANNEL, options)?;
+        let (_target_server_id, target_channel_id) =
+            name_to_ids(db, target_server, target_channel).await?;
+
+        let settings = match window {
+            0 => None,
+            n if n < 0 => bail!("The window can't be negative"),
+            n => Some(dedup::Settings {
+                window: Duration::from_secs(n as u64 * 60),
+                annotate,
+            }),
+        };
+        dedup::set_settings(db, &target_channel_id, settings).await?;
+
+        let msg = match settings {
+            Some(_) => format!(
+                "Messages relayed into <#{}> again within {} minute(s) are skipped{}.",
+                target_channel_id,
+                window,
+                match annotate {
+                    true => ", the relayed copy lists where else they were posted",
+                    false => "",
+                }
+            ),
+            None => format!("Every message is relayed into <#{}>.", target_channel_id),
+        };
+        Ok(CommandResponse {
+            title: "De-duplication Updated".to_owned(),
+            msg,
+            ..Default::default()
+        })
+    }
+
+    async fn autocomplete(
+        &self,
+        db: &SqlitePool,
+        autocomplete: &AutocompleteInteraction,
+    ) -> Result<AutocompleteResponse> {
+        target_autocomplete(db, autocomplete).await
+    }
+}
+
+pub struct TransformAdd;
+
+#[async_trait]
+impl Command for TransformAdd {
+    fn name(&self) -> &'static str {
+        "transform-add"
+    }
+
+    fn create<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Add a regex replace rule for the content relayed through a connection")
+            .create_option(|option| {
+                option
+                    .name(opt::SOURCE)
+                    .description("Source channel")
+                    .kind(ApplicationCommandOptionType::Channel)
+                    .required(true)
+                    .connectable_channel_types()
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::TARGET_CHANNEL)
+                    .description("Target channel")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+                    .set_autocomplete(true)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::PATTERN)
+                    .description("Regex to replace, a rule with the same pattern is updated")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::REPLACEMENT)
+                    .description("Replacement, $1 etc. refer to groups of the pattern (removes matches if empty)")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(false)
+            })
+    }
+
+    async fn run(
+        &self,
+        cx: &CommandContext<'_>,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResponse> {
+        let db = cx.db;
+        let options = &command.data.options;
+        let pattern = get_string_opt(opt::PATTERN, options)?;
+        let replacement = get_string_opt(opt::REPLACEMENT, options).map_or("", |s| s.as_str());
+        let rule = transform::Rule::new(pattern, replacement)?;
+        let (connection, source, target) = connection_from_options(db, command).await?;
+        transform::add_rule(db, connection, &rule).await?;
+        let rules = transform::get_rules(db, connection).await?;
+
+        Ok(CommandResponse {
+            title: "Transform Rules Updated".to_owned(),
+            msg: format!(
+                "<#{}> => <#{}>\nRules, applied in order:\n{}",
+                source,
+                target,
+                transform_rules(&rules)
+            ),
+            ..Default::default()
+        })
+    }
+
+    async fn autocomplete(
+        &self,
+        db: &SqlitePool,
+        autocomplete: &AutocompleteInteraction,
+    ) -> Result<AutocompleteResponse> {
+        connection_autocomplete(db, autocomplete).await
+    }
+}
+
+pub struct TransformRemove;
+
+#[async_trait]
+impl Command for TransformRemove {
+    fn name(&self) -> &'static str {
+        "transform-remove"
+    }
+
+    fn create<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Remove a regex replace rule of a connection")
+            .create_option(|option| {
+                option
+                    .name(opt::SOURCE)
+                    .description("Source channel")
+                    .kind(ApplicationCommandOptionType::Channel)
+                    .required(true)
+                    .connectable_channel_types()
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::TARGET_CHANNEL)
+                    .description("Target channel")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+                    .set_autocomplete(true)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::PATTERN)
+                    .description("Regex of the rule to remove")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+            })
+    }
+
+    async fn run(
+        &self,
+        cx: &CommandContext<'_>,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResponse> {
+        let db = cx.db;
+        let pattern = get_string_opt(opt::PATTERN, &command.data.options)?;
+        let (connection, source, target) = connection_from_options(db, command).await?;
+        transform::remove_rule(db, connection, pattern).await?;
+        let rules = transform::get_rules(db, connection).await?;
+
+        Ok(CommandResponse {
+            title: "Transform Rules Updated".to_owned(),
+            msg: format!(
+                "<#{}> => <#{}>\nRemoved: `{}`\n\nRules, applied in order:\n{}",
+                source,
+                target,
+                pattern,
+                transform_rules(&rules)
+            ),
+            ..Default::default()
+        })
+    }
+
+    async fn autocomplete(
+        &self,
+        db: &SqlitePool,
+        autocomplete: &AutocompleteInteraction,
+    ) -> Result<AutocompleteResponse> {
+        connection_autocomplete(db, autocomplete).await
+    }
+}
+
+pub struct TransformList;
+
+#[async_trait]
+impl Command for TransformList {
+    fn name(&self) -> &'static str {
+        "transform-list"
+    }
+
+    fn create<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("List the regex replace rules of a connection")
+            .create_option(|option| {
+                option
+                    .name(opt::SOURCE)
+                    .description("Source channel")
+                    .kind(ApplicationCommandOptionType::Channel)
+                    .required(true)
+                    .connectable_channel_types()
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::TARGET_CHANNEL)
+                    .description("Target channel")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+                    .set_autocomplete(true)
+            })
+    }
+
+    async fn run(
+        &self,
+        cx: &CommandContext<'_>,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResponse> {
+        let db = cx.db;
+        let (connection, source, target) = connection_from_options(db, command).await?;
+        let rules = transform::get_rules(db, connection).await?;
+
+        Ok(CommandResponse {
+            title: "Transform Rules".to_owned(),
+            msg: format!(
+                "<#{}> => <#{}>\nMentions of the source server are resolved and invite links \
+                removed before these rules are applied in order:\n{}",
+                source,
+                target,
+                transform_rules(&rules)
+            ),
+            ..Default::default()
+        })
+    }
+
+    async fn autocomplete(
+        &self,
+        db: &SqlitePool,
+        autocomplete: &AutocompleteInteraction,
+    ) -> Result<AutocompleteResponse> {
+        connection_autocomplete(db, autocomplete).await
+    }
+}
+
+pub struct TargetThread;
+
+#[async_trait]
+impl Command for TargetThread {
+    fn name(&self) -> &'static str {
+        "target-thread"
+    }
+
+    fn create<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Relay a connection into a thread or forum post of the target channel")
+            .create_option(|option| {
+                option
+                    .name(opt::SOURCE)
+                    .description("Source channel")
+                    .kind(ApplicationCommandOptionType::Channel)
+                    .required(true)
+                    .connectable_channel_types()
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::TARGET_CHANNEL)
+                    .description("Target channel")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+                    .set_autocomplete(true)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::THREAD)
+                    .description("Thread to post in (relays into the channel itself if left out)")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(false)
+                    .set_autocomplete(true)
+            })
+    }
+
+    async fn run(
+        &self,
+        cx: &CommandContext<'_>,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResponse> {
+        let db = cx.db;
+        let name = get_string_opt(opt::THREAD, &command.data.options).ok();
+        let (connection, source, target) = connection_from_options(db, command).await?;
+        let thread = match name {
+            Some(name) => Some(
+                thread::get_threads(db, &target)
+                    .await?
+                    .into_iter()
+                    .find(|t| &t.name == name)
+                    .ok_or(anyhow!("No such thread in <#{target}>: {name}"))?,
+            ),
+            None => None,
+        };
+        thread::set_target_thread(db, connection, thread.as_ref().map(|t| &t.id)).await?;
+
+        Ok(CommandResponse {
+            title: "Target Thread Updated".to_owned(),
+            msg: match thread {
+                Some(thread) => format!("<#{}> => <#{}> (in <#{}>)", source, thread.id, target),
+                None => format!("<#{}> => <#{}>", source, target),
+            },
+            ..Default::default()
+        })
+    }
+
+    async fn autocomplete(
+        &self,
+        db: &SqlitePool,
+        autocomplete: &AutocompleteInteraction,
+    ) -> Result<AutocompleteResponse> {
+        let param_target_channel = find_param(opt::TARGET_CHANNEL, autocomplete)?;
+        let param_thread = match find_param(opt::THREAD, autocomplete) {
+            Ok(param) if param.focused => param,
+            _ => return connection_autocomplete(db, autocomplete).await,
+        };
+
+        let (input, combined) = match (&param_thread.value, &param_target_channel.value) {
+            (Some(serde_json::Value::String(input)), Some(serde_json::Value::String(combined))) => {
+                (input.clone(), combined.clone())
+            }
+            (val, _) => bail!("Unexpected parameter type (expected string):\n{:#?}", val),
+        };
+        let (target_server_name, target_channel_name) = parse_target_channel(&combined)?;
+        let (_target_server_id, target_channel_id) =
+            name_to_ids(db, &target_server_name, &target_channel_name).await?;
+
+        let mut matching: Vec<(isize, String)> = thread::get_threads(db, &target_channel_id)
+            .await?
+            .into_iter()
+            .map(|t| match best_match(input.as_str(), t.name.as_str()) {
+                Some(m) => (100 - m.score(), t.name),
+                None => (100, t.name),
+            })
+            .collect();
+
+        matching.sort();
+        matching.drain(cmp::min(25, matching.len())..);
+
+        Ok(AutocompleteResponse {
+            options: matching.into_iter().map(|(_score, name)| name).collect(),
+        })
+    }
+}
+
+pub struct AutoPublish;
+
+#[async_trait]
+impl Command for AutoPublish {
+    fn name(&self) -> &'static str {
+        "auto-publish"
+    }
+
+    fn create<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Publish messages relayed into an announcement channel to its followers")
+            .create_option(|option| {
+                option
+                    .name(opt::SOURCE)
+                    .description("Source channel")
+                    .kind(ApplicationCommandOptionType::Channel)
+                    .required(true)
+                    .connectable_channel_types()
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::TARGET_CHANNEL)
+                    .description("Target channel")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+                    .set_autocomplete(true)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::ENABLED)
+                    .description("Publish each relayed message")
+                    .kind(ApplicationCommandOptionType::Boolean)
+                    .required(true)
+            })
+    }
+
+    async fn run(
+        &self,
+        cx: &CommandContext<'_>,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResponse> {
+        let db = cx.db;
+        let enabled = get_bool_opt(opt::ENABLED, &command.data.options)?;
+        let (connection, source, target) = connection_from_options(db, command).await?;
+        let kind = thread::channel_kind(db, &target).await?;
+        if enabled && kind != ChannelKind::News {
+            bail!("<#{target}> is not an announcement channel, there is nothing to publish to");
+        }
+        thread::set_publish(db, connection, enabled).await?;
+
+        Ok(CommandResponse {
+            title: "Auto-Publish Updated".to_owned(),
+            msg: match enabled {
+                true => format!(
+                    "<#{}> => <#{}>\nRelayed messages are published to the followers of <#{}>. \
+                    The bot needs the Manage Messages permission there.",
+                    source, target, target
+                ),
+                false => format!(
+                    "<#{}> => <#{}>\nRelayed messages are not published",
+                    source, target
+                ),
+            },
+            ..Default::default()
+        })
+    }
+
+    async fn autocomplete(
+        &self,
+        db: &SqlitePool,
+        autocomplete: &AutocompleteInteraction,
+    ) -> Result<AutocompleteResponse> {
+        connection_autocomplete(db, autocomplete).await
+    }
+}
+
+pub struct ReactionMirroring;
+
+#[async_trait]
+impl Command for ReactionMirroring {
+    fn name(&self) -> &'static str {
+        "reaction-mirroring"
+    }
+
+    fn create<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Show the reactions on source messages below their relayed copies")
+            .create_option(|option| {
+                option
+                    .name(opt::SOURCE)
+                    .description("Source channel")
+                    .kind(ApplicationCommandOptionType::Channel)
+                    .required(true)
+                    .connectable_channel_types()
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::TARGET_CHANNEL)
+                    .description("Target channel")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+                    .set_autocomplete(true)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::ENABLED)
+                    .description("Mirror reactions added and removed on source messages")
+                    .kind(ApplicationCommandOptionType::Boolean)
+                    .required(true)
+            })
+    }
+
+    async fn run(
+        &self,
+        cx: &CommandContext<'_>,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResponse> {
+        let db = cx.db;
+        let enabled = get_bool_opt(opt::ENABLED, &command.data.options)?;
+        let (connection, source, target) = connection_from_options(db, command).await?;
+        reaction::set_mirroring(db, connection, enabled).await?;
+
+        Ok(CommandResponse {
+            title: "Reaction Mirroring Updated".to_owned(),
+            msg: match enabled {
+                true => format!(
+                    "<#{}> => <#{}>\nReactions on source messages are shown below their relayed copies",
+                    source, target
+                ),
+                false => format!("<#{}> => <#{}>\nReactions are not mirrored", source, target),
+            },
+            ..Default::default()
+        })
+    }
+
+    async fn autocomplete(
+        &self,
+        db: &SqlitePool,
+        autocomplete: &AutocompleteInteraction,
+    ) -> Result<AutocompleteResponse> {
+        connection_autocomplete(db, autocomplete).await
+    }
+}
+
+pub struct Scoreboard;
+
+#[async_trait]
+impl Command for Scoreboard {
+    fn name(&self) -> &'static str {
+        "scoreboard"
+    }
+
+    fn create<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Rank the authors of the relayed messages")
+            .create_option(|option| {
+                option
+                    .name(opt::PERIOD)
+                    .description("Messages relayed in this period count")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+                    .add_string_choice("Last 24 hours", "day")
+                    .add_string_choice("Last 7 days", "week")
+                    .add_string_choice("Last 30 days", "month")
+                    .add_string_choice("All time", "all")
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::BY)
+                    .description("What the authors are ranked by (reactions if left out)")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(false)
+                    .add_string_choice("Messages relayed", "messages")
+                    .add_string_choice("Reactions on the relayed copies", "reactions")
+                    .add_string_choice("Target channels reached", "targets")
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::SERVER)
+                    .description("Only count messages from this source server")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(false)
+                    .set_autocomplete(true)
+            })
+            .create_option(|option| {
+                option
+                    .name(opt::PUBLIC)
+                    .description("Show the scoreboard to everyone in the channel")
+                    .kind(ApplicationCommandOptionType::Boolean)
+                    .required(false)
+            })
+    }
+
+    fn timing(&self) -> CommandTiming {
+        CommandTiming::Deferred(Duration::from_secs(30))
+    }
+
+    async fn run(
+        &self,
+        cx: &CommandContext<'_>,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResponse> {
+        let db = cx.db;
+        let options = &command.data.options;
+        let period = scoreboard::Period::parse(get_string_opt(opt::PERIOD, options)?)?;
+        let ranking = match get_string_opt(opt::BY, options) {
+            Ok(ranking) => scoreboard::Ranking::parse(ranking)?,
+            Err(_) => scoreboard::Ranking::Reactions,
+        };
+        let guild = match get_string_opt(opt::SERVER, options) {
+            Ok(server_name) => Some(get_guild_id(db, server_name).await?),
+            Err(_) => None,
+        };
+
+        let scope = SearchScope {
+            user: command.user.id,
+            guild: command.guild_id.map(|g| g.0 as i64),
+        };
+        let since = period.since(digest::unix_now());
+        let scores = scoreboard::scoreboard(db, &scope, since, guild, ranking).await?;
+        if scores.is_empty() {
+            bail!("No messages were relayed in the {period}");
+        }
+
+        Ok(CommandResponse {
+            title: format!("Scoreboard ({period}, by {})", ranking.as_str()),
+            msg: scores
+                .iter()
+                .enumerate()
+                .map(|(i, score)| score.line(i + 1))
+                .collect::<Vec<String>>()
+                .join("\n"),
+            public: get_bool_opt(opt::PUBLIC, options).unwrap_or(false),
+            ..Default::default()
+        })
+    }
+
+    async fn autocomplete(
+        &self,
+        db: &SqlitePool,
+        autocomplete: &AutocompleteInteraction,
+    ) -> Result<AutocompleteResponse> {
+        let input = match &find_param(opt::SERVER, autocomplete)?.value {
+            Some(serde_json::Value::String(input)) => input.clone(),
+            Some(val) => bail!("Unexpected parameter type (expected string):\n{:#?}", val),
+            None => bail!("No parameter value found"),
+        };
+        let scope = SearchScope {
+            user: autocomplete.user.id,
+            guild: autocomplete.guild_id.map(|g| g.0 as i64),
+        };
+        closest_names(&input, scoreboard::servers(db, &scope).await?)
+    }
+}
+
+pub struct RelayMessage;
+
+#[async_trait]
+impl Command for RelayMessage {
+    fn name(&self) -> &'static str {
+        RELAY_COMMAND
+    }
+
+    fn create<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command.kind(ApplicationCommandType::Message)
+    }
+
+    async fn run(
+        &self,
+        cx: &CommandContext<'_>,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResponse> {
+        let db = cx.db;
+        let relays = cx.relays;
+        let mut msg = match &command.data.target {
+            Some(ResolvedTarget::Message(msg)) => msg.clone(),
+            _ => bail!("No message to relay"),
+        };
+        // Resolved messages don't always carry the guild, it is needed for the jump link.
+        if msg.guild_id.is_none() {
+            msg.guild_id = command.guild_id;
+        }
+
+        let servers = relay::servers(db).await?;
+        if servers.is_empty() {
+            bail!("There are no servers to relay to");
+        }
+        let link = msg.link();
+        relays.insert(command.id.0, command.user.id, msg);
+
+        Ok(CommandResponse {
+            title: "Relay message".to_owned(),
+            msg: format!("Select the server to relay [the message](<{link}>) to"),
+            components: Some(relay::server_select(command.id.0, &servers, 0)),
+            ..Default::default()
+        })
+    }
+}
+
+/// The target server, then a channel on that server.
+async fn target_autocomplete(
+    db: &SqlitePool,
+    autocomplete: &AutocompleteInteraction,
+) -> Result<AutocompleteResponse> {
+    let param_target_server = find_param(opt::TARGET_SERVER, autocomplete)?;
+
+    let server_name = match &param_target_server.value {
+        Some(serde_json::Value::String(input)) => input.clone(),
+        Some(val) => bail!("Unexpected parameter type (expected string):\n{:#?}", val),
+        None => bail!("No parameter value found"),
+    };
+
+    if param_target_server.focused {
+        return connect_target_server_autocomplete(db, &server_name).await;
+    }
+    let param_target_channel =
+        find_param(opt::TARGET_CHANNEL, autocomplete).map_err(|_| anyhow!("No target channel"))?;
+    if param_target_channel.focused {
+        connect_target_channel_autocomplete(db, &server_name, param_target_channel).await
+    } else {
+        bail!("Target channel not focused")
+    }
+}
+
+/// Target channels the source channel is connected to.
+async fn connection_autocomplete(
+    db: &SqlitePool,
+    autocomplete: &AutocompleteInteraction,
+) -> Result<AutocompleteResponse> {
+    let param_source_channel = find_param(opt::SOURCE, autocomplete)?;
+    let param_target_channel = find_param(opt::TARGET_CHANNEL, autocomplete)?;
+
+    if param_target_channel.focused {
+        disconnect_target_channel_autocomplete(db, param_source_channel, param_target_channel).await
+    } else {
+        bail!("Target channel not focused")
+    }
+}
+
+/// Any server the bot is in.
+async fn server_autocomplete(
+    db: &SqlitePool,
+    autocomplete: &AutocompleteInteraction,
+) -> Result<AutocompleteResponse> {
+    let param_server = find_param(opt::SERVER, autocomplete)?;
+
+    let server_name = match &param_server.value {
+        Some(serde_json::Value::String(input)) => input.clone(),
+        Some(val) => bail!("Unexpected parameter type (expected string):\n{:#?}", val),
+        None => bail!("No parameter value found"),
+    };
+
+    connect_target_server_autocomplete(db, &server_name).await
+}
+
+/// Summary lines of the form "[<SERVER_NAME>] <CHANNEL_NAME>: <COUNT> <WHAT>".
+fn count_summary(rows: Vec<(String, String, i32)>, what: &str) -> (i32, String) {
+    let total = rows.iter().map(|(_, _, count)| count).sum();
+    let lines = rows
+        .into_iter()
+        .map(|(guild, channel, count)| format!("> [{guild}] {channel}: **{count}** {what}"))
+        .collect::<Vec<String>>()
+        .join("\n");
+    (total, lines)
+}
+
+async fn mention_exists(
+    db: &SqlitePool,
+    source: &ChannelId,
+    target: &ChannelId,
+    mention: &str,
+    condition: Option<&MentionCondition>,
+) -> Result<bool> {
+    let source = source.0 as i64;
+    let target = target.0 as i64;
+    let kind = condition.map(|c| c.kind());
+    let value = condition.map(|c| c.value());
+    let count = sqlx::query!(
+        "
+        SELECT COUNT(1) as count\n\
+        FROM Mentions\n\
+        WHERE source = ? AND target = ? AND mention = ? AND condition_kind IS ? AND condition IS ?
+        ",
+        source,
+        target,
+        mention,
+        kind,
+        value
+    )
+    .fetch_one(db)
+    .and_then(|row| async move { Ok(row.count) })
+    .await
+    .map_err(|e| Error::new(e).context("Failed to count existing mentions in the database"))?;
+
+    Ok(count != 0)
+}
+
+async fn mention_exists_no_source(
+    db: &SqlitePool,
+    target: &ChannelId,
+    mention: &str,
+    condition: Option<&MentionCondition>,
+) -> Result<bool> {
+    let target = target.0 as i64;
+    let kind = condition.map(|c| c.kind());
+    let value = condition.map(|c| c.value());
+    let count = sqlx::query!(
+        "
+        SELECT COUNT(1) as count\n\
+        FROM Mentions\n\
+        WHERE source IS NULL AND target = ? AND mention = ? AND condition_kind IS ? AND condition IS ?
+        ",
+        target,
+        mention,
+        kind,
+        value
+    )
+    .fetch_one(db)
+    .and_then(|row| async move { Ok(row.count) })
+    .await
+    .map_err(|e| Error::new(e).context("Failed to count existing mentions in the database"))?;
+
+    Ok(count != 0)
+}
+
+fn watchlist_tags(symbols: &[String]) -> String {
+    match symbols.is_empty() {
+        true => "*(empty, every message is relayed)*".to_owned(),
+        false => symbols
+            .iter()
+            .map(|s| format!("`{s}`"))
+            .collect::<Vec<String>>()
+            .join(" "),
+    }
+}
+
+fn transform_rules(rules: &[transform::Rule]) -> String {
+    match rules.is_empty() {
+        true => "*(none)*".to_owned(),
+        false => rules
+            .iter()
+            .enumerate()
+            .map(|(i, r)| format!("{}. `{}` → `{}`", i + 1, r.pattern, r.replacement))
+            .collect::<Vec<String>>()
+            .join("\n"),
+    }
+}
+
+async fn get_guild_id(db: &SqlitePool, server_name: &str) -> Result<i64> {
+    sqlx::query!(
+        "SELECT id as \"id: i64\" FROM Guilds WHERE name = ?",
+        server_name
+    )
+    .fetch_optional(db)
+    .await
+    .map_err(|e| Error::new(e).context("Failed to retrieve guild id from the database"))?
+    .map(|row| row.id)
+    .ok_or(anyhow!("Unknown server: __**{server_name}**__"))
+}
@@ -0,0 +1,348 @@
+//! Reading the options of a command and completing them as they are typed.
+
+use anyhow::{anyhow, bail, Context, Result};
+use futures::TryFutureExt;
+use regex::Regex;
+use serenity::model::{
+    channel::PartialChannel,
+    id::{ChannelId, GuildId, UserId},
+    interactions::{
+        application_command::{
+            ApplicationCommandInteraction, ApplicationCommandInteractionDataOption,
+            ApplicationCommandInteractionDataOptionValue,
+        },
+        autocomplete::AutocompleteInteraction,
+    },
+};
+use sqlx::SqlitePool;
+use std::cmp;
+use sublime_fuzzy::best_match;
+
+use super::opt;
+use crate::{condition::MentionCondition, get_connection_id, name_to_ids, AutocompleteResponse};
+
+pub fn get_channel_opt<'a>(
+    name: &str,
+    options: &'a [ApplicationCommandInteractionDataOption],
+) -> Result<&'a PartialChannel> {
+    options
+        .iter()
+        .find(|&opt| opt.name == name)
+        .and_then(|op| {
+            op.resolved.as_ref().and_then(|ch| match ch {
+                ApplicationCommandInteractionDataOptionValue::Channel(ch) => Some(ch),
+                _ => None,
+            })
+        })
+        .ok_or(anyhow!("Failed to retrieve channel option: \"{}\"", name))
+}
+
+pub fn get_int_opt(name: &str, options: &[ApplicationCommandInteractionDataOption]) -> Result<i64> {
+    options
+        .iter()
+        .find(|&opt| opt.name == name)
+        .and_then(|op| {
+            op.resolved.as_ref().and_then(|val| match val {
+                ApplicationCommandInteractionDataOptionValue::Integer(i) => Some(*i),
+                _ => None,
+            })
+        })
+        .ok_or(anyhow!("Failed to retrieve integer option: \"{}\"", name))
+}
+
+pub fn get_bool_opt(
+    name: &str,
+    options: &[ApplicationCommandInteractionDataOption],
+) -> Result<bool> {
+    options
+        .iter()
+        .find(|&opt| opt.name == name)
+        .and_then(|op| {
+            op.resolved.as_ref().and_then(|val| match val {
+                ApplicationCommandInteractionDataOptionValue::Boolean(b) => Some(*b),
+                _ => None,
+            })
+        })
+        .ok_or(anyhow!("Failed to retrieve boolean option: \"{}\"", name))
+}
+
+pub fn get_string_opt<'a>(
+    name: &str,
+    options: &'a [ApplicationCommandInteractionDataOption],
+) -> Result<&'a String> {
+    options
+        .iter()
+        .find(|&opt| opt.name == name)
+        .and_then(|op| {
+            op.resolved.as_ref().and_then(|ch| match ch {
+                ApplicationCommandInteractionDataOptionValue::String(s) => Some(s),
+                _ => None,
+            })
+        })
+        .ok_or(anyhow!("Failed to retrieve string option: \"{}\"", name))
+}
+
+pub fn get_user_opt(
+    name: &str,
+    options: &[ApplicationCommandInteractionDataOption],
+) -> Result<UserId> {
+    options
+        .iter()
+        .find(|&opt| opt.name == name)
+        .and_then(|op| {
+            op.resolved.as_ref().and_then(|val| match val {
+                ApplicationCommandInteractionDataOptionValue::User(user, _) => Some(user.id),
+                _ => None,
+            })
+        })
+        .ok_or(anyhow!("Failed to retrieve user option: \"{}\"", name))
+}
+
+/// Split a "[<SERVER_NAME>] <CHANNEL_NAME>" autocomplete choice into its parts.
+pub fn parse_target_channel(combined: &str) -> Result<(String, String)> {
+    let re = Regex::new(r"\[(?P<server>.*)\] (?P<channel>.*)")?;
+    match re.captures(combined) {
+        Some(caps) => {
+            let server_name = caps["server"].trim().to_owned();
+            let channel_name = caps["channel"].trim().to_owned();
+            Ok((server_name, channel_name))
+        }
+        None => {
+            bail!("Invalid target channel format\nIt has to be the following format: [<SERVER_NAME>] <CHANNEL_NAME>");
+        }
+    }
+}
+
+/// The condition of a new mention rule, at most one of the condition options can be set.
+pub fn mention_condition_opt(
+    options: &[ApplicationCommandInteractionDataOption],
+) -> Result<Option<MentionCondition>> {
+    let mut conditions = Vec::new();
+    for kind in [opt::KEYWORD, opt::REGEX, opt::TICKER] {
+        if let Ok(value) = get_string_opt(kind, options) {
+            conditions.push(MentionCondition::parse(kind, value)?);
+        }
+    }
+    if let Ok(author) = get_user_opt(opt::AUTHOR, options) {
+        conditions.push(MentionCondition::Author(author));
+    }
+    if conditions.len() > 1 {
+        bail!("Only one of keyword, regex, ticker or author can be set");
+    }
+    Ok(conditions.pop())
+}
+
+/// `None` for 0, which removes a limit.
+pub fn per_minute_opt(options: &[ApplicationCommandInteractionDataOption]) -> Result<Option<i64>> {
+    match get_int_opt(opt::PER_MINUTE, options)? {
+        0 => Ok(None),
+        n if n < 0 => bail!("The limit can't be negative"),
+        n => Ok(Some(n)),
+    }
+}
+
+/// "-" is used to remove an optional template part.
+pub fn optional_template_text(text: &str) -> Option<String> {
+    match text.trim() {
+        "-" => None,
+        _ => Some(text.replace("\\n", "\n")),
+    }
+}
+
+/// The user's connection picked with the `source` and `target_channel` options.
+pub async fn connection_from_options(
+    db: &SqlitePool,
+    command: &ApplicationCommandInteraction,
+) -> Result<(i64, ChannelId, ChannelId)> {
+    let options = &command.data.options;
+    let source = get_channel_opt(opt::SOURCE, options)?;
+    let combined = get_string_opt(opt::TARGET_CHANNEL, options)?;
+    let (target_server_name, target_channel_name) = parse_target_channel(combined)?;
+    let (_target_server_id, target_channel_id) =
+        name_to_ids(db, &target_server_name, &target_channel_name).await?;
+    let connection =
+        get_connection_id(db, &source.id, &target_channel_id, &command.user.id).await?;
+    Ok((connection, source.id, target_channel_id))
+}
+
+/// Alerts are kept per server, so that they only match what the user can see.
+pub fn alert_guild(guild: Option<GuildId>) -> Result<GuildId> {
+    guild.ok_or_else(|| anyhow!("Alerts can only be managed in a server"))
+}
+
+pub fn find_param<'a>(
+    name: &str,
+    autocomplete: &'a AutocompleteInteraction,
+) -> Result<&'a ApplicationCommandInteractionDataOption> {
+    autocomplete
+        .data
+        .options
+        .iter()
+        .find(|opt| opt.name == name)
+        .ok_or(anyhow!("Did not find autocomplete parameter: {name}"))
+}
+
+/// The names best matching the input, at most 25 as Discord allows.
+pub fn closest_names(input: &str, names: Vec<String>) -> Result<AutocompleteResponse> {
+    // Matching score, lower score is a better match.
+    let mut matching: Vec<(isize, String)> = names
+        .into_iter()
+        .map(|s| {
+            let score = match best_match(input, s.as_str()) {
+                Some(m) => (100 - m.score(), s),
+                None => (100, s),
+            };
+            score
+        })
+        .collect();
+
+    if matching.is_empty() {
+        bail!("No guilds found");
+    }
+
+    matching.sort();
+    matching.drain(cmp::min(25, matching.len())..);
+
+    Ok(AutocompleteResponse {
+        options: matching.into_iter().map(|(_score, name)| name).collect(),
+    })
+}
+
+async fn get_guild_names(db: &SqlitePool) -> Result<Vec<String>> {
+    sqlx::query!("SELECT Guilds.name FROM Guilds")
+        .fetch_all(db)
+        .and_then(|result| async { Ok(result.into_iter().map(|record| record.name).collect()) })
+        .await
+        .map_err(|e| anyhow!(e).context("Failed to retrieve guild names from the database"))
+}
+
+async fn get_channel_names(server_name: &str, db: &SqlitePool) -> Result<Vec<String>> {
+    sqlx::query!(
+        "
+        SELECT Channels.name\n\
+        FROM Channels\n\
+        JOIN Guilds\n\
+        ON Guilds.name = ? AND Channels.guild = Guilds.id",
+        server_name
+    )
+    .fetch_all(db)
+    .and_then(|records| async { Ok(records.into_iter().map(|record| record.name).collect()) })
+    .await
+    .map_err(|e| anyhow!(e).context("Failed to retrieve channel names from database"))
+}
+
+pub async fn connect_target_server_autocomplete(
+    db: &SqlitePool,
+    server_name: &str,
+) -> Result<AutocompleteResponse> {
+    let servers = get_guild_names(db).await?;
+    closest_names(server_name, servers)
+}
+
+pub async fn connect_target_channel_autocomplete(
+    db: &SqlitePool,
+    server_name: &str,
+    opt: &ApplicationCommandInteractionDataOption,
+) -> Result<AutocompleteResponse> {
+    if server_name.trim().is_empty() {
+        bail!("No server name");
+    }
+
+    let channel_name = match &opt.value {
+        Some(serde_json::Value::String(input)) => input.clone(),
+        _ => bail!("Expected option to be of type string:\n{:#?}", opt.value),
+    };
+
+    let channels = get_channel_names(server_name, db).await?;
+
+    // Matching score, lower score is a better match.
+    let mut matching: Vec<(isize, String)> = channels
+        .into_iter()
+        .map(|s| {
+            let score = match best_match(channel_name.as_str(), s.as_str()) {
+                Some(m) => (100 - m.score(), s),
+                None => (100, s),
+            };
+            score
+        })
+        .collect();
+
+    if matching.is_empty() {
+        bail!("No matching channels");
+    }
+
+    matching.sort();
+    matching.drain(cmp::min(25, matching.len())..);
+
+    Ok(AutocompleteResponse {
+        options: matching.into_iter().map(|(_score, name)| name).collect(),
+    })
+}
+
+pub async fn disconnect_target_channel_autocomplete(
+    db: &SqlitePool,
+    source_channel: &ApplicationCommandInteractionDataOption,
+    target_channel: &ApplicationCommandInteractionDataOption,
+) -> Result<AutocompleteResponse> {
+    let target_channel = match &target_channel.value {
+        Some(serde_json::Value::String(input)) => input.clone(),
+        Some(val) => bail!("Expected option to be of type string:\n{:#?}", val),
+        None => bail!("Did not find option \"target_channel\""),
+    };
+
+    let source_channel: i64 = match &source_channel.value {
+        Some(serde_json::Value::String(input)) => input
+            .parse()
+            .context("Failed to parse \"source_channel\"")?,
+        Some(val) => bail!("Expected option to be of type string:\n{:#?}", val),
+        None => bail!("Did not find option \"target_channel\""),
+    };
+
+    let channels: Vec<String> = sqlx::query!(
+        "
+        SELECT\n\
+        Guilds.name as guild_name,\n\
+        Channels.name as channel_name\n\
+        FROM Channels\n\
+        JOIN Connections\n\
+        ON Channels.id = Connections.target\n\
+        JOIN Guilds\n\
+        ON Channels.guild = Guilds.id\n\
+        WHERE Connections.source = ?\n\
+        ORDER BY Guilds.name
+        ",
+        source_channel
+    )
+    .fetch_all(db)
+    .and_then(|rows| async move {
+        Ok(rows
+            .into_iter()
+            .map(|row| format!("[{}] {}", row.guild_name, row.channel_name))
+            .collect())
+    })
+    .await
+    .context("Failed to retrieve target channel names from the database")?;
+
+    if channels.is_empty() {
+        bail!("No target channels found")
+    }
+
+    // Matching score, lower score is a better match.
+    let mut matching: Vec<(isize, String)> = channels
+        .into_iter()
+        .map(|s| {
+            let score = match best_match(target_channel.as_str(), s.as_str()) {
+                Some(m) => (100 - m.score(), s),
+                None => (100, s),
+            };
+            score
+        })
+        .collect();
+
+    matching.sort();
+    matching.drain(cmp::min(25, matching.len())..);
+
+    Ok(AutocompleteResponse {
+        options: matching.into_iter().map(|(_score, name)| name).collect(),
+    })
+}
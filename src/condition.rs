@@ -0,0 +1,148 @@
+use anyhow::{anyhow, bail, Result};
+use once_cell::sync::Lazy;
+use regex::{Regex, RegexBuilder};
+use serenity::model::id::UserId;
+use std::{collections::HashMap, fmt::Display, sync::Mutex};
+
+use crate::ticker::{self, Ticker};
+
+// Keeps a pathological pattern from using up memory when it is compiled.
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+// Compiled patterns are kept until there are more than this many.
+const MAX_COMPILED: usize = 1000;
+
+// User-defined patterns are checked against every relayed message, they're
+// only compiled once. Keyed by the pattern and whether it ignores case.
+static COMPILED: Lazy<Mutex<HashMap<(String, bool), Regex>>> = Lazy::new(Default::default);
+
+/// A regex compiled when its rule is loaded, compared by its source.
+#[derive(Clone, Debug)]
+pub struct Pattern(Regex);
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+/// When a mention rule applies, rules without a condition always do.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MentionCondition {
+    /// The content contains the keyword, ignoring case.
+    Keyword(String),
+    /// The content matches the pattern, ignoring case.
+    Regex(Pattern),
+    /// One of the tickers in the content is covered by the symbol.
+    Ticker(String),
+    /// The message was posted by the user.
+    Author(UserId),
+}
+
+/// The message a mention rule is checked against.
+pub struct Subject {
+    content: String,
+    tickers: Vec<Ticker>,
+    author: UserId,
+}
+
+impl Subject {
+    pub fn new(content: &str, author: UserId) -> Self {
+        Subject {
+            content: content.to_owned(),
+            tickers: ticker::extract(content),
+            author,
+        }
+    }
+}
+
+/// Compile a user-defined pattern, or take it from those compiled before.
+pub fn compile_pattern(pattern: &str, case_insensitive: bool) -> Result<Regex> {
+    let key = (pattern.to_owned(), case_insensitive);
+    if let Some(regex) = COMPILED.lock().unwrap().get(&key) {
+        return Ok(regex.clone());
+    }
+    let regex = RegexBuilder::new(pattern)
+        .case_insensitive(case_insensitive)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .build()
+        .map_err(|e| anyhow!("Invalid regex `{pattern}`: {e}"))?;
+    let mut compiled = COMPILED.lock().unwrap();
+    if compiled.len() >= MAX_COMPILED {
+        compiled.clear();
+    }
+    compiled.insert(key, regex.clone());
+    Ok(regex)
+}
+
+fn compile(pattern: &str) -> Result<Regex> {
+    compile_pattern(pattern, true)
+}
+
+impl MentionCondition {
+    pub fn parse(kind: &str, value: &str) -> Result<Self> {
+        let value = value.trim();
+        if value.is_empty() {
+            bail!("Empty {kind} condition");
+        }
+        match kind {
+            "keyword" => Ok(MentionCondition::Keyword(value.to_owned())),
+            "regex" => Ok(MentionCondition::Regex(Pattern(compile(value)?))),
+            "ticker" => Ok(MentionCondition::Ticker(ticker::parse_symbol(value)?)),
+            "author" => value
+                .parse()
+                .map(|id| MentionCondition::Author(UserId(id)))
+                .map_err(|_| anyhow!("Invalid author id: {value}")),
+            kind => Err(anyhow!("Unknown mention condition: {kind}")),
+        }
+    }
+
+    /// The condition as it is stored in the `condition_kind` and `condition`
+    /// columns, both are NULL for rules without a condition.
+    pub fn from_row(kind: Option<&str>, value: Option<&str>) -> Result<Option<Self>> {
+        match (kind, value) {
+            (Some(kind), Some(value)) => Self::parse(kind, value).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            MentionCondition::Keyword(_) => "keyword",
+            MentionCondition::Regex(_) => "regex",
+            MentionCondition::Ticker(_) => "ticker",
+            MentionCondition::Author(_) => "author",
+        }
+    }
+
+    pub fn value(&self) -> String {
+        match self {
+            MentionCondition::Keyword(s) | MentionCondition::Ticker(s) => s.clone(),
+            MentionCondition::Regex(pattern) => pattern.0.as_str().to_owned(),
+            MentionCondition::Author(id) => id.0.to_string(),
+        }
+    }
+
+    pub fn matches(&self, subject: &Subject) -> bool {
+        match self {
+            MentionCondition::Keyword(keyword) => subject
+                .content
+                .to_lowercase()
+                .contains(&keyword.to_lowercase()),
+            MentionCondition::Regex(pattern) => pattern.0.is_match(&subject.content),
+            MentionCondition::Ticker(symbol) => subject.tickers.iter().any(|t| t.matches(symbol)),
+            MentionCondition::Author(id) => subject.author == *id,
+        }
+    }
+}
+
+impl Display for MentionCondition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MentionCondition::Keyword(keyword) => write!(f, "mentions \"{keyword}\""),
+            MentionCondition::Regex(pattern) => write!(f, "matches `{}`", pattern.0.as_str()),
+            MentionCondition::Ticker(symbol) => write!(f, "mentions `{symbol}`"),
+            MentionCondition::Author(id) => write!(f, "posted by <@{id}>"),
+        }
+    }
+}
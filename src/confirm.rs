@@ -0,0 +1,96 @@
+use anyhow::{bail, Result};
+use serenity::{
+    builder::CreateComponents, model::id::UserId,
+    model::interactions::message_component::ButtonStyle,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+// Destructive commands have to be confirmed within this time.
+pub const CONFIRM_TIMEOUT: Duration = Duration::from_secs(60);
+
+const CONFIRM_PREFIX: &str = "confirm";
+const CANCEL_PREFIX: &str = "cancel";
+
+/// A destructive command waiting for the user to press Confirm.
+#[derive(Clone)]
+pub enum PendingAction {
+    WipeConnections { server_name: String },
+    WipeMentions { server_name: String },
+}
+
+struct Pending {
+    user: UserId,
+    action: PendingAction,
+    created: Instant,
+}
+
+/// Pending destructive commands, keyed by the id of the command interaction.
+#[derive(Clone, Default)]
+pub struct Confirmations(Arc<Mutex<HashMap<u64, Pending>>>);
+
+impl Confirmations {
+    pub fn insert(&self, key: u64, user: UserId, action: PendingAction) {
+        let mut map = self.0.lock().unwrap();
+        map.retain(|_, p| p.created.elapsed() < CONFIRM_TIMEOUT);
+        map.insert(
+            key,
+            Pending {
+                user,
+                action,
+                created: Instant::now(),
+            },
+        );
+    }
+
+    /// Remove and return the pending action if `user` is the one who ran the command.
+    pub fn take(&self, key: u64, user: UserId) -> Result<PendingAction> {
+        let mut map = self.0.lock().unwrap();
+        match map.get(&key) {
+            Some(p) if p.user != user => bail!("Only the user who ran the command can confirm it"),
+            Some(p) if p.created.elapsed() < CONFIRM_TIMEOUT => (),
+            _ => {
+                map.remove(&key);
+                bail!("The confirmation timed out, nothing was deleted")
+            }
+        }
+        Ok(map.remove(&key).unwrap().action)
+    }
+
+    pub fn cancel(&self, key: u64, user: UserId) -> Result<()> {
+        self.take(key, user).map(|_| ())
+    }
+}
+
+pub enum Choice {
+    Confirm,
+    Cancel,
+}
+
+pub fn parse_confirm_id(custom_id: &str) -> Option<(Choice, u64)> {
+    let (prefix, key) = custom_id.split_once(':')?;
+    let key = key.parse().ok()?;
+    match prefix {
+        CONFIRM_PREFIX => Some((Choice::Confirm, key)),
+        CANCEL_PREFIX => Some((Choice::Cancel, key)),
+        _ => None,
+    }
+}
+
+pub fn confirm_buttons(components: &mut CreateComponents, key: u64) -> &mut CreateComponents {
+    components.create_action_row(|row| {
+        row.create_button(|b| {
+            b.style(ButtonStyle::Danger)
+                .label("Confirm")
+                .custom_id(format!("{CONFIRM_PREFIX}:{key}"))
+        })
+        .create_button(|b| {
+            b.style(ButtonStyle::Secondary)
+                .label("Cancel")
+                .custom_id(format!("{CANCEL_PREFIX}:{key}"))
+        })
+    })
+}
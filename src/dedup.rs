@@ -0,0 +1,210 @@
+use anyhow::{Error, Result};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serenity::model::id::{ChannelId, WebhookId};
+use sqlx::SqlitePool;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, VecDeque},
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{output::OutgoingMessage, transport::Posted};
+
+static MENTIONS: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"<(@[!&]?|#)\d+>|@everyone|@here").unwrap());
+
+/// How duplicates are suppressed in a target channel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Settings {
+    pub window: Duration,
+    /// Add "also posted in …" to the copy that was relayed.
+    pub annotate: bool,
+}
+
+/// The de-duplication settings of the target channel, `None` if it's off.
+pub async fn get_settings(db: &SqlitePool, target: &ChannelId) -> Result<Option<Settings>> {
+    let target = target.0 as i64;
+    let row = sqlx::query!(
+        "
+        SELECT dedup_window, dedup_annotate as \"dedup_annotate: bool\"\n\
+        FROM Channels WHERE id = ?
+        ",
+        target
+    )
+    .fetch_optional(db)
+    .await
+    .map_err(|e| Error::new(e).context("Failed to retrieve de-duplication settings"))?;
+
+    Ok(row.and_then(|row| {
+        row.dedup_window.map(|window| Settings {
+            window: Duration::from_secs(window as u64),
+            annotate: row.dedup_annotate,
+        })
+    }))
+}
+
+/// `None` turns de-duplication off for the target channel.
+pub async fn set_settings(
+    db: &SqlitePool,
+    target: &ChannelId,
+    settings: Option<Settings>,
+) -> Result<()> {
+    let target = target.0 as i64;
+    let window = settings.map(|s| s.window.as_secs() as i64);
+    let annotate = matches!(settings, Some(s) if s.annotate);
+    sqlx::query!(
+        "UPDATE Channels SET dedup_window = ?, dedup_annotate = ? WHERE id = ?",
+        window,
+        annotate,
+        target
+    )
+    .execute(db)
+    .await
+    .map_err(|e| Error::new(e).context("Failed to update de-duplication settings"))?;
+
+    Ok(())
+}
+
+/// Case, whitespace and mentions don't make a message new.
+pub fn normalize(content: &str) -> String {
+    MENTIONS
+        .replace_all(content, " ")
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+pub fn content_hash(content: &str) -> u64 {
+    hash_normalized(&normalize(content))
+}
+
+/// Hash of content that was already normalized.
+fn hash_normalized(normalized: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    normalized.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The copy of a message that was relayed into a target channel.
+struct Relayed {
+    hash: u64,
+    at: Instant,
+    source: String,
+    /// Set once the copy was posted, it's edited to list the other sources.
+    posted: Option<(WebhookId, Posted, OutgoingMessage)>,
+    /// Sources the duplicates came from, e.g. "**Server** #channel".
+    also: Vec<String>,
+}
+
+/// What to do with a message about to be relayed.
+pub enum Verdict {
+    Relay,
+    /// Already relayed, edit the copy if it's annotated with a new source.
+    Duplicate(Option<Annotation>),
+}
+
+/// A note on a relayed copy listing where else it was posted.
+pub struct Annotation {
+    pub webhook: WebhookId,
+    pub posted: Posted,
+    /// The first part of the copy as it was posted.
+    pub original: OutgoingMessage,
+    pub note: String,
+}
+
+fn annotation(relayed: &Relayed) -> Option<Annotation> {
+    let (webhook, posted, original) = relayed.posted.as_ref()?;
+    if relayed.also.is_empty() {
+        return None;
+    }
+    Some(Annotation {
+        webhook: *webhook,
+        posted: *posted,
+        original: original.clone(),
+        note: format!("*Also posted in {}*", relayed.also.join(", ")),
+    })
+}
+
+/// Messages recently relayed into each target channel.
+#[derive(Clone, Default)]
+pub struct Duplicates(Arc<Mutex<HashMap<ChannelId, VecDeque<Relayed>>>>);
+
+impl Duplicates {
+    /// Whether the content was relayed into the target within the window, if
+    /// not it counts as relayed from now on. Messages without content other
+    /// than mentions are never duplicates.
+    pub fn check(
+        &self,
+        target: ChannelId,
+        content: &str,
+        source: &str,
+        settings: Settings,
+    ) -> Verdict {
+        let normalized = normalize(content);
+        if normalized.is_empty() {
+            return Verdict::Relay;
+        }
+        let hash = hash_normalized(&normalized);
+        let mut map = self.0.lock().unwrap();
+        let recent = map.entry(target).or_default();
+        recent.retain(|r| r.at.elapsed() < settings.window);
+
+        match recent.iter_mut().find(|r| r.hash == hash) {
+            Some(relayed) => {
+                let new_source =
+                    relayed.source != source && !relayed.also.iter().any(|s| s == source);
+                if new_source {
+                    relayed.also.push(source.to_owned());
+                }
+                Verdict::Duplicate(match settings.annotate && new_source {
+                    true => annotation(relayed),
+                    false => None,
+                })
+            }
+            None => {
+                recent.push_back(Relayed {
+                    hash,
+                    at: Instant::now(),
+                    source: source.to_owned(),
+                    posted: None,
+                    also: Vec::new(),
+                });
+                Verdict::Relay
+            }
+        }
+    }
+
+    /// Drop the content that was checked but not posted after all, so the
+    /// next copy of it is relayed.
+    pub fn forget(&self, target: ChannelId, content: &str) {
+        let hash = content_hash(content);
+        let mut map = self.0.lock().unwrap();
+        if let Some(recent) = map.get_mut(&target) {
+            recent.retain(|r| r.hash != hash || r.posted.is_some());
+        }
+    }
+
+    /// Remember where the copy of the content was posted. Returns the
+    /// annotation for duplicates that came in while it was being sent.
+    pub fn posted(
+        &self,
+        target: ChannelId,
+        content: &str,
+        webhook: WebhookId,
+        posted: Posted,
+        first: OutgoingMessage,
+        settings: Settings,
+    ) -> Option<Annotation> {
+        let hash = content_hash(content);
+        let mut map = self.0.lock().unwrap();
+        let relayed = map.get_mut(&target)?.iter_mut().find(|r| r.hash == hash)?;
+        relayed.posted = Some((webhook, posted, first));
+        match settings.annotate {
+            true => annotation(relayed),
+            false => None,
+        }
+    }
+}
@@ -0,0 +1,305 @@
+use anyhow::{anyhow, bail, Context, Error, Result};
+use futures::TryFutureExt;
+use serenity::{
+    builder::CreateEmbed,
+    model::{
+        channel::Message,
+        id::{ChannelId, WebhookId},
+    },
+    utils::Color,
+};
+use sqlx::SqlitePool;
+
+use crate::{
+    output::{self, OutgoingMessage, EMBED_DESCRIPTION_LIMIT},
+    ratelimit::{self, RateLimiter},
+    thread, transform,
+    transport::{Author, DiscordTransport, WebhookSender},
+};
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+// How often the background task looks for digests that are due.
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+// Maximum number of characters of a message shown in the digest.
+const EXCERPT_LEN: usize = 200;
+
+const HOUR: i64 = 60 * 60;
+const DAY: i64 = 24 * HOUR;
+
+pub enum DigestSchedule {
+    Hourly,
+    Daily { hour: i64 },
+}
+
+impl DigestSchedule {
+    pub fn parse(kind: &str, hour: Option<i64>) -> Result<Self> {
+        match kind {
+            "hourly" if hour.is_some() => bail!("A digest hour can only be set for daily digests"),
+            "hourly" => Ok(DigestSchedule::Hourly),
+            "daily" => {
+                let hour = hour.unwrap_or(0);
+                if !(0..24).contains(&hour) {
+                    bail!("Digest hour has to be between 0 and 23 (UTC), got: {hour}");
+                }
+                Ok(DigestSchedule::Daily { hour })
+            }
+            s => Err(anyhow!("Unknown digest schedule: {s}")),
+        }
+    }
+
+    pub fn interval(&self) -> i64 {
+        match self {
+            DigestSchedule::Hourly => HOUR,
+            DigestSchedule::Daily { .. } => DAY,
+        }
+    }
+
+    /// Unix timestamp of the first digest after `now`.
+    pub fn first_due(&self, now: i64) -> i64 {
+        match self {
+            DigestSchedule::Hourly => (now / HOUR + 1) * HOUR,
+            DigestSchedule::Daily { hour } => {
+                let due = (now / DAY) * DAY + hour * HOUR;
+                if due <= now {
+                    due + DAY
+                } else {
+                    due
+                }
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for DigestSchedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DigestSchedule::Hourly => write!(f, "Hourly"),
+            DigestSchedule::Daily { hour } => write!(f, "Daily at {hour:02}:00 UTC"),
+        }
+    }
+}
+
+pub fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+pub async fn buffer_message(db: &SqlitePool, connection: i64, msg: &Message) -> Result<()> {
+    let content = transform::transform(db, msg, Some(connection)).await?;
+    let link = msg.link();
+    let timestamp = msg.timestamp.timestamp();
+    sqlx::query!(
+        "INSERT INTO DigestBuffer (connection, author, content, link, timestamp) VALUES (?, ?, ?, ?, ?)",
+        connection,
+        msg.author.name,
+        content,
+        link,
+        timestamp
+    )
+    .execute(db)
+    .await
+    .map_err(|e| Error::new(e).context("Failed to buffer message for digest in the database"))?;
+
+    Ok(())
+}
+
+struct DigestEntry {
+    id: i64,
+    author: String,
+    content: String,
+    link: String,
+    timestamp: i64,
+}
+
+impl From<&DigestEntry> for String {
+    fn from(e: &DigestEntry) -> Self {
+        let mut excerpt: String = e.content.chars().take(EXCERPT_LEN).collect();
+        if excerpt.len() < e.content.len() {
+            excerpt.push('…');
+        }
+        let excerpt = excerpt.replace('\n', " ");
+        format!(
+            "**{}** <t:{}:f> [Jump]({})\n> {}",
+            e.author, e.timestamp, e.link, excerpt
+        )
+    }
+}
+
+/// Split the digest lines into embed descriptions that fit within Discord's
+/// limits, along with the id of the last entry on each page. Entries aren't
+/// split over pages so the buffer can be cleared page by page.
+fn digest_pages(entries: &[DigestEntry]) -> Vec<(String, i64)> {
+    let mut pages: Vec<(String, i64)> = Vec::new();
+    for entry in entries {
+        let line = output::truncate(&String::from(entry), EMBED_DESCRIPTION_LIMIT);
+        match pages.last_mut() {
+            Some((page, last))
+                if page.chars().count() + 2 + line.chars().count() <= EMBED_DESCRIPTION_LIMIT =>
+            {
+                page.push_str("\n\n");
+                page.push_str(&line);
+                *last = entry.id;
+            }
+            _ => pages.push((line, entry.id)),
+        }
+    }
+    pages
+}
+
+struct DueDigest {
+    id: i64,
+    webhook: i64,
+    target: i64,
+    thread: Option<i64>,
+    publish: bool,
+    digest_interval: i64,
+    next_digest: i64,
+}
+
+async fn post_digest(
+    db: &SqlitePool,
+    sender: &dyn WebhookSender,
+    limiter: &RateLimiter,
+    due: &DueDigest,
+) -> Result<()> {
+    let connection = due.id;
+    let entries: Vec<DigestEntry> = sqlx::query_as!(
+        DigestEntry,
+        "
+        SELECT id, author, content, link, timestamp\n\
+        FROM DigestBuffer\n\
+        WHERE connection = ?\n\
+        ORDER BY timestamp, id
+        ",
+        connection
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| Error::new(e).context("Failed to retrieve digest buffer from the database"))?;
+
+    if entries.is_empty() {
+        return Ok(());
+    }
+
+    let webhook = WebhookId(due.webhook as u64);
+    let limits = ratelimit::get_limits(db, connection).await?;
+    let author = Author {
+        name: "Analyst Bot Digest".to_owned(),
+        avatar_url: String::new(),
+    };
+    let target = ChannelId(due.target as u64);
+    let thread = due.thread.map(|t| ChannelId(t as u64));
+    let title = format!("Digest ({} messages)", entries.len());
+    let mut destination = thread::channel_destination(db, &target, thread, title).await?;
+    let publish = due.publish && thread::publishable(db, &target, &destination).await?;
+    let pages = digest_pages(&entries);
+    let count = pages.len();
+    for (i, (page, last_id)) in pages.into_iter().enumerate() {
+        let title = match count {
+            1 => format!("Digest ({} messages)", entries.len()),
+            _ => format!("Digest ({} messages) [{}/{}]", entries.len(), i + 1, count),
+        };
+        let mut embed = CreateEmbed::default();
+        embed.title(&title).description(&page).color(Color::GOLD);
+        let message = OutgoingMessage {
+            content: String::new(),
+            embed: Some(embed),
+        };
+        limiter.wait(connection, webhook, limits).await;
+        let posted = sender
+            .execute(webhook, &destination, &author, &message)
+            .await
+            .context("Failed to post digest")?;
+        if publish {
+            if let Err(e) = sender.publish(&posted).await {
+                println!("{:?}", e);
+            }
+        }
+        destination = thread::continued(&destination, &posted);
+
+        // Cleared as the pages go out, a failed page and the ones after it
+        // are retried with the next digest.
+        sqlx::query!(
+            "DELETE FROM DigestBuffer WHERE connection = ? AND id <= ?",
+            connection,
+            last_id
+        )
+        .execute(db)
+        .await
+        .map_err(|e| Error::new(e).context("Failed to clear digest buffer in the database"))?;
+    }
+
+    Ok(())
+}
+
+pub async fn post_due_digests(
+    db: &SqlitePool,
+    sender: &dyn WebhookSender,
+    limiter: &RateLimiter,
+) -> Result<()> {
+    let now = unix_now();
+    let due = sqlx::query_as!(
+        DueDigest,
+        "
+        SELECT\n\
+        id,\n\
+        webhook,\n\
+        target,\n\
+        thread,\n\
+        publish as \"publish: bool\",\n\
+        digest_interval as \"digest_interval!: i64\",\n\
+        next_digest as \"next_digest!: i64\"\n\
+        FROM Connections\n\
+        WHERE digest_interval IS NOT NULL AND next_digest <= ?
+        ",
+        now
+    )
+    .fetch_all(db)
+    .map_err(|e| Error::new(e).context("Failed to retrieve due digests from the database"))
+    .await?;
+
+    for row in due {
+        // What failed to post stays in the buffer and goes out with the next digest.
+        if let Err(e) = post_digest(db, sender, limiter, &row).await {
+            println!("{:?}", e);
+        }
+
+        let interval = clamp_interval(row.digest_interval);
+        let next = row.next_digest + interval * ((now - row.next_digest) / interval + 1);
+        sqlx::query!(
+            "UPDATE Connections SET next_digest = ? WHERE id = ?",
+            next,
+            row.id
+        )
+        .execute(db)
+        .await
+        .map_err(|e| Error::new(e).context("Failed to schedule next digest in the database"))?;
+    }
+
+    Ok(())
+}
+
+fn clamp_interval(interval: i64) -> i64 {
+    // Guard against a corrupt row spinning the scheduler.
+    std::cmp::max(interval, HOUR)
+}
+
+pub async fn run_digest_task(
+    db: SqlitePool,
+    transport: Arc<DiscordTransport>,
+    limiter: RateLimiter,
+) {
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = post_due_digests(&db, transport.as_ref(), &limiter).await {
+            println!("{:?}", e);
+        }
+    }
+}
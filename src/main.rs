@@ -1,53 +1,114 @@
 #![feature(hash_drain_filter)]
 #![feature(io_error_other)]
 
-use anyhow::{anyhow, bail, Context, Error, Result};
+mod alert;
+mod archive;
+mod backfill;
+mod commands;
+mod condition;
+mod confirm;
+mod dedup;
+mod digest;
+mod output;
+mod ratelimit;
+mod reaction;
+mod registration;
+mod relay;
+mod scoreboard;
+mod search;
+mod template;
+#[cfg(test)]
+mod tests;
+mod thread;
+mod ticker;
+mod transform;
+mod transport;
+mod trash;
+
+use alert::AlertLimiter;
+use anyhow::{anyhow, Context, Error, Result};
+use archive::{Delivery, Retention};
+use backfill::Backfill;
+use commands::{opt, options::get_bool_opt, CommandContext, CommandTiming, Registry};
+use condition::{MentionCondition, Subject};
+use confirm::{Choice, Confirmations, PendingAction};
 use console::style;
-use futures::TryFutureExt;
+use dedup::{Annotation, Duplicates, Verdict};
+use digest::DigestSchedule;
+use futures::{Future, TryFutureExt};
+use once_cell::sync::OnceCell;
+use output::{OutgoingMessage, Paginator, CONTENT_LIMIT, EMBED_DESCRIPTION_LIMIT};
+use ratelimit::{Admission, Limits, Overflow, Pacing, Queued, RateLimiter};
+use reaction::PendingMirrors;
 use regex::Regex;
+use registration::CommandScope;
+use relay::{Relays, Selection};
 use serenity::{
     async_trait,
+    builder::{
+        CreateApplicationCommands, CreateComponents, CreateEmbed, CreateInteractionResponse,
+        CreateInteractionResponseFollowup, EditInteractionResponse,
+    },
     client::Context as ClientContext, // Alias to avoid name collision with anyhow::Context
     model::{
-        channel::{ChannelType, Embed, GuildChannel, Message, PartialChannel},
+        channel::{GuildChannel, Message, PartialGuildChannel, Reaction},
+        event::ThreadListSyncEvent,
         gateway::Ready,
-        id::{ChannelId, GuildId, UserId, WebhookId},
+        id::{ChannelId, GuildId, MessageId, UserId, WebhookId},
         interactions::{
-            application_command::{
-                ApplicationCommandInteraction, ApplicationCommandInteractionDataOption,
-                ApplicationCommandInteractionDataOptionValue, ApplicationCommandOptionType,
-            },
-            autocomplete::AutocompleteInteraction,
-            Interaction, InteractionResponseType,
+            application_command::ApplicationCommandInteraction,
+            autocomplete::AutocompleteInteraction, message_component::MessageComponentInteraction,
+            Interaction, InteractionApplicationCommandCallbackDataFlags, InteractionResponseType,
         },
-        webhook::Webhook,
     },
     prelude::*,
     utils::Color,
 };
 use sqlx::SqlitePool;
-use std::{
-    cmp,
-    collections::HashMap,
-    fmt::Display,
+use std::{fmt::Display, sync::Arc, time::Duration};
+use template::{Placeholders, RenderedMessage, Template};
+use thread::{Thread, ThreadLocks};
+use transport::{
+    Author, ChannelResolver, CommandResponder, ComponentResponder, Destination, DirectMessenger,
+    DiscordTransport, InteractionResponder, Posted, WebhookSender,
 };
-use sublime_fuzzy::best_match;
 
+#[derive(Default)]
 struct CommandResponse {
     title: String,
     msg: String,
+    preview: Option<RenderedMessage>,
+    /// Only performed once the user presses Confirm.
+    confirm: Option<PendingAction>,
+    /// Visible to everyone in the channel instead of only the user.
+    public: bool,
+    /// Shown instead of the page and confirm buttons, e.g. select menus.
+    components: Option<CreateComponents>,
+    error: bool,
+}
+
+impl CommandResponse {
+    fn error(msg: impl Display) -> Self {
+        CommandResponse {
+            title: "Error".to_owned(),
+            msg: msg.to_string(),
+            error: true,
+            ..Default::default()
+        }
+    }
 }
 
 struct AutocompleteResponse {
     options: Vec<String>,
 }
 
-async fn create_server_mapping(db: &SqlitePool, ctx: &ClientContext, id: &GuildId) -> Result<()> {
+async fn create_server_mapping(
+    db: &SqlitePool,
+    resolver: &dyn ChannelResolver,
+    id: &GuildId,
+) -> Result<()> {
     let guild = id.0 as i64;
-    let name = id
-        .name(&ctx)
-        .await
-        .context(format!("Failed to get name from guild id: {guild}"))?;
+    let name = resolver.guild_name(*id).await?;
 
     sqlx::query!(
         "INSERT INTO Guilds (id, name, is_banned) VALUES (?, ?, false)",
@@ -58,38 +119,27 @@ async fn create_server_mapping(db: &SqlitePool, ctx: &ClientContext, id: &GuildI
     .await
     .map_err(|_e| anyhow!("Guild already exists in the database: {name}"))?;
 
-    let channels: Vec<(ChannelId, GuildChannel)> =
-        id.channels(&ctx).await.unwrap().into_iter().collect();
-
-    for (ch_id, ch) in channels {
-        if ch.kind == ChannelType::Text {
-            let webhook = ch.create_webhook(&ctx, "Analyst Bot").await.unwrap().id.0 as i64;
-            let channel = ch_id.0 as i64;
-            let name = format!("#{}", ch.name);
-            sqlx::query!(
-                "INSERT INTO Channels (id, name, guild, webhook) VALUES (?, ?, ?, ?)",
-                channel,
-                name,
-                guild,
-                webhook
-            )
-            .execute(db)
-            .await
-            .unwrap();
-        }
+    for (ch_id, ch_name, kind) in resolver.channels(*id).await? {
+        let webhook = resolver.create_webhook(ch_id).await?.0 as i64;
+        let channel = ch_id.0 as i64;
+        let name = format!("#{}", ch_name);
+        let kind = kind.as_str();
+        sqlx::query!(
+            "INSERT INTO Channels (id, name, guild, webhook, kind) VALUES (?, ?, ?, ?, ?)",
+            channel,
+            name,
+            guild,
+            webhook,
+            kind
+        )
+        .execute(db)
+        .await
+        .map_err(|e| Error::new(e).context("Failed to insert channel into the database"))?;
     }
 
     Ok(())
 }
 
-async fn get_guild_names(db: &SqlitePool) -> Result<Vec<String>> {
-    sqlx::query!("SELECT Guilds.name FROM Guilds")
-        .fetch_all(db)
-        .and_then(|result| async { Ok(result.into_iter().map(|record| record.name).collect()) })
-        .await
-        .map_err(|e| anyhow!(e).context("Failed to retrieve guild names from the database"))
-}
-
 // async fn get_guild_ids(db: &SqlitePool) -> Vec<GuildId> {
 //     sqlx::query!("SELECT Guilds.id FROM Guilds")
 //         .fetch_all(db)
@@ -100,24 +150,78 @@ async fn get_guild_names(db: &SqlitePool) -> Result<Vec<String>> {
 //         .collect()
 // }
 
-async fn get_channel_names(server_name: &String, db: &SqlitePool) -> Result<Vec<String>> {
-    sqlx::query!(
-        "
-        SELECT Channels.name\n\
-        FROM Channels\n\
-        JOIN Guilds\n\
-        ON Guilds.name = ? AND Channels.guild = Guilds.id",
-        server_name
-    )
-    .fetch_all(db)
-    .and_then(|records| async { Ok(records.into_iter().map(|record| record.name).collect()) })
-    .await
-    .map_err(|e| anyhow!(e).context("Failed to retrieve channel names from database"))
+/// The copies are edited once per burst of reactions on the source message.
+fn mirror_reactions(
+    db: &SqlitePool,
+    transport: &Arc<DiscordTransport>,
+    state: &RelayState,
+    channel: ChannelId,
+    message: MessageId,
+) {
+    if !state.mirrors.schedule(message) {
+        return;
+    }
+    let (db, transport, state) = (db.clone(), transport.clone(), state.clone());
+    tokio::spawn(async move {
+        tokio::time::sleep(reaction::MIRROR_DELAY).await;
+        state.mirrors.start(message);
+        let (sender, limiter) = (transport.as_ref(), &state.limits);
+        if let Err(e) = reaction::mirror(&db, sender, sender, limiter, channel, message).await {
+            println!("{:?}", e);
+        }
+    });
+}
+
+/// serenity 0.10 calls the parent of a thread its category.
+async fn save_thread_event(db: &SqlitePool, channel: &GuildChannel) {
+    if let Some(parent) = channel.category_id {
+        let thread = Thread {
+            id: channel.id,
+            name: channel.name.clone(),
+            parent,
+        };
+        if let Err(e) = thread::save_thread(db, &thread).await {
+            println!("{:?}", e);
+        }
+    }
+}
+
+/// State shared by every relayed message.
+#[derive(Clone, Default)]
+struct RelayState {
+    alerts: AlertLimiter,
+    limits: RateLimiter,
+    duplicates: Duplicates,
+    threads: ThreadLocks,
+    mirrors: PendingMirrors,
+}
+
+/// What relaying a message over a connection needs.
+struct RelayContext<'a> {
+    db: &'a SqlitePool,
+    sender: &'a dyn WebhookSender,
+    resolver: &'a dyn ChannelResolver,
+    state: &'a RelayState,
 }
 
 struct Handler {
     db: SqlitePool,
+    commands: Registry,
+    pages: Paginator,
+    confirmations: Confirmations,
+    relays: Relays,
+    relay_state: RelayState,
+    command_scope: CommandScope,
     cache_rdy_tx: tokio::sync::mpsc::Sender<bool>,
+    // One transport for every event, so that webhooks are only looked up once.
+    transport: Arc<OnceCell<Arc<DiscordTransport>>>,
+}
+
+impl Handler {
+    fn transport(&self, ctx: &ClientContext) -> &Arc<DiscordTransport> {
+        self.transport
+            .get_or_init(|| Arc::new(DiscordTransport::new(ctx.http.clone())))
+    }
 }
 
 #[async_trait]
@@ -158,173 +262,20 @@ impl EventHandler for Handler {
 
     async fn cache_ready(&self, ctx: ClientContext, guilds: Vec<GuildId>) {
         println!("Cache is ready");
+        let transport = self.transport(&ctx);
         for id in &guilds {
-            match create_server_mapping(&self.db, &ctx, &id).await {
+            match create_server_mapping(&self.db, transport.as_ref(), &id).await {
                 Ok(_) => (),
                 Err(e) => println!("{:?}", e),
             }
-        }
-        println!("Server mapping created");
-        for id in &guilds {
-            let result = GuildId::set_application_commands(id, &ctx.http, |commands| {
-                commands
-                    .create_application_command(|command| {
-                        command
-                            .name("connect")
-                            .description("Connect a source channel to a target channel")
-                            .create_option(|option| {
-                                option
-                                    .name("source")
-                                    .description("Source channel")
-                                    .kind(ApplicationCommandOptionType::Channel)
-                                    .required(true)
-                                    .channel_types(&[ChannelType::Text])
-                            })
-                            .create_option(|option| {
-                                option
-                                    .name("target_server")
-                                    .description("Target server")
-                                    .kind(ApplicationCommandOptionType::String)
-                                    .required(true)
-                                    .set_autocomplete(true)
-                            })
-                            .create_option(|option| {
-                                option
-                                    .name("target_channel")
-                                    .description("Target channel")
-                                    .kind(ApplicationCommandOptionType::String)
-                                    .required(true)
-                                    .set_autocomplete(true)
-                            })
-                    })
-                    .create_application_command(|command| {
-                        command
-                            .name("disconnect")
-                            .description("Disconnect one target channel from a source channel")
-                            .create_option(|option| {
-                                option
-                                    .name("source")
-                                    .description("Source channel")
-                                    .kind(ApplicationCommandOptionType::Channel)
-                                    .required(true)
-                                    .channel_types(&[ChannelType::Text])
-                            })
-                            .create_option(|option| {
-                                option
-                                    .name("target_channel")
-                                    .description("Target channel")
-                                    .kind(ApplicationCommandOptionType::String)
-                                    .required(true)
-                                    .set_autocomplete(true)
-                            })
-                    })
-                    .create_application_command(|command| {
-                        command
-                            .name("disconnect-all")
-                            .description("Disconnect all target channels from a source channel")
-                            .create_option(|option| {
-                                option
-                                    .name("source")
-                                    .description("Source channel")
-                                    .kind(ApplicationCommandOptionType::Channel)
-                                    .required(true)
-                                    .channel_types(&[ChannelType::Text])
-                            })
-                    })
-                    .create_application_command(|command| {
-                        command
-                            .name("list-connections")
-                            .description("List all the active connections between all servers")
-                    })
-                    .create_application_command(|command| {
-                        command
-                            .name("wipe-connections")
-                            .description(
-                                "[WARNING] Will remove ALL connections to/from the selected server",
-                            )
-                            .create_option(|option| {
-                                option
-                                    .name("server")
-                                    .description("Server name")
-                                    .kind(ApplicationCommandOptionType::String)
-                                    .required(true)
-                                    .set_autocomplete(true)
-                            })
-                    })
-                    .create_application_command(|command| {
-                        command
-                            .name("wipe-mentions")
-                            .description(
-                                "[WARNING] Will remove ALL mentions to/from channels in the selected server",
-                            )
-                            .create_option(|option| {
-                                option
-                                    .name("server")
-                                    .description("Server name")
-                                    .kind(ApplicationCommandOptionType::String)
-                                    .required(true)
-                                    .set_autocomplete(true)
-                            })
-                    })
-                    .create_application_command(|command| {
-                        command
-                            .name("mention-add")
-                            .description("Add mentions to the target channel")
-                            .create_option(|option| {
-                                option
-                                    .name("target_server")
-                                    .description("Target server")
-                                    .kind(ApplicationCommandOptionType::String)
-                                    .required(true)
-                                    .set_autocomplete(true)
-                            })
-                            .create_option(|option| {
-                                option
-                                    .name("target_channel")
-                                    .description("Target channel")
-                                    .kind(ApplicationCommandOptionType::String)
-                                    .required(true)
-                                    .set_autocomplete(true)
-                            })
-                            .create_option(|option| {
-                                option
-                                    .name("mentions")
-                                    .description("One or more mentions separated by whitespace")
-                                    .kind(ApplicationCommandOptionType::String)
-                                    .required(true)
-                            })
-                            .create_option(|option| {
-                                option
-                                    .name("source")
-                                    .description(
-                                        "If set then only messages from this channel are mentioned",
-                                    )
-                                    .kind(ApplicationCommandOptionType::Channel)
-                                    .required(false)
-                                    .channel_types(&[ChannelType::Text])
-                            })
-                    })
-                    .create_application_command(|command| {
-                        command
-                            .name("list-mentions")
-                            .description("List all mentions for channels in the target server")
-                            .create_option(|option| {
-                                option
-                                    .name("target_server")
-                                    .description("Target server")
-                                    .kind(ApplicationCommandOptionType::String)
-                                    .required(true)
-                                    //.set_autocomplete(true)
-                            })
-                    })
-            })
-            .await;
-            let guild_name = id.name(&ctx).await.unwrap();
-            match result {
-                Ok(_) => println!("Successfully installed slash commands in {guild_name}"),
-                Err(why) => println!("Failed to install slash commands in {guild_name}: {why}"),
+            if let Err(e) = thread::sync_threads(&self.db, transport.as_ref(), *id).await {
+                println!("{:?}", e);
             }
         }
+        println!("Server mapping created");
+        let mut commands = CreateApplicationCommands::default();
+        self.commands.create_commands(&mut commands);
+        registration::register_commands(&ctx.http, self.command_scope, &guilds, &commands.0).await;
         println!("Slash commands added");
         self.cache_rdy_tx
             .send(true)
@@ -333,38 +284,148 @@ impl EventHandler for Handler {
     }
 
     async fn message(&self, ctx: ClientContext, msg: Message) {
-        match handle_message(&self.db, &ctx, &msg).await {
+        let transport = self.transport(&ctx).clone();
+        let (db, alerts, alerted) = (
+            self.db.clone(),
+            self.relay_state.alerts.clone(),
+            msg.clone(),
+        );
+        tokio::spawn(async move {
+            if let Err(e) = send_alerts(&db, transport.as_ref(), &alerts, &alerted).await {
+                println!("{:?}", e);
+            }
+        });
+
+        let transport = self.transport(&ctx).as_ref();
+        let state = &self.relay_state;
+        match handle_message(&self.db, transport, transport, state, &msg).await {
             Ok(_) => (),
             Err(e) => println!("{:?}", e),
         }
     }
 
+    // Reactions on a source message are mirrored, those on a relayed copy
+    // count for the scoreboard of its author.
+    async fn reaction_add(&self, ctx: ClientContext, reaction: Reaction) {
+        let counted = scoreboard::count_reaction(&self.db, &reaction.message_id, 1).await;
+        if let Err(e) = counted {
+            println!("{:?}", e);
+        }
+        mirror_reactions(
+            &self.db,
+            self.transport(&ctx),
+            &self.relay_state,
+            reaction.channel_id,
+            reaction.message_id,
+        )
+    }
+
+    async fn reaction_remove(&self, ctx: ClientContext, reaction: Reaction) {
+        let counted = scoreboard::count_reaction(&self.db, &reaction.message_id, -1).await;
+        if let Err(e) = counted {
+            println!("{:?}", e);
+        }
+        mirror_reactions(
+            &self.db,
+            self.transport(&ctx),
+            &self.relay_state,
+            reaction.channel_id,
+            reaction.message_id,
+        )
+    }
+
+    async fn reaction_remove_all(
+        &self,
+        ctx: ClientContext,
+        channel: ChannelId,
+        message: MessageId,
+    ) {
+        if let Err(e) = scoreboard::clear_reactions(&self.db, &message).await {
+            println!("{:?}", e);
+        }
+        mirror_reactions(
+            &self.db,
+            self.transport(&ctx),
+            &self.relay_state,
+            channel,
+            message,
+        )
+    }
+
+    async fn thread_create(&self, _ctx: ClientContext, thread: GuildChannel) {
+        save_thread_event(&self.db, &thread).await
+    }
+
+    async fn thread_update(&self, _ctx: ClientContext, thread: GuildChannel) {
+        save_thread_event(&self.db, &thread).await
+    }
+
+    async fn thread_delete(&self, _ctx: ClientContext, thread: PartialGuildChannel) {
+        if let Err(e) = thread::delete_thread(&self.db, &thread.id).await {
+            println!("{:?}", e);
+        }
+    }
+
+    async fn thread_list_sync(&self, _ctx: ClientContext, sync: ThreadListSyncEvent) {
+        for thread in &sync.threads {
+            save_thread_event(&self.db, thread).await
+        }
+    }
+
     async fn interaction_create(&self, ctx: ClientContext, interaction: Interaction) {
         match interaction {
             Interaction::ApplicationCommand(command) => {
-                handle_application_command(&self.db, &command, &ctx).await
+                let cx = CommandContext {
+                    db: &self.db,
+                    ctx: &ctx,
+                    relays: &self.relays,
+                    transport: self.transport(&ctx),
+                    state: &self.relay_state,
+                };
+                handle_application_command(
+                    &cx,
+                    &self.commands,
+                    &self.pages,
+                    &self.confirmations,
+                    &command,
+                )
+                .await
             }
             Interaction::Autocomplete(autocomplete) => {
-                handle_autocomplete(&self.db, &autocomplete, &ctx).await
+                handle_autocomplete(&self.db, &self.commands, &autocomplete, &ctx).await
+            }
+            Interaction::MessageComponent(component) => {
+                handle_message_component(
+                    &self.db,
+                    &self.pages,
+                    &self.confirmations,
+                    &self.relays,
+                    self.transport(&ctx),
+                    &component,
+                    &ctx,
+                )
+                .await
             }
             _ => println!("Received unknown interaction:\n{:#?}", interaction),
         }
     }
 }
 
-async fn get_mentions(
+/// Mention rules of the user for the target channel that apply to messages
+/// from the source channel, with their condition (if any).
+async fn get_mention_rules(
     db: &SqlitePool,
     target: &ChannelId,
     source: &ChannelId,
     user: &UserId,
-) -> Result<Vec<String>> {
+) -> Result<Vec<(String, Option<MentionCondition>)>> {
     let target = target.0 as i64;
     let source = source.0 as i64;
     let user = user.0 as i64;
 
-    let mut mentions: Vec<String> = sqlx::query!(
+    let rows = sqlx::query!(
         "
-        SELECT mention\n\
+        SELECT mention, condition_kind, condition\n\
         FROM Mentions\n\
         WHERE (source IS NULL AND target = ? AND user = ?) OR (source = ? AND target = ? AND user = ?)
         ",
@@ -375,456 +436,780 @@ async fn get_mentions(
         user
     )
     .fetch_all(db)
-    .and_then(|rows| async move { Ok(rows.into_iter().map(|row| row.mention).collect()) })
     .map_err(|e| Error::new(e).context("Failed to retrieve mentions (no source) from database"))
     .await?;
 
+    let mut rules = Vec::new();
+    for row in rows {
+        match MentionCondition::from_row(row.condition_kind.as_deref(), row.condition.as_deref()) {
+            Ok(condition) => rules.push((row.mention, condition)),
+            Err(e) => println!(
+                "Skipping mention {} with a broken condition: {:?}",
+                row.mention, e
+            ),
+        }
+    }
+
+    Ok(rules)
+}
+
+/// The mentions attached to a message relayed from the source to the target,
+/// conditional rules only count when the message matches them.
+async fn get_mentions(
+    db: &SqlitePool,
+    target: &ChannelId,
+    source: &ChannelId,
+    user: &UserId,
+    subject: &Subject,
+) -> Result<Vec<String>> {
+    let mut mentions: Vec<String> = get_mention_rules(db, target, source, user)
+        .await?
+        .into_iter()
+        .filter(|(_, condition)| !matches!(condition, Some(c) if !c.matches(subject)))
+        .map(|(mention, _)| mention)
+        .collect();
+
     mentions.sort_unstable();
     mentions.dedup();
 
     Ok(mentions)
 }
 
-async fn handle_message(db: &SqlitePool, ctx: &ClientContext, msg: &Message) -> Result<()> {
-    if msg.author.bot == true {
-        return Ok(());
-    }
-    let source = msg.channel_id.0 as i64;
-    let user = msg.author.id.0 as i64;
-    let webhook_ids: Vec<WebhookId> = sqlx::query!(
-        "
-        SELECT webhook as \"webhook_id: i64\"\n\
-        FROM Connections\n\
-        WHERE Connections.source = ? AND Connections.user = ?
-        ",
-        source,
-        user,
-    )
-    .fetch_all(db)
-    .and_then(|rows| async move {
-        Ok(rows
-            .into_iter()
-            .map(|row| WebhookId(row.webhook_id as u64))
-            .collect())
-    })
-    .map_err(|e| Error::new(e).context("Failed to retrieve webhook ids from database"))
-    .await?;
-
-    for id in webhook_ids {
-        let webhook = id
-            .to_webhook(&ctx)
-            .await
-            .context(format!("Failed to retrieve webhook from Discord: {id}"))?;
-        let target = &webhook.channel_id;
-        let source = &msg.channel_id;
-        let mentions = get_mentions(db, target, source, &msg.author.id).await?;
-        match execute_webhook(&webhook, ctx, msg, &mentions).await {
-            Err(e) => println!("{:?}", e),
-            _ => (),
-        }
-    }
-
-    Ok(())
+/// A connection as it is needed to relay a message over it.
+struct RelayConnection {
+    id: i64,
+    webhook: i64,
+    target: i64,
+    thread: Option<i64>,
+    publish: bool,
+    digest_interval: Option<i64>,
+    rate_limit: Option<i64>,
+    overflow: String,
+    webhook_rate_limit: Option<i64>,
 }
 
-async fn execute_webhook(
-    webhook: &Webhook,
-    ctx: &ClientContext,
-    msg: &Message,
-    mentions: &Vec<String>,
-) -> Result<()> {
-    let avatar_url = match msg.author.avatar_url() {
-        Some(url) => url,
-        None => "".to_owned(),
-    };
-    // webhook
-    //     .edit(
-    //         &ctx,
-    //         Some(&msg.author.name),
-    //         Some(&image),
-    //     )
-    //     .await
-    //     .context(format!("Failed to edit webhook:\n{:#?}", webhook))?;
-    webhook
-        .execute(&ctx, false, |w| {
-            let embed = Embed::fake(|e| {
-                e /*.author(|a| a.name(username).url(user_url).icon_url(icon_url))*/
-                    .description(&msg.content)
-                    .color(Color::GOLD)
-            });
-            w.username(&msg.author.name)
-                .avatar_url(&avatar_url)
-                .embeds(vec![embed])
-                .content(mentions.join("\n"))
-        })
-        .await
-        .context(format!("Failed to execute webhook:\n{:#?}", webhook))?;
-    Ok(())
+/// Where a relayed message comes from.
+struct MessageSource {
+    /// The channel whose connections relay the message, the parent of a thread.
+    id: ChannelId,
+    thread: Option<Thread>,
+    guild: String,
+    channel: String,
+    /// e.g. "**Server** #channel"
+    label: String,
 }
 
-async fn send_empty_response(autocomplete: &AutocompleteInteraction, ctx: &ClientContext) {
-    autocomplete
-        .create_autocomplete_response(&ctx, move |rsp| rsp)
-        .await
-        .unwrap()
+async fn get_relay_connection(db: &SqlitePool, id: i64) -> Result<RelayConnection> {
+    sqlx::query_as!(
+        RelayConnection,
+        "
+        SELECT\n\
+        id,\n\
+        webhook,\n\
+        target,\n\
+        thread,\n\
+        publish as \"publish: bool\",\n\
+        digest_interval,\n\
+        rate_limit,\n\
+        overflow,\n\
+        (SELECT rate_limit FROM Channels WHERE Channels.id = target)\n\
+        as \"webhook_rate_limit: i64\"\n\
+        FROM Connections\n\
+        WHERE Connections.id = ?
+        ",
+        id
+    )
+    .fetch_one(db)
+    .map_err(|e| Error::new(e).context("Failed to retrieve connection from the database"))
+    .await
 }
 
-async fn disconnect_target_channel_autocomplete(
+async fn get_relay_connections(
     db: &SqlitePool,
-    source_channel: &ApplicationCommandInteractionDataOption,
-    target_channel: &ApplicationCommandInteractionDataOption,
-) -> Result<AutocompleteResponse> {
-    let target_channel = match &target_channel.value {
-        Some(serde_json::Value::String(input)) => input.clone(),
-        Some(val) => bail!("Expected option to be of type string:\n{:#?}", val),
-        None => bail!("Did not find option \"target_channel\""),
-    };
-
-    let source_channel: i64 = match &source_channel.value {
-        Some(serde_json::Value::String(input)) => input
-            .parse()
-            .context("Failed to parse \"source_channel\"")?,
-        Some(val) => bail!("Expected option to be of type string:\n{:#?}", val),
-        None => bail!("Did not find option \"target_channel\""),
-    };
-
-    let channels: Vec<String> = sqlx::query!(
+    source: &ChannelId,
+    user: &UserId,
+) -> Result<Vec<RelayConnection>> {
+    let source = source.0 as i64;
+    let user = user.0 as i64;
+    sqlx::query_as!(
+        RelayConnection,
         "
         SELECT\n\
-        Guilds.name as guild_name,\n\
-        Channels.name as channel_name\n\
-        FROM Channels\n\
-        JOIN Connections\n\
-        ON Channels.id = Connections.target\n\
-        JOIN Guilds\n\
-        ON Channels.guild = Guilds.id\n\
-        WHERE Connections.source = ?\n\
-        ORDER BY Guilds.name
+        id,\n\
+        webhook,\n\
+        target,\n\
+        thread,\n\
+        publish as \"publish: bool\",\n\
+        digest_interval,\n\
+        rate_limit,\n\
+        overflow,\n\
+        (SELECT rate_limit FROM Channels WHERE Channels.id = target)\n\
+        as \"webhook_rate_limit: i64\"\n\
+        FROM Connections\n\
+        WHERE Connections.source = ? AND Connections.user = ?
         ",
-        source_channel
+        source,
+        user,
     )
     .fetch_all(db)
-    .and_then(|rows| async move {
-        Ok(rows
-            .into_iter()
-            .map(|row| format!("[{}] {}", row.guild_name, row.channel_name))
-            .collect())
-    })
+    .map_err(|e| Error::new(e).context("Failed to retrieve webhook ids from database"))
     .await
-    .context("Failed to retrieve target channel names from the database")?;
-
-    if channels.is_empty() {
-        bail!("No target channels found")
-    }
-
-    // Matching score, lower score is a better match.
-    let mut matching: Vec<(isize, String)> = channels
-        .into_iter()
-        .map(|s| {
-            let score = match best_match(target_channel.as_str(), s.as_str()) {
-                Some(m) => (100 - m.score(), s),
-                None => (100, s),
-            };
-            score
-        })
-        .collect();
-
-    matching.sort();
-    matching.drain(cmp::min(25, matching.len())..);
-
-    Ok(AutocompleteResponse {
-        options: matching.into_iter().map(|(_score, name)| name).collect(),
-    })
 }
 
-async fn connect_target_channel_autocomplete(
+async fn message_source(
     db: &SqlitePool,
-    server_name: &String,
-    opt: &ApplicationCommandInteractionDataOption,
-) -> Result<AutocompleteResponse> {
-    if server_name.trim().is_empty() {
-        bail!("No server name");
-    }
-
-    let channel_name = match &opt.value {
-        Some(serde_json::Value::String(input)) => input.clone(),
-        _ => bail!("Expected option to be of type string:\n{:#?}", opt.value),
+    id: ChannelId,
+    thread: Option<Thread>,
+) -> Result<MessageSource> {
+    let (guild, channel) = template::channel_names(db, &id).await?;
+    let channel = match &thread {
+        Some(thread) => format!("{channel} › {}", thread.name),
+        None => channel,
     };
+    let label = format!("**{guild}** {channel}");
+    Ok(MessageSource {
+        id,
+        thread,
+        guild,
+        channel,
+        label,
+    })
+}
 
-    let channels = get_channel_names(server_name, db).await?;
-
-    // Matching score, lower score is a better match.
-    let mut matching: Vec<(isize, String)> = channels
-        .into_iter()
-        .map(|s| {
-            let score = match best_match(channel_name.as_str(), s.as_str()) {
-                Some(m) => (100 - m.score(), s),
-                None => (100, s),
-            };
-            score
-        })
-        .collect();
-
-    if matching.is_empty() {
-        bail!("No matching channels");
+/// Relay again the messages that were still held back by the rate limits
+/// when the bot stopped.
+async fn restore_queued(cx: &RelayContext<'_>) -> Result<()> {
+    for (id, msg) in ratelimit::take_persisted(cx.db).await? {
+        let connection = match get_relay_connection(cx.db, id).await {
+            Ok(connection) => connection,
+            Err(e) => {
+                println!("{:?}", e);
+                continue;
+            }
+        };
+        let source_thread = thread::get_thread(cx.db, &msg.channel_id).await?;
+        let source_id = source_thread.as_ref().map_or(msg.channel_id, |t| t.parent);
+        let source = message_source(cx.db, source_id, source_thread).await?;
+        relay_to_connection(cx, &source, &msg, &connection, Pacing::Admit).await;
     }
-
-    matching.sort();
-    matching.drain(cmp::min(25, matching.len())..);
-
-    Ok(AutocompleteResponse {
-        options: matching.into_iter().map(|(_score, name)| name).collect(),
-    })
+    Ok(())
 }
 
-async fn connect_target_server_autocomplete(
+async fn handle_message(
     db: &SqlitePool,
-    server_name: &String,
-) -> Result<AutocompleteResponse> {
-    let servers = get_guild_names(db).await?;
-
-    // Matching score, lower score is a better match.
-    let mut matching: Vec<(isize, String)> = servers
-        .into_iter()
-        .map(|s| {
-            let score = match best_match(server_name.as_str(), s.as_str()) {
-                Some(m) => (100 - m.score(), s),
-                None => (100, s),
-            };
-            score
-        })
-        .collect();
-
-    if matching.is_empty() {
-        bail!("No guilds found");
+    sender: &dyn WebhookSender,
+    resolver: &dyn ChannelResolver,
+    state: &RelayState,
+    msg: &Message,
+) -> Result<()> {
+    if msg.author.bot == true {
+        return Ok(());
+    }
+    // Messages in threads and forum posts go through the connections of the
+    // parent channel.
+    let source_thread = thread::get_thread(db, &msg.channel_id).await?;
+    let source_id = source_thread.as_ref().map_or(msg.channel_id, |t| t.parent);
+    let connections = get_relay_connections(db, &source_id, &msg.author.id).await?;
+    if connections.is_empty() {
+        return Ok(());
     }
 
-    matching.sort();
-    matching.drain(cmp::min(25, matching.len())..);
+    let source = message_source(db, source_id, source_thread).await?;
+    let cx = RelayContext {
+        db,
+        sender,
+        resolver,
+        state,
+    };
+    for connection in &connections {
+        relay_to_connection(&cx, &source, msg, connection, Pacing::Admit).await;
+    }
 
-    Ok(AutocompleteResponse {
-        options: matching.into_iter().map(|(_score, name)| name).collect(),
-    })
+    Ok(())
 }
 
-fn find_param<'a>(
-    name: &str,
-    autocomplete: &'a AutocompleteInteraction,
-) -> Result<&'a ApplicationCommandInteractionDataOption> {
-    autocomplete
-        .data
-        .options
-        .iter()
-        .find(|opt| opt.name == name)
-        .ok_or(anyhow!("Did not find autocomplete parameter: {name}"))
+/// A message that can't be relayed over one connection is archived as failed,
+/// the other connections still get it.
+async fn relay_to_connection(
+    cx: &RelayContext<'_>,
+    source: &MessageSource,
+    msg: &Message,
+    connection: &RelayConnection,
+    pacing: Pacing,
+) {
+    if let Err(e) = send_to_connection(cx, source, msg, connection, pacing).await {
+        println!("{:?}", e);
+        let target = ChannelId(connection.target as u64);
+        let error = Some(format!("{:#}", e));
+        archive_message(cx.db, msg, connection.id, &target, Delivery::Failed, error).await;
+    }
 }
 
-async fn handle_autocomplete(
-    db: &SqlitePool,
-    autocomplete: &AutocompleteInteraction,
-    ctx: &ClientContext,
-) {
-    let result: Result<AutocompleteResponse> = match autocomplete.data.name.as_str() {
-        "connect" => handle_connect_autocomplete(db, autocomplete).await,
-        "disconnect" => handle_disconnect_autocomplete(db, autocomplete).await,
-        "wipe-connections" => handle_wipe_connections_autocomplete(db, autocomplete).await,
-        "wipe-mentions" => handle_wipe_mentions_autocomplete(db, autocomplete).await,
-        "mention-add" => handle_mention_add_autocomplete(db, autocomplete).await,
-        s => Err(anyhow!("Unhandled autocomplete:\n{s}")),
-    };
-    match result {
-        Ok(rsp) => autocomplete
-            .create_autocomplete_response(&ctx, move |c| {
-                for name in rsp.options {
-                    c.add_string_choice(name.as_str(), name.as_str());
-                }
-                c
-            })
-            .await
-            .unwrap(),
-        Err(e) => {
+async fn send_to_connection(
+    cx: &RelayContext<'_>,
+    source: &MessageSource,
+    msg: &Message,
+    connection: &RelayConnection,
+    pacing: Pacing,
+) -> Result<()> {
+    let RelayContext {
+        db, sender, state, ..
+    } = *cx;
+    let watchlist = ticker::get_watchlist(db, connection.id).await?;
+    if !ticker::on_watchlist(&ticker::extract(&msg.content), &watchlist) {
+        return Ok(());
+    }
+    if connection.digest_interval.is_some() {
+        digest::buffer_message(db, connection.id, msg).await?;
+        let target = ChannelId(connection.target as u64);
+        archive_message(db, msg, connection.id, &target, Delivery::Buffered, None).await;
+        if let Err(e) = scoreboard::record(db, msg, connection.id).await {
             println!("{:?}", e);
-            send_empty_response(autocomplete, ctx).await;
         }
+        return Ok(());
     }
-}
-
-async fn handle_mention_add_autocomplete(
-    db: &SqlitePool,
-    autocomplete: &AutocompleteInteraction,
-) -> Result<AutocompleteResponse> {
-    let param_target_server = find_param("target_server", &autocomplete)?;
-    let param_target_channel = find_param("target_channel", &autocomplete);
-
-    let server_name = match &param_target_server.value {
-        Some(serde_json::Value::String(input)) => input.clone(),
-        Some(val) => bail!("Unexpected parameter type (expected string):\n{:#?}", val),
-        None => bail!("No parameter value found"),
-    };
-
-    if param_target_server.focused {
-        connect_target_server_autocomplete(db, &server_name).await
-    } else if param_target_channel.is_ok() {
-        let param_target_channel = param_target_channel.unwrap();
-        if param_target_channel.focused {
-            connect_target_channel_autocomplete(db, &server_name, &param_target_channel).await
-        } else {
-            bail!("Target channel not focused")
+    let webhook = WebhookId(connection.webhook as u64);
+    let target = sender.webhook_channel(webhook).await?;
+    let dedup = dedup::get_settings(db, &target).await?;
+    if let Some(settings) = dedup {
+        let verdict = state
+            .duplicates
+            .check(target, &msg.content, &source.label, settings);
+        if let Verdict::Duplicate(annotation) = verdict {
+            annotate_duplicate(db, sender, annotation).await;
+            archive_message(db, msg, connection.id, &target, Delivery::Duplicate, None).await;
+            return Ok(());
         }
-    } else {
-        bail!("Invalid parameter focus")
     }
+    // A message that doesn't go out after all mustn't hold back its duplicates.
+    let result = post_to_connection(cx, source, msg, connection, pacing, target, dedup).await;
+    if dedup.is_some() && !matches!(result, Ok(true)) {
+        state.duplicates.forget(target, &msg.content);
+    }
+    result.map(|_| ())
 }
 
-async fn handle_wipe_connections_autocomplete(
-    db: &SqlitePool,
-    autocomplete: &AutocompleteInteraction,
-) -> Result<AutocompleteResponse> {
-    let param_target_server = find_param("server", &autocomplete)?;
-
-    let server_name = match &param_target_server.value {
-        Some(serde_json::Value::String(input)) => input.clone(),
-        Some(val) => bail!("Unexpected parameter type (expected string):\n{:#?}", val),
-        None => bail!("No parameter value found"),
-    };
-
-    connect_target_server_autocomplete(db, &server_name).await
-}
-
-async fn handle_wipe_mentions_autocomplete(
-    db: &SqlitePool,
-    autocomplete: &AutocompleteInteraction,
-) -> Result<AutocompleteResponse> {
-    let param_target_server = find_param("server", &autocomplete)?;
-
-    let server_name = match &param_target_server.value {
-        Some(serde_json::Value::String(input)) => input.clone(),
-        Some(val) => bail!("Unexpected parameter type (expected string):\n{:#?}", val),
-        None => bail!("No parameter value found"),
-    };
+/// Returns whether the message was posted or queued to be posted.
+async fn post_to_connection(
+    cx: &RelayContext<'_>,
+    source: &MessageSource,
+    msg: &Message,
+    connection: &RelayConnection,
+    pacing: Pacing,
+    target: ChannelId,
+    dedup: Option<dedup::Settings>,
+) -> Result<bool> {
+    let RelayContext {
+        db,
+        sender,
+        resolver,
+        state,
+    } = *cx;
+    let webhook = WebhookId(connection.webhook as u64);
+    let _thread = state
+        .threads
+        .lock(connection.id, source.thread.as_ref())
+        .await;
+    let destination = thread::destination(
+        db,
+        resolver,
+        connection.id,
+        &target,
+        connection.thread.map(|t| ChannelId(t as u64)),
+        source.thread.as_ref(),
+        msg,
+        &source.channel,
+    )
+    .await?;
+    let publish = connection.publish && thread::publishable(db, &target, &destination).await?;
+    let rendered = render_for_connection(
+        db,
+        msg,
+        &source.id,
+        connection.id,
+        &target,
+        &source.guild,
+        &source.channel,
+    )
+    .await?;
+    let limits = Limits {
+        connection: connection.rate_limit,
+        webhook: connection
+            .webhook_rate_limit
+            .unwrap_or(ratelimit::DEFAULT_WEBHOOK_LIMIT),
+    };
+    let admission = match pacing {
+        Pacing::Admit => state.limits.admit(
+            connection.id,
+            webhook,
+            target,
+            limits,
+            Overflow::parse(&connection.overflow)?,
+            source.label.clone(),
+            Queued {
+                message: msg.clone(),
+                rendered: rendered.clone(),
+                destination: destination.clone(),
+                publish,
+                source_thread: source.thread.clone(),
+                dedup,
+            },
+        ),
+        Pacing::Wait => {
+            state.limits.wait(connection.id, webhook, limits).await;
+            Admission::Send
+        }
+    };
+    if let Admission::Queued = admission {
+        if let Err(e) = ratelimit::persist(db, connection.id, msg).await {
+            println!("{:?}", e);
+        }
+    }
+    if let Some(delivery) = admission.delivery() {
+        archive_message(db, msg, connection.id, &target, delivery, None).await;
+        return Ok(matches!(admission, Admission::Queued));
+    }
+    let posted = execute_webhook(sender, webhook, &destination, publish, msg, &rendered).await?;
+    if let Some((posted, _)) = &posted {
+        let thread = source.thread.as_ref();
+        let recorded =
+            thread::record_post(db, connection.id, &destination, thread, posted, &target).await;
+        if let Err(e) = recorded {
+            println!("{:?}", e);
+        }
+    }
+    // Archived first, the note on the copy is kept along with it.
+    archive_delivered(db, msg, connection.id, &target, &posted).await;
+    if let (Some((posted, first)), Some(settings)) = (&posted, dedup) {
+        let annotation = state.duplicates.posted(
+            target,
+            &msg.content,
+            webhook,
+            *posted,
+            first.clone(),
+            settings,
+        );
+        annotate_duplicate(db, sender, annotation).await;
+    }
+    Ok(posted.is_some())
+}
 
-    connect_target_server_autocomplete(db, &server_name).await
+/// Editing is best effort, the duplicate is suppressed either way. The
+/// footer with the reactions on the copy is kept.
+async fn annotate_duplicate(
+    db: &SqlitePool,
+    sender: &dyn WebhookSender,
+    annotation: Option<Annotation>,
+) {
+    if let Some(annotation) = annotation {
+        let Annotation {
+            webhook,
+            posted,
+            original,
+            note,
+        } = annotation;
+        let footer = match archive::set_copy_note(db, &posted, &note).await {
+            Ok(footer) => footer.unwrap_or_default(),
+            Err(e) => {
+                println!("{:?}", e);
+                String::new()
+            }
+        };
+        let content = reaction::compose(&original.content, Some(&note), &footer);
+        if content.chars().count() > CONTENT_LIMIT {
+            return;
+        }
+        let edited = OutgoingMessage {
+            content,
+            embed: original.embed,
+        };
+        if let Err(e) = sender.edit(webhook, &posted, &edited).await {
+            println!("{:?}", e);
+        }
+    }
 }
 
-async fn handle_connect_autocomplete(
+/// DM the users with an alert matching the message, formatted like a relayed
+/// message with the default template. Users don't get alerts for their own
+/// messages.
+/// Sent apart from relaying, slow direct messages don't hold back the
+/// relayed copies.
+async fn send_alerts(
     db: &SqlitePool,
-    autocomplete: &AutocompleteInteraction,
-) -> Result<AutocompleteResponse> {
-    let param_target_server = find_param("target_server", &autocomplete)?;
-    let param_target_channel = find_param("target_channel", &autocomplete);
-
-    let server_name = match &param_target_server.value {
-        Some(serde_json::Value::String(input)) => input.clone(),
-        Some(val) => bail!("Unexpected parameter type (expected string):\n{:#?}", val),
-        None => bail!("No parameter value found"),
+    messenger: &dyn DirectMessenger,
+    alerts: &AlertLimiter,
+    msg: &Message,
+) -> Result<()> {
+    let guild = match msg.guild_id {
+        Some(guild) if !msg.author.bot => guild,
+        _ => return Ok(()),
     };
+    let source_thread = thread::get_thread(db, &msg.channel_id).await?;
+    let source_id = source_thread.as_ref().map_or(msg.channel_id, |t| t.parent);
+    if get_relay_connections(db, &source_id, &msg.author.id)
+        .await?
+        .is_empty()
+    {
+        return Ok(());
+    }
 
-    if param_target_server.focused {
-        connect_target_server_autocomplete(db, &server_name).await
-    } else if param_target_channel.is_ok() {
-        let param_target_channel = param_target_channel.unwrap();
-        if param_target_channel.focused {
-            connect_target_channel_autocomplete(db, &server_name, &param_target_channel).await
-        } else {
-            bail!("Target channel not focused")
+    let subject = Subject::new(&msg.content, msg.author.id);
+    let mut matched: Vec<(UserId, MentionCondition)> = Vec::new();
+    for (user, condition) in alert::source_alerts(db, &source_id, &guild).await? {
+        let first_match = !matched.iter().any(|(u, _)| *u == user);
+        if user != msg.author.id && first_match && condition.matches(&subject) {
+            matched.push((user, condition));
         }
-    } else {
-        bail!("Invalid parameter focus")
     }
+    if matched.is_empty() {
+        return Ok(());
+    }
+
+    let source = message_source(db, source_id, source_thread).await?;
+    let rendered = Template::default().render(&message_placeholders(
+        msg,
+        &source.guild,
+        &source.channel,
+        Vec::new(),
+    ));
+    for (user, condition) in matched {
+        if !alerts.admit(user, &msg.content) {
+            continue;
+        }
+        let header = format!(
+            "Alert `{}`: **{}** posted a message that {}",
+            alert::alert_name(&condition),
+            msg.author.name,
+            condition
+        );
+        if let Err(e) = execute_direct_message(messenger, user, &header, &rendered).await {
+            println!("{:?}", e);
+        }
+    }
+    Ok(())
 }
 
-async fn handle_disconnect_autocomplete(
-    db: &SqlitePool,
-    autocomplete: &AutocompleteInteraction,
-) -> Result<AutocompleteResponse> {
-    let param_source_channel = find_param("source", &autocomplete)?;
-    let param_target_channel = find_param("target_channel", &autocomplete)?;
+async fn execute_direct_message(
+    messenger: &dyn DirectMessenger,
+    user: UserId,
+    header: &str,
+    rendered: &RenderedMessage,
+) -> Result<()> {
+    let mut messages = rendered.messages();
+    if let Some(first) = messages.first_mut() {
+        first.content = header.to_owned();
+    }
+    for message in messages {
+        messenger.send_dm(user, &message).await?;
+    }
+    Ok(())
+}
 
-    if param_target_channel.focused {
-        disconnect_target_channel_autocomplete(db, &param_source_channel, &param_target_channel)
-            .await
-    } else {
-        bail!("Target channel not focused")
+/// Archiving is best effort, a message is relayed even if it can't be recorded.
+async fn archive_message(
+    db: &SqlitePool,
+    msg: &Message,
+    connection: i64,
+    target: &ChannelId,
+    delivery: Delivery,
+    error: Option<String>,
+) {
+    if let Err(e) = archive::record(db, msg, connection, target, delivery, error).await {
+        println!("{:?}", e);
     }
 }
 
-async fn ok_command_response(
-    title: &impl Display,
-    msg: &impl Display,
-    command: &ApplicationCommandInteraction,
-    ctx: &ClientContext,
+/// Archive a delivered message along with the copy it was relayed as.
+async fn archive_delivered(
+    db: &SqlitePool,
+    msg: &Message,
+    connection: i64,
+    target: &ChannelId,
+    posted: &Option<(Posted, OutgoingMessage)>,
 ) {
-    if let Err(why) = command
-        .create_interaction_response(&ctx.http, |response| {
-            response
-                .kind(InteractionResponseType::ChannelMessageWithSource)
-                .interaction_response_data(|message| {
-                    message.create_embed(|e| {
-                        e /*.author(|a| a.name(username).url(user_url).icon_url(icon_url))*/
-                            .color(Color::DARK_GREEN)
-                            .title(title)
-                            .description(msg)
-                    })
-                })
-        })
-        .await
-    {
-        println!("Cannot respond to slash command: {why}");
+    archive_message(db, msg, connection, target, Delivery::Delivered, None).await;
+    if let Some((posted, first)) = posted {
+        if let Err(e) = archive::record_copy(db, msg, connection, posted, &first.content).await {
+            println!("{:?}", e);
+        }
+        if let Err(e) = scoreboard::record(db, msg, connection).await {
+            println!("{:?}", e);
+        }
     }
 }
 
-async fn error_command_response(
-    msg: &impl Display,
-    command: &ApplicationCommandInteraction,
+/// Render a message with the template of the connection and the mentions
+/// of the target channel, after transforming its content.
+async fn render_for_connection(
+    db: &SqlitePool,
+    msg: &Message,
+    source: &ChannelId,
+    connection: i64,
+    target: &ChannelId,
+    source_guild: &str,
+    source_channel: &str,
+) -> Result<RenderedMessage> {
+    let subject = Subject::new(&msg.content, msg.author.id);
+    let mentions = get_mentions(db, target, source, &msg.author.id, &subject).await?;
+    let template = template::get_template(db, connection).await?;
+    let mut placeholders = message_placeholders(msg, source_guild, source_channel, mentions);
+    placeholders.content = transform::transform(db, msg, Some(connection)).await?;
+    Ok(template.render(&placeholders))
+}
+
+fn message_placeholders(
+    msg: &Message,
+    source_guild: &str,
+    source_channel: &str,
+    mentions: Vec<String>,
+) -> Placeholders {
+    Placeholders {
+        author: msg.author.name.clone(),
+        source_channel: source_channel.to_owned(),
+        source_guild: source_guild.to_owned(),
+        content: msg.content.clone(),
+        jump_url: msg.link(),
+        mentions,
+        tickers: ticker::extract(&msg.content)
+            .into_iter()
+            .map(|t| t.symbol)
+            .collect(),
+        timestamp: msg.timestamp,
+    }
+}
+
+/// Returns the first message that was posted and where. Publishing is best
+/// effort, the message was relayed either way.
+async fn execute_webhook(
+    sender: &dyn WebhookSender,
+    webhook: WebhookId,
+    destination: &Destination,
+    publish: bool,
+    msg: &Message,
+    rendered: &RenderedMessage,
+) -> Result<Option<(Posted, OutgoingMessage)>> {
+    let author = Author {
+        name: msg.author.name.clone(),
+        avatar_url: msg.author.avatar_url().unwrap_or_default(),
+    };
+    let mut first: Option<(Posted, OutgoingMessage)> = None;
+    for message in rendered.messages() {
+        let destination = match &first {
+            Some((posted, _)) => thread::continued(destination, posted),
+            None => destination.clone(),
+        };
+        let posted = sender
+            .execute(webhook, &destination, &author, &message)
+            .await?;
+        if publish {
+            if let Err(e) = sender.publish(&posted).await {
+                println!("{:?}", e);
+            }
+        }
+        first.get_or_insert((posted, message));
+    }
+    Ok(first)
+}
+
+async fn send_empty_response(autocomplete: &AutocompleteInteraction, ctx: &ClientContext) {
+    autocomplete
+        .create_autocomplete_response(&ctx, move |rsp| rsp)
+        .await
+        .unwrap()
+}
+
+async fn handle_autocomplete(
+    db: &SqlitePool,
+    commands: &Registry,
+    autocomplete: &AutocompleteInteraction,
     ctx: &ClientContext,
 ) {
-    if let Err(why) = command
-        .create_interaction_response(&ctx.http, |response| {
-            response
-                .kind(InteractionResponseType::ChannelMessageWithSource)
-                .interaction_response_data(|message| {
-                    message.create_embed(|e| e.color(Color::RED).title("Error").description(&msg))
-                })
-        })
-        .await
-    {
-        println!(
-            "Cannot respond to slash command: {}\nError message: {}",
-            why, msg
-        );
+    let result = match commands.get(&autocomplete.data.name) {
+        Some(handler) => handler.autocomplete(db, autocomplete).await,
+        None => Err(anyhow!(
+            "Unhandled autocomplete:\n{}",
+            autocomplete.data.name
+        )),
+    };
+    match result {
+        Ok(rsp) => autocomplete
+            .create_autocomplete_response(&ctx, move |c| {
+                for name in rsp.options {
+                    c.add_string_choice(name.as_str(), name.as_str());
+                }
+                c
+            })
+            .await
+            .unwrap(),
+        Err(e) => {
+            println!("{:?}", e);
+            send_empty_response(autocomplete, ctx).await;
+        }
     }
 }
 
-fn get_channel_opt<'a>(
-    name: &str,
-    options: &'a Vec<ApplicationCommandInteractionDataOption>,
-) -> Result<&'a PartialChannel> {
-    options
-        .iter()
-        .find(|&opt| opt.name == name)
-        .and_then(|op| {
-            op.resolved.as_ref().and_then(|ch| match ch {
-                ApplicationCommandInteractionDataOptionValue::Channel(ch) => Some(ch),
-                _ => None,
-            })
-        })
-        .ok_or(anyhow!("Failed to retrieve channel option: \"{}\"", name))
+/// Everything a command response shows, shared by new, updated and edited responses.
+struct ResponseParts {
+    content: Option<String>,
+    embeds: Vec<CreateEmbed>,
+    components: CreateComponents,
 }
 
-fn get_string_opt<'a>(
-    name: &str,
-    options: &'a Vec<ApplicationCommandInteractionDataOption>,
-) -> Result<&'a String> {
-    options
-        .iter()
-        .find(|&opt| opt.name == name)
-        .and_then(|op| {
-            op.resolved.as_ref().and_then(|ch| match ch {
-                ApplicationCommandInteractionDataOptionValue::String(s) => Some(s),
-                _ => None,
-            })
-        })
-        .ok_or(anyhow!("Failed to retrieve string option: \"{}\"", name))
+/// Render a command response. Every response to a command or button goes
+/// through here so they all look and behave the same.
+///
+/// `rsp.msg` is the page shown, `index` and `count` place it among the pages.
+fn response_parts(rsp: &CommandResponse, key: u64, index: usize, count: usize) -> ResponseParts {
+    let color = match rsp.error {
+        true => Color::RED,
+        false => Color::DARK_GREEN,
+    };
+    let mut embeds = vec![output::page_embed(
+        color, &rsp.title, &rsp.msg, index, count,
+    )];
+
+    // Without buttons the ones of the message being updated are removed.
+    let mut components = CreateComponents::default();
+    if let Some(c) = &rsp.components {
+        components = c.clone();
+    } else if rsp.confirm.is_some() {
+        confirm::confirm_buttons(&mut components, key);
+    } else if count > 1 {
+        output::page_buttons(&mut components, key, index, count);
+    }
+
+    let content = rsp.preview.as_ref().map(|preview| {
+        if let Some(embed) = preview.embeds.first() {
+            embeds.push(embed.clone());
+        }
+        let content = match &preview.followup {
+            Some(followup) => format!("{}\n{}", preview.content, followup),
+            None => preview.content.clone(),
+        };
+        output::truncate(&content, CONTENT_LIMIT)
+    });
+
+    ResponseParts {
+        content,
+        embeds,
+        components,
+    }
+}
+
+fn build_response<'a>(
+    response: &'a mut CreateInteractionResponse,
+    kind: InteractionResponseType,
+    rsp: &CommandResponse,
+    key: u64,
+    index: usize,
+    count: usize,
+) -> &'a mut CreateInteractionResponse {
+    // A deferred response only decides the visibility of the one that follows.
+    if matches!(
+        kind,
+        InteractionResponseType::DeferredChannelMessageWithSource
+            | InteractionResponseType::DeferredUpdateMessage
+    ) {
+        return response.kind(kind).interaction_response_data(|message| {
+            if kind == InteractionResponseType::DeferredChannelMessageWithSource && !rsp.public {
+                message.flags(InteractionApplicationCommandCallbackDataFlags::EPHEMERAL);
+            }
+            message
+        });
+    }
+    let parts = response_parts(rsp, key, index, count);
+    response.kind(kind).interaction_response_data(|message| {
+        message
+            .embeds(parts.embeds)
+            .set_components(parts.components);
+        if let Some(content) = parts.content {
+            // The preview must not ping anyone.
+            message
+                .content(content)
+                .allowed_mentions(|m| m.empty_parse());
+        }
+        // Visibility is decided when the message is created, updates keep it.
+        if kind == InteractionResponseType::ChannelMessageWithSource && !rsp.public {
+            message.flags(InteractionApplicationCommandCallbackDataFlags::EPHEMERAL);
+        }
+        message
+    })
+}
+
+/// Fill in the original response of a deferred command.
+fn edit_response<'a>(
+    edit: &'a mut EditInteractionResponse,
+    rsp: &CommandResponse,
+    key: u64,
+    count: usize,
+) -> &'a mut EditInteractionResponse {
+    let parts = response_parts(rsp, key, 0, count);
+    edit.set_embeds(parts.embeds).components(|c| {
+        *c = parts.components;
+        c
+    });
+    if let Some(content) = parts.content {
+        edit.content(content).allowed_mentions(|m| m.empty_parse());
+    }
+    edit
+}
+
+/// Respond to the command `key` that `user` ran.
+async fn command_response(
+    result: Result<CommandResponse>,
+    deferred: bool,
+    pages: &Paginator,
+    confirmations: &Confirmations,
+    responder: &dyn InteractionResponder,
+    key: u64,
+    user: UserId,
+) {
+    let rsp = match result {
+        Ok(rsp) => rsp,
+        Err(e) => {
+            println!("{:?}", e);
+            CommandResponse::error(e)
+        }
+    };
+
+    // Long responses are split into pages that are browsed with buttons. A
+    // confirmation has buttons of its own, so its summary is kept to one page.
+    let mut msg_pages = match rsp.confirm {
+        Some(_) => vec![output::truncate(&rsp.msg, EMBED_DESCRIPTION_LIMIT)],
+        None => output::split_text(&rsp.msg, EMBED_DESCRIPTION_LIMIT),
+    };
+    if msg_pages.is_empty() {
+        msg_pages.push(String::new());
+    }
+    let count = msg_pages.len();
+    let rsp = CommandResponse {
+        msg: msg_pages[0].clone(),
+        ..rsp
+    };
+    if count > 1 {
+        pages.insert(key, rsp.title.clone(), msg_pages);
+    }
+    if let Some(action) = &rsp.confirm {
+        confirmations.insert(key, user, action.clone());
+    }
+
+    let result = match deferred {
+        true => responder.edit_original(&rsp, key, count).await,
+        false => {
+            responder
+                .respond(
+                    InteractionResponseType::ChannelMessageWithSource,
+                    &rsp,
+                    key,
+                    0,
+                    count,
+                )
+                .await
+        }
+    };
+    if let Err(why) = result {
+        println!("{:?}\nResponse: {}", why, rsp.msg);
+    }
 }
 
 async fn name_to_ids(
@@ -907,12 +1292,22 @@ async fn connection_exists(
     Ok(count != 0)
 }
 
+async fn get_channel_webhook(db: &SqlitePool, channel: &ChannelId) -> Result<WebhookId> {
+    let id = channel.0 as i64;
+    sqlx::query!("SELECT webhook FROM Channels WHERE id = ?", id)
+        .fetch_one(db)
+        .and_then(|record| async move { Ok(WebhookId(record.webhook as u64)) })
+        .map_err(|e| Error::new(e).context("Failed to retrieve channel webhook from the database"))
+        .await
+}
+
 async fn maybe_add_connection(
     db: &SqlitePool,
     source_channel_id: &ChannelId,
     target_channel_id: &ChannelId,
     user_id: &UserId,
     webhook_id: &WebhookId,
+    digest: Option<&DigestSchedule>,
 ) -> Result<bool> {
     match connection_exists(db, source_channel_id, target_channel_id, user_id).await {
         Ok(true) => return Ok(false),
@@ -923,12 +1318,19 @@ async fn maybe_add_connection(
     let target = target_channel_id.0 as i64;
     let user = user_id.0 as i64;
     let webhook = webhook_id.0 as i64;
+    let digest_interval = digest.map(|d| d.interval());
+    let next_digest = digest.map(|d| d.first_due(digest::unix_now()));
     sqlx::query!(
-        "INSERT INTO Connections (source, target, user, webhook) VALUES (?, ?, ?, ?)",
+        "
+        INSERT INTO Connections (source, target, user, webhook, digest_interval, next_digest)\n\
+        VALUES (?, ?, ?, ?, ?, ?)
+        ",
         source,
         target,
         user,
-        webhook
+        webhook,
+        digest_interval,
+        next_digest
     )
     .execute(db)
     .await
@@ -937,200 +1339,141 @@ async fn maybe_add_connection(
     Ok(true)
 }
 
-async fn handle_connect_command(
+async fn get_connection_id(
     db: &SqlitePool,
-    command: &ApplicationCommandInteraction,
-) -> Result<CommandResponse> {
-    let options = &command.data.options;
-    let source = get_channel_opt("source", options)?;
-    let target_server_name = get_string_opt("target_server", options)?;
-    let target_channel_name = get_string_opt("target_channel", options)?;
-    let (_target_server_id, target_channel_id) =
-        name_to_ids(db, target_server_name, target_channel_name).await?;
-
-    let id = target_channel_id.0 as i64;
-    let webhook_id = sqlx::query!("SELECT webhook FROM Channels WHERE id = ?", id)
-        .fetch_one(db)
-        .and_then(|record| async move { Ok(WebhookId(record.webhook as u64)) })
-        .await?;
-
-    let result = maybe_add_connection(
-        db,
-        &source.id,
-        &target_channel_id,
-        &command.user.id,
-        &webhook_id,
-    )
-    .await?;
-
-    match result {
-        true => {
-            let title = "Connection created".to_owned();
-            let msg = format!(
-                "Source: <#{}>\nTarget server: __**{}**__\nTarget channel: <#{}>",
-                source.id,
-                target_server_name,
-                target_channel_id.as_u64()
-            );
-            Ok(CommandResponse { title, msg })
-        }
-        false => Err(anyhow!("Connection already exists")),
-    }
-}
-
-async fn handle_disconnect_command(
-    db: &SqlitePool,
-    command: &ApplicationCommandInteraction,
-) -> Result<CommandResponse> {
-    let options = &command.data.options;
-    let source_channel = get_channel_opt("source", options)?;
-    let combined = get_string_opt("target_channel", options)?;
-
-    let re = Regex::new(r"\[(?P<server>.*)\] (?P<channel>.*)")?;
-    let (target_server_name, target_channel_name) = match re.captures(combined) {
-        Some(caps) => {
-            let server_name = caps["server"].trim().to_owned();
-            let channel_name = caps["channel"].trim().to_owned();
-            (server_name, channel_name)
-        }
-        None => {
-            bail!("Invalid target channel format\nIt has to be the following format: [<SERVER_NAME>] <CHANNEL_NAME>");
-        }
-    };
-
-    let (_target_server_id, target_channel_id) =
-        name_to_ids(db, &target_server_name, &target_channel_name).await?;
-
-    let source = source_channel.id.0 as i64;
+    source_channel_id: &ChannelId,
+    target_channel_id: &ChannelId,
+    user_id: &UserId,
+) -> Result<i64> {
+    let source = source_channel_id.0 as i64;
     let target = target_channel_id.0 as i64;
-    let user = command.user.id.0 as i64;
-
+    let user = user_id.0 as i64;
     sqlx::query!(
-        "DELETE FROM Connections WHERE source = ? AND target = ? AND user = ?",
+        "
+        SELECT id\n\
+        FROM Connections\n\
+        WHERE source = ? AND target = ? AND user = ?
+        ",
         source,
         target,
-        user
+        user,
     )
-    .execute(db)
+    .fetch_optional(db)
     .await
-    .map_err(|e| Error::new(e).context("Failed to delete connection in the database"))?;
-
-    let title = "Disconnected".to_owned();
-    let msg = format!(
-        "Source: <#{}>\nServer: {target_server_name}\nTarget: <#{}>",
-        source, target,
-    );
-    Ok(CommandResponse { title, msg })
+    .map_err(|e| Error::new(e).context("Failed to retrieve connection from the database"))?
+    .map(|row| row.id)
+    .ok_or(anyhow!("Connection does not exist"))
 }
 
-async fn handle_disconnect_all_command(
-    db: &SqlitePool,
-    command: &ApplicationCommandInteraction,
-) -> Result<CommandResponse> {
-    let options = &command.data.options;
-    let source_channel = get_channel_opt("source", options)?;
-    let source = source_channel.id.0 as i64;
-    let user = command.user.id.0 as i64;
-
-    sqlx::query!(
-        "DELETE FROM Connections WHERE source = ? AND user = ?",
-        source,
-        user
-    )
-    .execute(db)
-    .await
-    .map_err(|e| Error::new(e).context("Failed to delete connections in the database"))?;
+fn followup_response<'a, 'b>(
+    followup: &'b mut CreateInteractionResponseFollowup<'a>,
+    rsp: &CommandResponse,
+) -> &'b mut CreateInteractionResponseFollowup<'a> {
+    let parts = response_parts(rsp, 0, 0, 1);
+    followup
+        .embeds(parts.embeds)
+        .set_components(parts.components)
+}
 
-    let title = "Disconnected All".to_owned();
-    let msg = format!("Source: <#{}>", source,);
-    Ok(CommandResponse { title, msg })
+fn backfill_progress(backfill: &Backfill, done: usize, total: usize) -> CommandResponse {
+    let title = match done == total {
+        true => "Backfill complete",
+        false => "Backfilling",
+    };
+    CommandResponse {
+        title: title.to_owned(),
+        msg: format!("{backfill}\nRelayed {done}/{total} message(s)"),
+        ..Default::default()
+    }
 }
 
-async fn handle_list_mentions_command(
-    db: &SqlitePool,
-    command: &ApplicationCommandInteraction,
-) -> Result<CommandResponse> {
-    struct Mentions {
-        source: Option<i64>,
-        target: i64,
-        mentions: Vec<String>,
+/// Backfilled messages are relayed like new ones, only they wait for the rate
+/// limits instead of going by the overflow policy of the connection.
+async fn backfill_messages(
+    cx: &RelayContext<'_>,
+    source: &MessageSource,
+    connection: &RelayConnection,
+    messages: &[Message],
+) {
+    for msg in messages {
+        relay_to_connection(cx, source, msg, connection, Pacing::Wait).await;
     }
+}
 
-    impl From<Mentions> for String {
-        fn from(c: Mentions) -> Self {
-            match c.source {
-                Some(source) => {
-                    format!(
-                        "(**Boll's Server**) <#{}> => <#{}>\n> {}",
-                        source,
-                        c.target,
-                        c.mentions.join("\n> ")
-                    )
-                }
-                None => {
-                    format!(
-                        "(**ALL**) => <#{}>\n> {}",
-                        c.target,
-                        c.mentions.join("\n> ")
-                    )
-                }
-            }
+async fn backfill_connection(
+    cx: &RelayContext<'_>,
+    ctx: &ClientContext,
+    command: &ApplicationCommandInteraction,
+    connection: i64,
+    source: ChannelId,
+    backfill: &Backfill,
+) -> Result<()> {
+    let db = cx.db;
+    let connection = get_relay_connection(db, connection).await?;
+    let messages = backfill::fetch_history(&ctx.http, source, command.user.id, backfill).await?;
+    let total = messages.len();
+    let followup = command
+        .create_followup_message(&ctx.http, |f| {
+            followup_response(f, &backfill_progress(backfill, 0, total))
+                .flags(InteractionApplicationCommandCallbackDataFlags::EPHEMERAL)
+        })
+        .await
+        .context("Failed to report backfill progress")?;
+
+    let source = message_source(db, source, None).await?;
+    let mut done = 0;
+    for chunk in messages.chunks(backfill::PROGRESS_INTERVAL) {
+        backfill_messages(cx, &source, &connection, chunk).await;
+        done += chunk.len();
+        let progress = backfill_progress(backfill, done, total);
+        if let Err(why) = command
+            .edit_followup_message(&ctx.http, followup.id, |f| followup_response(f, &progress))
+            .await
+        {
+            println!("Cannot report backfill progress: {why}");
         }
     }
 
-    let test_source = ChannelId(945744069596971021);
-    let test_target = ChannelId(948272822441091144);
-    let test_user = command.user.id;
-    let mentions = get_mentions(db, &test_target, &test_source, &test_user).await?;
-
-    let m = Mentions {
-        source: None, //Some(test_source.0 as i64),
-        target: test_target.0 as i64,
-        mentions,
-    };
-
-    // async fn get_mentions(
-    //     db: &SqlitePool,
-    //     target: &ChannelId,
-    //     source: &ChannelId,
-    //     user: &UserId,
-    // ) -> Result<Vec<String>> {
-
-    Ok(CommandResponse {
-        title: "Mention List for \"Boll's Server\"".to_owned(),
-        msg: m.into(),
-    })
+    Ok(())
 }
 
-async fn handle_list_connections_command(
-    db: &SqlitePool,
+/// Relay the history of the source channel over a new connection, reporting
+/// the progress in a follow-up message.
+async fn run_backfill(
+    cx: &RelayContext<'_>,
+    ctx: &ClientContext,
     command: &ApplicationCommandInteraction,
-) -> Result<CommandResponse> {
-    struct Connection {
-        source: i64,
-        target: i64,
-        source_guild: String,
-        target_guild: String,
-    }
-
-    impl From<Connection> for String {
-        fn from(c: Connection) -> Self {
-            format!(
-                "> <#{}> => <#{}> **({})**",
-                c.source, c.target, c.target_guild
-            )
+    connection: i64,
+    source: ChannelId,
+    backfill: &Backfill,
+) {
+    let backfilled = backfill_connection(cx, ctx, command, connection, source, backfill).await;
+    if let Err(e) = backfilled {
+        println!("{:?}", e);
+        let rsp = CommandResponse::error(format!("Backfill failed: {e}"));
+        if let Err(why) = command
+            .create_followup_message(&ctx.http, |f| {
+                followup_response(f, &rsp)
+                    .flags(InteractionApplicationCommandCallbackDataFlags::EPHEMERAL)
+            })
+            .await
+        {
+            println!("Cannot report backfill error: {why}");
         }
     }
+}
 
-    let user = command.user.id.0 as i64;
-    let connections: Vec<Connection> = sqlx::query!(
+async fn wipe_connections(
+    db: &SqlitePool,
+    server_name: &str,
+    user: &UserId,
+) -> Result<CommandResponse> {
+    let user_id = user;
+    let user = user.0 as i64;
+
+    let ids: Vec<i64> = sqlx::query!(
         "
-        SELECT\n\
-        user as \"user: i64\",\n\
-        source as \"source: i64\",\n\
-        target as \"target: i64\",\n\
-        source_guild.name as source_guild,\n\
-        target_guild.name as target_guild\n\
+        SELECT Connections.id\n\
         FROM Connections\n\
         JOIN Channels source_channel\n\
         ON Connections.source = source_channel.id\n\
@@ -1140,282 +1483,377 @@ async fn handle_list_connections_command(
         ON Connections.target = target_channel.id\n\
         JOIN Guilds target_guild\n\
         ON target_guild.id = target_channel.guild\n\
-        WHERE user = ?
+        WHERE (source_guild.name = ? OR target_guild.name = ?) AND user = ?
         ",
+        server_name,
+        server_name,
         user
     )
     .fetch_all(db)
-    .and_then(|records| async {
-        Ok(records
-            .into_iter()
-            .map(|record| Connection {
-                source: record.source,
-                target: record.target,
-                source_guild: record.source_guild,
-                target_guild: record.target_guild,
-            })
-            .collect::<Vec<Connection>>())
-    })
     .await
     .map_err(|e| {
-        anyhow!(e).context("Failed to retrieve connections for server from the database")
-    })?;
-
-    let grouped = {
-        let mut grouped: HashMap<String, Vec<Connection>> = HashMap::default();
-        for c in connections {
-            match grouped.get_mut(&c.source_guild) {
-                Some(val) => val.push(c),
-                None => {
-                    let _ = grouped.insert(c.source_guild.clone(), vec![c]);
-                }
-            };
-        }
-        grouped
-    };
+        Error::new(e).context("Failed to retrieve connections for server from the database")
+    })?
+    .into_iter()
+    .map(|row| row.id)
+    .collect();
 
-    let msg = grouped
-        .into_iter()
-        .map(|(k, cs)| {
-            let s = cs
-                .into_iter()
-                .map(String::from)
-                .collect::<Vec<String>>()
-                .join("\n");
-            format!("__**{}**__\n{}", k, s)
-        })
-        .collect::<Vec<String>>()
-        .join("\n\n");
+    let count = trash::delete_connections(db, user_id, "wipe-connections", &ids).await?;
 
+    let title = "Wiped Connections".to_owned();
+    let msg = format!(
+        "Removed {count} connection(s) to/from: __**{server_name}**__\n\nUse /undo to restore them"
+    );
     Ok(CommandResponse {
-        title: "Connection List".to_owned(),
+        title,
         msg,
+        ..Default::default()
     })
 }
 
-async fn handle_wipe_connections_command(
-    db: &SqlitePool,
-    command: &ApplicationCommandInteraction,
-) -> Result<CommandResponse> {
-    let options = &command.data.options;
-    let server_name = get_string_opt("server", options)?;
-    let user = command.user.id.0 as i64;
-
-    sqlx::query!(
-        "
-        DELETE FROM Connections\n\
-        WHERE Connections.id in (\n\
-            SELECT Connections.id from Connections\n\
-            JOIN Channels source_channel\n\
-            ON Connections.source = source_channel.id\n\
-            JOIN Guilds source_guild\n\
-            ON source_guild.id = source_channel.guild\n\
-            JOIN Channels target_channel\n\
-            ON Connections.target = target_channel.id\n\
-            JOIN Guilds target_guild\n\
-            ON target_guild.id = target_channel.guild\n\
-            WHERE (source_guild.name = ? OR target_guild.name = ?) AND user = ?\n\
-        );
-        ",
-        server_name,
-        server_name,
-        user
-    )
-    .execute(db)
-    .await
-    .map_err(|e| Error::new(e).context("Failed to wipe connections for server in the database"))?;
-
-    let title = "Wiped Connections".to_owned();
-    let msg = format!("Removed all connections to/from: __**{server_name}**__");
-    Ok(CommandResponse { title, msg })
-}
-
-async fn handle_wipe_mentions_command(
+async fn wipe_mentions(
     db: &SqlitePool,
-    command: &ApplicationCommandInteraction,
+    server_name: &str,
+    user: &UserId,
 ) -> Result<CommandResponse> {
-    let options = &command.data.options;
-    let server_name = get_string_opt("server", options)?;
-    let user = command.user.id.0 as i64;
+    let user_id = user;
+    let user = user.0 as i64;
 
-    sqlx::query!(
+    let ids: Vec<i64> = sqlx::query!(
         "
-        DELETE FROM Mentions\n\
-        WHERE Mentions.id in (\n\
-            SELECT Mentions.id from Mentions\n\
-            LEFT JOIN Channels source_channel\n\
-            ON Mentions.source = source_channel.id\n\
-            LEFT JOIN Guilds source_guild\n\
-            ON source_guild.id = source_channel.guild\n\
-            JOIN Channels target_channel\n\
-            ON Mentions.target = target_channel.id\n\
-            JOIN Guilds target_guild\n\
-            ON target_guild.id = target_channel.guild\n\
-            WHERE (source_guild.name = ? OR target_guild.name = ?) AND user = ?\n\
-        );
+        SELECT Mentions.id\n\
+        FROM Mentions\n\
+        JOIN Channels target_channel\n\
+        ON Mentions.target = target_channel.id\n\
+        JOIN Guilds target_guild\n\
+        ON target_guild.id = target_channel.guild\n\
+        WHERE (\n\
+            target_guild.name = ? OR Mentions.source IN (\n\
+                SELECT Channels.id FROM Channels\n\
+                JOIN Guilds ON Guilds.id = Channels.guild\n\
+                WHERE Guilds.name = ?\n\
+            )\n\
+        ) AND user = ?
         ",
         server_name,
         server_name,
         user,
     )
-    .execute(db)
+    .fetch_all(db)
     .await
-    .map_err(|e| Error::new(e).context("Failed to wipe mentions for server in the database"))?;
+    .map_err(|e| Error::new(e).context("Failed to retrieve mentions for server from the database"))?
+    .into_iter()
+    .map(|row| row.id)
+    .collect();
+
+    let count = trash::delete_mentions(db, user_id, "wipe-mentions", &ids).await?;
 
     let title = "Wiped Mentions".to_owned();
-    let msg = format!("Removed all mentions to/from: __**{server_name}**__");
-    Ok(CommandResponse { title, msg })
+    let msg = format!(
+        "Removed {count} mention(s) to/from: __**{server_name}**__\n\nUse /undo to restore them"
+    );
+    Ok(CommandResponse {
+        title,
+        msg,
+        ..Default::default()
+    })
 }
 
-async fn mention_exists(
+/// Relay a single message to the target channel, formatted like a relayed
+/// message of a connection with the default template.
+async fn relay_message(
     db: &SqlitePool,
-    source: &ChannelId,
+    sender: &dyn WebhookSender,
+    msg: &Message,
     target: &ChannelId,
-    mention: &str,
-) -> Result<bool> {
-    let source = source.0 as i64;
-    let target = target.0 as i64;
-    let count = sqlx::query!(
-        "
-        SELECT COUNT(1) as count\n\
-        FROM Mentions\n\
-        WHERE source = ? AND target = ? AND mention = ?
-        ",
-        source,
-        target,
-        mention
-    )
-    .fetch_one(db)
-    .and_then(|row| async move { Ok(row.count) })
-    .await
-    .map_err(|e| Error::new(e).context("Failed to count existing mentions in the database"))?;
-
-    Ok(count != 0)
+    user: &UserId,
+) -> Result<()> {
+    let (source_guild, source_channel) = template::channel_names(db, &msg.channel_id).await?;
+    let subject = Subject::new(&msg.content, msg.author.id);
+    let mentions = get_mentions(db, target, &msg.channel_id, user, &subject).await?;
+    let mut placeholders = message_placeholders(msg, &source_guild, &source_channel, mentions);
+    let connection = transform::find_connection(db, &msg.channel_id, target).await?;
+    placeholders.content = transform::transform(db, msg, connection).await?;
+    let rendered = Template::default().render(&placeholders);
+    let webhook = get_channel_webhook(db, target).await?;
+    let post_name = thread::post_name(msg, &source_channel);
+    let destination = thread::channel_destination(db, target, None, post_name).await?;
+    execute_webhook(sender, webhook, &destination, false, msg, &rendered).await?;
+    Ok(())
 }
 
-async fn mention_exists_no_source(
-    db: &SqlitePool,
-    target: &ChannelId,
-    mention: &str,
-) -> Result<bool> {
-    let target = target.0 as i64;
-    let count = sqlx::query!(
-        "
-        SELECT COUNT(1) as count\n\
-        FROM Mentions\n\
-        WHERE source IS NULL AND target = ? AND mention = ?
-        ",
-        target,
-        mention
+// Discord fails an interaction that isn't answered within 3 seconds, leave
+// some room for the response itself.
+const IMMEDIATE_TIMEOUT: Duration = Duration::from_millis(2500);
+
+async fn handle_application_command(
+    cx: &CommandContext<'_>,
+    commands: &Registry,
+    pages: &Paginator,
+    confirmations: &Confirmations,
+    command: &ApplicationCommandInteraction,
+) {
+    let responder = CommandResponder {
+        http: &cx.ctx.http,
+        command,
+    };
+    let (key, user) = (command.id.0, command.user.id);
+    let handler = match commands.get(&command.data.name) {
+        Some(handler) => handler,
+        None => {
+            let e = anyhow!("Unknown command: **{}**", command.data.name);
+            command_response(Err(e), false, pages, confirmations, &responder, key, user).await;
+            return;
+        }
+    };
+    // The visibility can't be changed after deferring so it is decided here.
+    let public = get_bool_opt(opt::PUBLIC, &command.data.options).unwrap_or(false);
+    run_command(
+        &command.data.name,
+        handler.timing(),
+        public,
+        handler.run(cx, command),
+        pages,
+        confirmations,
+        &responder,
+        key,
+        user,
     )
-    .fetch_one(db)
-    .and_then(|row| async move { Ok(row.count) })
-    .await
-    .map_err(|e| Error::new(e).context("Failed to count existing mentions in the database"))?;
+    .await;
+}
 
-    Ok(count != 0)
+/// Run a command handler and send its response. A handler that is still busy
+/// when its time is up is left to finish, it may be halfway through its writes.
+#[allow(clippy::too_many_arguments)]
+async fn run_command(
+    name: &str,
+    timing: CommandTiming,
+    public: bool,
+    run: impl Future<Output = Result<CommandResponse>>,
+    pages: &Paginator,
+    confirmations: &Confirmations,
+    responder: &dyn InteractionResponder,
+    key: u64,
+    user: UserId,
+) {
+    let (timeout, mut deferred) = match timing {
+        CommandTiming::Immediate => (IMMEDIATE_TIMEOUT, false),
+        CommandTiming::Deferred(timeout) => (timeout, true),
+    };
+    let defer = CommandResponse {
+        public,
+        ..Default::default()
+    };
+    let defer_kind = InteractionResponseType::DeferredChannelMessageWithSource;
+
+    if deferred {
+        if let Err(why) = responder.respond(defer_kind, &defer, key, 0, 1).await {
+            println!("Cannot defer slash command: {why:?}");
+            return;
+        }
+    }
+
+    let mut run = Box::pin(run);
+    let result = match tokio::time::timeout(timeout, &mut run).await {
+        Ok(result) => result,
+        Err(_) => {
+            let result = match deferred {
+                true => {
+                    let rsp = CommandResponse {
+                        title: format!("/{name}"),
+                        msg: format!(
+                            "Still working after {} seconds, the result will follow",
+                            timeout.as_secs_f32()
+                        ),
+                        ..Default::default()
+                    };
+                    responder.edit_original(&rsp, key, 1).await
+                }
+                false => responder.respond(defer_kind, &defer, key, 0, 1).await,
+            };
+            if let Err(why) = result {
+                println!("{why:?}");
+            }
+            deferred = true;
+            run.await
+        }
+    };
+    command_response(result, deferred, pages, confirmations, responder, key, user).await;
 }
 
-async fn handle_mention_add_command(
-    db: &SqlitePool,
-    command: &ApplicationCommandInteraction,
-) -> Result<CommandResponse> {
-    let options = &command.data.options;
-    let source = get_channel_opt("source", options);
-    let target_server = get_string_opt("target_server", options)?;
-    let target_channel = get_string_opt("target_channel", options)?;
-    let mentions: Vec<&str> = get_string_opt("mentions", options)?.split(' ').collect();
-
-    let (_target_server_id, target_channel_id) =
-        name_to_ids(db, target_server, target_channel).await?;
-
-    for m in &mentions {
-        let user = command.user.id.0 as i64;
-        let target = target_channel_id.0 as i64;
-
-        if let Ok(ch) = source {
-            let source = ch.id.0 as i64;
-            let exists = mention_exists(db, &ch.id, &target_channel_id, m).await?;
-            if !exists {
-                let result = sqlx::query!(
-                    "INSERT INTO Mentions (source, target, mention, user) VALUES (?, ?, ?, ?)",
-                    source,
-                    target,
-                    m,
-                    user
+async fn handle_page_button(
+    pages: &Paginator,
+    responder: &dyn InteractionResponder,
+    key: u64,
+    index: usize,
+) {
+    let result = match pages.get(key, index) {
+        Some((title, page, count)) => {
+            let rsp = CommandResponse {
+                title,
+                msg: page,
+                ..Default::default()
+            };
+            responder
+                .respond(
+                    InteractionResponseType::UpdateMessage,
+                    &rsp,
+                    key,
+                    index,
+                    count,
                 )
-                .execute(db)
                 .await
-                .map_err(|e| Error::new(e).context(format!("Failed to insert mention {m}")));
-                match result {
-                    Ok(_) => (),
-                    Err(e) => println!("{e}"),
-                };
-            }
-        } else {
-            // No source channel provided.
-            let exists = mention_exists_no_source(db, &target_channel_id, m).await?;
-            if !exists {
-                let result = sqlx::query!(
-                    "INSERT INTO Mentions (source, target, mention, user) VALUES (NULL, ?, ?, ?)",
-                    target,
-                    m,
-                    user
+        }
+        None => {
+            let rsp = CommandResponse::error("These pages have expired, run the command again");
+            responder
+                .respond(
+                    InteractionResponseType::ChannelMessageWithSource,
+                    &rsp,
+                    key,
+                    0,
+                    1,
                 )
-                .execute(db)
                 .await
-                .map_err(|e| Error::new(e).context(format!("Failed to insert mention {m}")));
-                match result {
-                    Ok(_) => (),
-                    Err(e) => println!("{e}"),
-                };
-            }
         }
+    };
+    if let Err(why) = result {
+        println!("{:?}", why);
     }
+}
 
-    let from_source = if let Ok(ch) = source {
-        format!("\nSource channel: <#{}>", ch.id)
-    } else {
-        "".to_owned()
+async fn handle_confirm_button(
+    db: &SqlitePool,
+    confirmations: &Confirmations,
+    responder: &dyn InteractionResponder,
+    user: &UserId,
+    choice: Choice,
+    key: u64,
+) {
+    let result = match choice {
+        Choice::Confirm => match confirmations.take(key, *user) {
+            Ok(PendingAction::WipeConnections { server_name }) => {
+                wipe_connections(db, &server_name, user).await
+            }
+            Ok(PendingAction::WipeMentions { server_name }) => {
+                wipe_mentions(db, &server_name, user).await
+            }
+            Err(e) => Err(e),
+        },
+        Choice::Cancel => confirmations.cancel(key, *user).map(|_| CommandResponse {
+            title: "Cancelled".to_owned(),
+            msg: "Nothing was deleted".to_owned(),
+            ..Default::default()
+        }),
     };
+    let rsp = result.unwrap_or_else(|e| {
+        println!("{:?}", e);
+        CommandResponse::error(e)
+    });
+    // Replace the confirmation prompt and remove its buttons.
+    if let Err(why) = responder
+        .respond(InteractionResponseType::UpdateMessage, &rsp, key, 0, 1)
+        .await
+    {
+        println!("{:?}", why);
+    }
+}
 
-    Ok(CommandResponse {
-        title: "Added Mentions".to_owned(),
-        msg: format!(
-            "Mentions:\n{}\n\nTarget server: __**{}**__\nTarget channel <#{}>{}",
-            mentions.join("\n"),
-            target_server,
-            target_channel_id,
-            from_source
-        ),
-    })
+async fn handle_relay_select(
+    db: &SqlitePool,
+    relays: &Relays,
+    sender: &dyn WebhookSender,
+    responder: &dyn InteractionResponder,
+    user: &UserId,
+    selection: Selection,
+    key: u64,
+) {
+    let menu = |msg: &str, components| CommandResponse {
+        title: "Relay message".to_owned(),
+        msg: msg.to_owned(),
+        components: Some(components),
+        ..Default::default()
+    };
+    let mut deferred = false;
+    let result = match selection {
+        Selection::Servers(page) => relay::servers(db).await.map(|servers| {
+            menu(
+                "Select the server to relay the message to",
+                relay::server_select(key, &servers, page),
+            )
+        }),
+        Selection::Server(guild, page) => match relay::channels(db, &guild).await {
+            Ok(channels) if channels.is_empty() => {
+                Err(anyhow!("There are no channels to relay to in this server"))
+            }
+            Ok(channels) => Ok(menu(
+                "Select the channel to relay the message to",
+                relay::channel_select(key, guild, &channels, page),
+            )),
+            Err(e) => Err(e),
+        },
+        Selection::Channel(channel) => match relays.take(key, *user) {
+            Ok(msg) => {
+                // Executing the webhook may take longer than Discord waits for an answer.
+                let kind = InteractionResponseType::DeferredUpdateMessage;
+                let defer = CommandResponse::default();
+                if let Err(why) = responder.respond(kind, &defer, key, 0, 1).await {
+                    println!("{:?}", why);
+                    return;
+                }
+                deferred = true;
+                relay_message(db, sender, &msg, &channel, user)
+                    .await
+                    .map(|_| CommandResponse {
+                        title: "Message relayed".to_owned(),
+                        msg: format!("Relayed [the message](<{}>) to <#{}>", msg.link(), channel),
+                        ..Default::default()
+                    })
+            }
+            Err(e) => Err(e),
+        },
+    };
+    let rsp = result.unwrap_or_else(|e| {
+        println!("{:?}", e);
+        CommandResponse::error(e)
+    });
+    let result = match deferred {
+        true => responder.edit_original(&rsp, key, 1).await,
+        false => {
+            responder
+                .respond(InteractionResponseType::UpdateMessage, &rsp, key, 0, 1)
+                .await
+        }
+    };
+    if let Err(why) = result {
+        println!("{:?}", why);
+    }
 }
 
-async fn handle_application_command(
+async fn handle_message_component(
     db: &SqlitePool,
-    command: &ApplicationCommandInteraction,
+    pages: &Paginator,
+    confirmations: &Confirmations,
+    relays: &Relays,
+    transport: &DiscordTransport,
+    component: &MessageComponentInteraction,
     ctx: &ClientContext,
 ) {
-    let result = match command.data.name.as_str() {
-        "connect" => handle_connect_command(db, command).await,
-        "disconnect" => handle_disconnect_command(db, command).await,
-        "disconnect-all" => handle_disconnect_all_command(db, command).await,
-        "list-connections" => handle_list_connections_command(db, command).await,
-        "wipe-connections" => handle_wipe_connections_command(db, command).await,
-        "wipe-mentions" => handle_wipe_mentions_command(db, command).await,
-        "mention-add" => handle_mention_add_command(db, command).await,
-        "list-mentions" => handle_list_mentions_command(db, command).await,
-        _ => Err(anyhow!(
-            "Unknown command: **{}**",
-            command.data.name.as_str()
-        )),
+    let responder = ComponentResponder {
+        http: &ctx.http,
+        component,
     };
-    match result {
-        Ok(rsp) => ok_command_response(&rsp.title, &rsp.msg, command, ctx).await,
-        Err(e) => {
-            println!("{:?}", e);
-            error_command_response(&e.to_string(), command, ctx).await;
-        }
+    let user = &component.user.id;
+    if let Some((key, index)) = output::parse_page_id(&component.data.custom_id) {
+        handle_page_button(pages, &responder, key, index).await
+    } else if let Some((choice, key)) = confirm::parse_confirm_id(&component.data.custom_id) {
+        handle_confirm_button(db, confirmations, &responder, user, choice, key).await
+    } else if let Some((selection, key)) =
+        relay::parse_relay_id(&component.data.custom_id, &component.data.values)
+    {
+        handle_relay_select(db, relays, transport, &responder, user, selection, key).await
+    } else {
+        println!("Received unknown message component:\n{:#?}", component.data);
     }
 }
 
@@ -1484,15 +1922,29 @@ async fn main() {
     // !HACK (this should be saved in the TOKEN file)
     let application_id: u64 = 936607788493307944;
 
+    let relay_state = RelayState::default();
+    let transport: Arc<OnceCell<Arc<DiscordTransport>>> = Arc::default();
     let mut client = Client::builder(&discord_token.trim())
         .event_handler(Handler {
-            db,
+            db: db.clone(),
+            commands: Registry::default(),
+            pages: Paginator::default(),
+            confirmations: Confirmations::default(),
+            relays: Relays::default(),
+            relay_state: relay_state.clone(),
+            command_scope: CommandScope::from_env().await,
             cache_rdy_tx,
+            transport: transport.clone(),
         })
         .application_id(application_id)
         .await
         .expect("Error creating Discord client");
 
+    let http = client.cache_and_http.http.clone();
+    let transport = transport
+        .get_or_init(|| Arc::new(DiscordTransport::new(http)))
+        .clone();
+
     tokio::spawn(async move {
         if let Err(why) = client.start().await {
             println!("Discord client error: {why}");
@@ -1503,6 +1955,34 @@ async fn main() {
     // Discord cache has been received and parsed.
     cache_rdy_rx.recv().await;
 
+    // Messages that were held back by the rate limits before a restart.
+    let cx = RelayContext {
+        db: &db,
+        sender: transport.as_ref(),
+        resolver: transport.as_ref(),
+        state: &relay_state,
+    };
+    if let Err(e) = restore_queued(&cx).await {
+        println!("{:?}", e);
+    }
+
+    // Post the buffered messages of digest connections periodically.
+    tokio::spawn(digest::run_digest_task(
+        db.clone(),
+        transport.clone(),
+        relay_state.limits.clone(),
+    ));
+
+    // Send the messages held back by the rate limits once they allow it.
+    tokio::spawn(ratelimit::run_overflow_task(
+        db.clone(),
+        transport,
+        relay_state,
+    ));
+
+    // Remove archived messages that are past the retention period.
+    tokio::spawn(archive::run_prune_task(db, Retention::from_env().await));
+
     let (_exit_tx, mut exit_rx) = tokio::sync::mpsc::channel::<bool>(1);
 
     // Main event loop.
@@ -0,0 +1,173 @@
+use serenity::{
+    builder::{CreateComponents, CreateEmbed},
+    model::interactions::message_component::ButtonStyle,
+    utils::Color,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+pub const CONTENT_LIMIT: usize = 2000;
+pub const EMBED_DESCRIPTION_LIMIT: usize = 4096;
+pub const EMBED_TITLE_LIMIT: usize = 256;
+pub const EMBED_FOOTER_LIMIT: usize = 2048;
+pub const EMBED_FIELD_LIMIT: usize = 1024;
+
+// Long command responses can be browsed for as long as the interaction token is valid.
+const PAGE_TTL: Duration = Duration::from_secs(15 * 60);
+
+const PAGE_PREFIX: &str = "page";
+
+/// Cut the text at `limit` characters, marking the cut with an ellipsis.
+pub fn truncate(text: &str, limit: usize) -> String {
+    match text.char_indices().nth(limit.saturating_sub(1)) {
+        Some((i, _)) if text.chars().count() > limit => format!("{}…", &text[..i]),
+        _ => text.to_owned(),
+    }
+}
+
+/// Split the text into chunks of at most `limit` characters.
+///
+/// Chunks are preferably cut at paragraph breaks, then line breaks and then
+/// whitespace so that lines and words are kept intact whenever possible.
+pub fn split_text(text: &str, limit: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut rest = text.trim();
+    while !rest.is_empty() {
+        let end = match rest.char_indices().nth(limit) {
+            Some((i, _)) => i,
+            None => {
+                chunks.push(rest.to_owned());
+                break;
+            }
+        };
+        let window = &rest[..end];
+        let cut = ["\n\n", "\n", " "]
+            .iter()
+            .filter_map(|sep| window.rfind(sep))
+            .find(|&i| i > 0)
+            .unwrap_or(end);
+        chunks.push(rest[..cut].trim_end().to_owned());
+        rest = rest[cut..].trim_start();
+    }
+    chunks
+}
+
+/// A single message as it is sent through a webhook.
+#[derive(Clone)]
+pub struct OutgoingMessage {
+    pub content: String,
+    pub embed: Option<CreateEmbed>,
+}
+
+/// Spread the content and embeds over as many messages as needed to stay within
+/// Discord's limits. Each embed gets its own message since a message can carry
+/// at most 6000 characters worth of embeds.
+pub fn split_message(content: &str, embeds: Vec<CreateEmbed>) -> Vec<OutgoingMessage> {
+    let mut messages: Vec<OutgoingMessage> = split_text(content, CONTENT_LIMIT)
+        .into_iter()
+        .map(|content| OutgoingMessage {
+            content,
+            embed: None,
+        })
+        .collect();
+
+    let mut embeds = embeds.into_iter();
+    match (messages.last_mut(), embeds.next()) {
+        (Some(last), Some(embed)) => last.embed = Some(embed),
+        (None, Some(embed)) => messages.push(OutgoingMessage {
+            content: String::new(),
+            embed: Some(embed),
+        }),
+        (_, None) => (),
+    }
+    messages.extend(embeds.map(|embed| OutgoingMessage {
+        content: String::new(),
+        embed: Some(embed),
+    }));
+    messages
+}
+
+struct Pages {
+    title: String,
+    pages: Vec<String>,
+    created: Instant,
+}
+
+/// Pages of long command responses, browsed with the Previous/Next buttons.
+/// Keyed by the id of the interaction that produced the response.
+#[derive(Clone, Default)]
+pub struct Paginator(Arc<Mutex<HashMap<u64, Pages>>>);
+
+impl Paginator {
+    pub fn insert(&self, key: u64, title: String, pages: Vec<String>) {
+        let mut map = self.0.lock().unwrap();
+        map.retain(|_, p| p.created.elapsed() < PAGE_TTL);
+        map.insert(
+            key,
+            Pages {
+                title,
+                pages,
+                created: Instant::now(),
+            },
+        );
+    }
+
+    /// Title, page content and page count.
+    pub fn get(&self, key: u64, index: usize) -> Option<(String, String, usize)> {
+        let map = self.0.lock().unwrap();
+        let p = map.get(&key).filter(|p| p.created.elapsed() < PAGE_TTL)?;
+        let page = p.pages.get(index)?;
+        Some((p.title.clone(), page.clone(), p.pages.len()))
+    }
+}
+
+pub fn parse_page_id(custom_id: &str) -> Option<(u64, usize)> {
+    let mut parts = custom_id.split(':');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(PAGE_PREFIX), Some(key), Some(index)) => {
+            Some((key.parse().ok()?, index.parse().ok()?))
+        }
+        _ => None,
+    }
+}
+
+pub fn page_buttons(
+    components: &mut CreateComponents,
+    key: u64,
+    index: usize,
+    count: usize,
+) -> &mut CreateComponents {
+    components.create_action_row(|row| {
+        row.create_button(|b| {
+            b.style(ButtonStyle::Secondary)
+                .label("Previous")
+                .custom_id(format!("{PAGE_PREFIX}:{key}:{}", index.saturating_sub(1)))
+                .disabled(index == 0)
+        })
+        .create_button(|b| {
+            b.style(ButtonStyle::Secondary)
+                .label("Next")
+                .custom_id(format!("{PAGE_PREFIX}:{key}:{}", index + 1))
+                .disabled(index + 1 >= count)
+        })
+    })
+}
+
+/// The embed showing one page of a command response.
+pub fn page_embed(
+    color: Color,
+    title: &str,
+    page: &str,
+    index: usize,
+    count: usize,
+) -> CreateEmbed {
+    let mut embed = CreateEmbed::default();
+    embed.color(color).title(title).description(page);
+    if count > 1 {
+        embed.footer(|f| f.text(format!("Page {}/{}", index + 1, count)));
+    }
+    embed
+}
@@ -0,0 +1,582 @@
+use anyhow::{anyhow, Error, Result};
+use serenity::model::{
+    channel::Message,
+    id::{ChannelId, WebhookId},
+};
+use sqlx::SqlitePool;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    archive::Delivery,
+    dedup::Settings,
+    output::OutgoingMessage,
+    template::RenderedMessage,
+    thread::{self, Thread},
+    transport::{Author, Destination, DiscordTransport, WebhookSender},
+    RelayState,
+};
+
+// Discord allows about 30 messages per minute through the webhooks of a channel.
+pub const DEFAULT_WEBHOOK_LIMIT: i64 = 30;
+
+// Messages queued per connection beyond this are dropped.
+const MAX_QUEUED: usize = 50;
+
+// How often the background task sends what was held back.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What happens to a message that exceeds the rate limit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Overflow {
+    /// Not relayed at all.
+    Drop,
+    /// Relayed once the rate limit allows it.
+    Queue,
+    /// Counted and relayed as a single "N more messages" summary.
+    Collapse,
+}
+
+impl Overflow {
+    pub fn parse(s: &str) -> Result<Self> {
+        match s {
+            "drop" => Ok(Overflow::Drop),
+            "queue" => Ok(Overflow::Queue),
+            "collapse" => Ok(Overflow::Collapse),
+            s => Err(anyhow!("Unknown overflow policy: {s}")),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Overflow::Drop => "drop",
+            Overflow::Queue => "queue",
+            Overflow::Collapse => "collapse",
+        }
+    }
+}
+
+/// How a relayed message is held to the rate limits.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pacing {
+    /// Sent if the limits allow it, otherwise the overflow policy applies.
+    Admit,
+    /// Sent once the limits allow it, e.g. backfilled messages.
+    Wait,
+}
+
+/// Messages per minute allowed for a connection and for the webhook of its
+/// target channel.
+#[derive(Clone, Copy)]
+pub struct Limits {
+    /// `None` for connections without a limit of their own.
+    pub connection: Option<i64>,
+    pub webhook: i64,
+}
+
+struct Bucket {
+    per_minute: i64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    fn new(per_minute: i64) -> Self {
+        Bucket {
+            per_minute,
+            tokens: Self::capacity(per_minute),
+            last: Instant::now(),
+        }
+    }
+
+    /// A full minute worth of messages can be sent in a row.
+    fn capacity(per_minute: i64) -> f64 {
+        per_minute.max(1) as f64
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_minute as f64 / 60.0)
+            .min(Self::capacity(self.per_minute));
+        self.last = now;
+    }
+}
+
+/// Get the bucket for the key, starting over when its rate was changed.
+fn bucket<K: std::hash::Hash + Eq>(
+    buckets: &mut HashMap<K, Bucket>,
+    key: K,
+    per_minute: i64,
+) -> &mut Bucket {
+    let bucket = buckets
+        .entry(key)
+        .or_insert_with(|| Bucket::new(per_minute));
+    if bucket.per_minute != per_minute {
+        *bucket = Bucket::new(per_minute);
+    }
+    bucket
+}
+
+/// A relayed message held back by the rate limit.
+pub struct Queued {
+    pub message: Message,
+    pub rendered: RenderedMessage,
+    pub destination: Destination,
+    /// Publish it once posted in the news channel.
+    pub publish: bool,
+    /// The thread the message was posted in, if any.
+    pub source_thread: Option<Thread>,
+    /// The de-duplication settings of the target when it was checked.
+    pub dedup: Option<Settings>,
+}
+
+struct Backlog {
+    webhook: WebhookId,
+    target: ChannelId,
+    limits: Limits,
+    queue: VecDeque<Queued>,
+    collapsed: u64,
+    /// Where the collapsed messages came from, e.g. "**Server** #channel".
+    source: String,
+    /// Where the last collapsed message would have been posted.
+    destination: Destination,
+}
+
+/// What was sent in place of a message over the limit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Admission {
+    Send,
+    Dropped,
+    Queued,
+    Collapsed,
+}
+
+impl Admission {
+    /// How the message is recorded in the archive, `None` when it's sent.
+    pub fn delivery(&self) -> Option<Delivery> {
+        match self {
+            Admission::Send => None,
+            Admission::Dropped => Some(Delivery::Dropped),
+            Admission::Queued => Some(Delivery::Queued),
+            Admission::Collapsed => Some(Delivery::Collapsed),
+        }
+    }
+}
+
+/// Sent by the background task once the rate limit allows it.
+pub enum Due {
+    Queued {
+        connection: i64,
+        webhook: WebhookId,
+        target: ChannelId,
+        queued: Box<Queued>,
+    },
+    Collapsed {
+        webhook: WebhookId,
+        destination: Destination,
+        count: u64,
+        source: String,
+    },
+}
+
+#[derive(Default)]
+struct State {
+    connections: HashMap<i64, Bucket>,
+    webhooks: HashMap<WebhookId, Bucket>,
+    backlogs: HashMap<i64, Backlog>,
+}
+
+impl State {
+    /// Take a token from the connection and the webhook bucket if both have one.
+    fn acquire(
+        &mut self,
+        connection: i64,
+        webhook: WebhookId,
+        limits: Limits,
+        now: Instant,
+    ) -> bool {
+        let webhook_bucket = bucket(&mut self.webhooks, webhook, limits.webhook);
+        webhook_bucket.refill(now);
+        if webhook_bucket.tokens < 1.0 {
+            return false;
+        }
+        if let Some(per_minute) = limits.connection {
+            let connection_bucket = bucket(&mut self.connections, connection, per_minute);
+            connection_bucket.refill(now);
+            if connection_bucket.tokens < 1.0 {
+                return false;
+            }
+            connection_bucket.tokens -= 1.0;
+        }
+        // Looked up again, the connection bucket borrowed the state in between.
+        self.webhooks.get_mut(&webhook).unwrap().tokens -= 1.0;
+        true
+    }
+}
+
+/// Token buckets per connection and per target webhook, and the messages
+/// held back by them.
+#[derive(Clone, Default)]
+pub struct RateLimiter(Arc<Mutex<State>>);
+
+impl RateLimiter {
+    /// Let the message through if the limits allow it, otherwise apply the
+    /// overflow policy of the connection. Messages that come in while others
+    /// are held back wait their turn.
+    #[allow(clippy::too_many_arguments)]
+    pub fn admit(
+        &self,
+        connection: i64,
+        webhook: WebhookId,
+        target: ChannelId,
+        limits: Limits,
+        overflow: Overflow,
+        source: String,
+        queued: Queued,
+    ) -> Admission {
+        let mut state = self.0.lock().unwrap();
+        let backlogged = matches!(
+            state.backlogs.get(&connection),
+            Some(b) if !b.queue.is_empty() || b.collapsed > 0
+        );
+        if !backlogged && state.acquire(connection, webhook, limits, Instant::now()) {
+            return Admission::Send;
+        }
+        if overflow == Overflow::Drop {
+            return Admission::Dropped;
+        }
+
+        let backlog = state.backlogs.entry(connection).or_insert_with(|| Backlog {
+            webhook,
+            target,
+            limits,
+            queue: VecDeque::new(),
+            collapsed: 0,
+            source: source.clone(),
+            destination: queued.destination.clone(),
+        });
+        backlog.limits = limits;
+        match overflow {
+            Overflow::Queue if backlog.queue.len() < MAX_QUEUED => {
+                backlog.queue.push_back(queued);
+                Admission::Queued
+            }
+            Overflow::Queue | Overflow::Drop => Admission::Dropped,
+            Overflow::Collapse => {
+                backlog.collapsed += 1;
+                backlog.source = source;
+                backlog.destination = queued.destination;
+                Admission::Collapsed
+            }
+        }
+    }
+
+    /// Wait until the limits allow a message to be sent.
+    pub async fn wait(&self, connection: i64, webhook: WebhookId, limits: Limits) {
+        loop {
+            let acquired = {
+                let mut state = self.0.lock().unwrap();
+                state.acquire(connection, webhook, limits, Instant::now())
+            };
+            if acquired {
+                return;
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Take what the limits now allow to be sent.
+    pub fn due(&self) -> Vec<Due> {
+        let mut state = self.0.lock().unwrap();
+        let now = Instant::now();
+        let mut due = Vec::new();
+        let connections: Vec<i64> = state.backlogs.keys().copied().collect();
+        for connection in connections {
+            loop {
+                let backlog = &state.backlogs[&connection];
+                let (webhook, limits) = (backlog.webhook, backlog.limits);
+                if backlog.queue.is_empty() && backlog.collapsed == 0 {
+                    state.backlogs.remove(&connection);
+                    break;
+                }
+                if !state.acquire(connection, webhook, limits, now) {
+                    break;
+                }
+                let backlog = state.backlogs.get_mut(&connection).unwrap();
+                match backlog.queue.pop_front() {
+                    Some(queued) => due.push(Due::Queued {
+                        connection,
+                        webhook,
+                        target: backlog.target,
+                        queued: Box::new(queued),
+                    }),
+                    None => {
+                        due.push(Due::Collapsed {
+                            webhook,
+                            destination: backlog.destination.clone(),
+                            count: backlog.collapsed,
+                            source: backlog.source.clone(),
+                        });
+                        backlog.collapsed = 0;
+                    }
+                }
+            }
+        }
+        due
+    }
+}
+
+/// Send the messages that were held back and are now within the limits.
+pub async fn send_due(db: &SqlitePool, sender: &dyn WebhookSender, state: &RelayState) {
+    for due in state.limits.due() {
+        match due {
+            Due::Queued {
+                connection,
+                webhook,
+                target,
+                queued,
+            } => {
+                let msg = &queued.message;
+                let source_thread = queued.source_thread.as_ref();
+                let (publish, rendered) = (queued.publish, &queued.rendered);
+                let _thread = state.threads.lock(connection, source_thread).await;
+                let result = match thread::queued_destination(
+                    db,
+                    connection,
+                    &queued.destination,
+                    source_thread,
+                )
+                .await
+                {
+                    Ok(destination) => crate::execute_webhook(
+                        sender,
+                        webhook,
+                        &destination,
+                        publish,
+                        msg,
+                        rendered,
+                    )
+                    .await
+                    .map(|posted| (destination, posted)),
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = forget(db, connection, msg).await {
+                    println!("{:?}", e);
+                }
+                let posted = match &result {
+                    Ok((_, Some((posted, first)))) => Some((*posted, first.clone())),
+                    _ => None,
+                };
+                match result {
+                    Err(e) => {
+                        println!("{:?}", e);
+                        let error = Some(format!("{:#}", e));
+                        crate::archive_message(
+                            db,
+                            msg,
+                            connection,
+                            &target,
+                            Delivery::Failed,
+                            error,
+                        )
+                        .await;
+                    }
+                    Ok((destination, posted)) => {
+                        if let Some((posted, _)) = &posted {
+                            let recorded = thread::record_post(
+                                db,
+                                connection,
+                                &destination,
+                                source_thread,
+                                posted,
+                                &target,
+                            )
+                            .await;
+                            if let Err(e) = recorded {
+                                println!("{:?}", e);
+                            }
+                        }
+                        crate::archive_delivered(db, msg, connection, &target, &posted).await
+                    }
+                }
+                if let Some(settings) = queued.dedup {
+                    match posted {
+                        Some((posted, first)) => {
+                            let annotation = state.duplicates.posted(
+                                target,
+                                &msg.content,
+                                webhook,
+                                posted,
+                                first,
+                                settings,
+                            );
+                            crate::annotate_duplicate(db, sender, annotation).await;
+                        }
+                        None => state.duplicates.forget(target, &msg.content),
+                    }
+                }
+            }
+            Due::Collapsed {
+                webhook,
+                destination,
+                count,
+                source,
+            } => {
+                let author = Author {
+                    name: "Analyst Bot".to_owned(),
+                    avatar_url: String::new(),
+                };
+                let message = OutgoingMessage {
+                    content: format!(
+                        "**{count} more message(s)** from {source} were held back by the rate limit"
+                    ),
+                    embed: None,
+                };
+                if let Err(e) = sender
+                    .execute(webhook, &destination, &author, &message)
+                    .await
+                {
+                    println!("{:?}", e);
+                }
+            }
+        }
+    }
+}
+
+pub async fn run_overflow_task(
+    db: SqlitePool,
+    transport: Arc<DiscordTransport>,
+    state: RelayState,
+) {
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        send_due(&db, transport.as_ref(), &state).await;
+    }
+}
+
+/// Keep a queued message in the database until it is sent.
+pub async fn persist(db: &SqlitePool, connection: i64, msg: &Message) -> Result<()> {
+    let message = msg.id.0 as i64;
+    let payload = serde_json::to_string(msg)?;
+    sqlx::query!(
+        "INSERT OR REPLACE INTO RelayQueue (connection, message, payload) VALUES (?, ?, ?)",
+        connection,
+        message,
+        payload
+    )
+    .execute(db)
+    .await
+    .map_err(|e| Error::new(e).context("Failed to save queued message in the database"))?;
+
+    Ok(())
+}
+
+async fn forget(db: &SqlitePool, connection: i64, msg: &Message) -> Result<()> {
+    let message = msg.id.0 as i64;
+    sqlx::query!(
+        "DELETE FROM RelayQueue WHERE connection = ? AND message = ?",
+        connection,
+        message
+    )
+    .execute(db)
+    .await
+    .map_err(|e| Error::new(e).context("Failed to remove queued message from the database"))?;
+
+    Ok(())
+}
+
+/// Remove and return the messages that were still queued when the bot stopped.
+pub async fn take_persisted(db: &SqlitePool) -> Result<Vec<(i64, Message)>> {
+    let mut tx = db.begin().await?;
+    let rows = sqlx::query!("SELECT connection, payload FROM RelayQueue ORDER BY rowid")
+        .fetch_all(&mut tx)
+        .await
+        .map_err(|e| {
+            Error::new(e).context("Failed to retrieve queued messages from the database")
+        })?;
+    sqlx::query!("DELETE FROM RelayQueue")
+        .execute(&mut tx)
+        .await
+        .map_err(|e| Error::new(e).context("Failed to clear queued messages in the database"))?;
+    tx.commit().await?;
+
+    let mut queued = Vec::new();
+    for row in rows {
+        match serde_json::from_str(&row.payload) {
+            Ok(msg) => queued.push((row.connection, msg)),
+            Err(e) => println!("Skipping a queued message that can't be read: {e}"),
+        }
+    }
+    Ok(queued)
+}
+
+/// Messages per minute allowed for a connection and the webhook of its target.
+pub async fn get_limits(db: &SqlitePool, connection: i64) -> Result<Limits> {
+    let row = sqlx::query!(
+        "
+        SELECT\n\
+        rate_limit,\n\
+        (SELECT rate_limit FROM Channels WHERE Channels.id = target)\n\
+        as \"webhook_rate_limit: i64\"\n\
+        FROM Connections WHERE id = ?
+        ",
+        connection
+    )
+    .fetch_one(db)
+    .await
+    .map_err(|e| Error::new(e).context("Failed to retrieve the rate limits from the database"))?;
+
+    Ok(Limits {
+        connection: row.rate_limit,
+        webhook: row.webhook_rate_limit.unwrap_or(DEFAULT_WEBHOOK_LIMIT),
+    })
+}
+
+/// Set the limit of a connection, `None` removes it. The overflow policy is
+/// kept if none is given.
+pub async fn set_connection_limit(
+    db: &SqlitePool,
+    connection: i64,
+    per_minute: Option<i64>,
+    overflow: Option<Overflow>,
+) -> Result<Overflow> {
+    let overflow = overflow.map(|o| o.as_str());
+    let row = sqlx::query!(
+        "
+        UPDATE Connections SET rate_limit = ?, overflow = COALESCE(?, overflow)\n\
+        WHERE id = ? RETURNING overflow
+        ",
+        per_minute,
+        overflow,
+        connection
+    )
+    .fetch_one(db)
+    .await
+    .map_err(|e| Error::new(e).context("Failed to update the rate limit in the database"))?;
+
+    Overflow::parse(&row.overflow)
+}
+
+/// Set the limit of the webhook of a target channel, `None` goes back to the
+/// default.
+pub async fn set_webhook_limit(
+    db: &SqlitePool,
+    target: &ChannelId,
+    per_minute: Option<i64>,
+) -> Result<()> {
+    let target = target.0 as i64;
+    sqlx::query!(
+        "UPDATE Channels SET rate_limit = ? WHERE id = ?",
+        per_minute,
+        target
+    )
+    .execute(db)
+    .await
+    .map_err(|e| Error::new(e).context("Failed to update the rate limit in the database"))?;
+
+    Ok(())
+}
@@ -0,0 +1,169 @@
+use anyhow::{Error, Result};
+use futures::TryFutureExt;
+use serenity::model::{
+    channel::ReactionType,
+    id::{ChannelId, MessageId, WebhookId},
+};
+use sqlx::SqlitePool;
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    output::{OutgoingMessage, CONTENT_LIMIT},
+    ratelimit::{self, Limits, RateLimiter},
+    transport::{ChannelResolver, Posted, WebhookSender},
+};
+
+// Reactions come in bursts, the copies are edited once they settle.
+pub const MIRROR_DELAY: Duration = Duration::from_secs(3);
+
+/// Source messages with an edit of their copies waiting to go out.
+#[derive(Clone, Default)]
+pub struct PendingMirrors(Arc<Mutex<HashSet<MessageId>>>);
+
+impl PendingMirrors {
+    /// Whether an edit has to be scheduled, false if one is waiting already.
+    pub fn schedule(&self, message: MessageId) -> bool {
+        self.0.lock().unwrap().insert(message)
+    }
+
+    /// The edit goes out, reactions from now on schedule another one.
+    pub fn start(&self, message: MessageId) {
+        self.0.lock().unwrap().remove(&message);
+    }
+}
+
+/// How a reaction is shown on the relayed copies. Custom emojis of the
+/// source server can't be used in the target, they're shown by name.
+pub fn emoji_name(reaction: &ReactionType) -> String {
+    match reaction {
+        ReactionType::Unicode(emoji) => emoji.clone(),
+        ReactionType::Custom { name, .. } => {
+            format!(":{}:", name.as_deref().unwrap_or("emoji"))
+        }
+        _ => "?".to_owned(),
+    }
+}
+
+/// The reactions and their counts as a compact footer, e.g. "✅ 3 · 🎯 1".
+pub fn status_footer(reactions: &[(String, u64)]) -> String {
+    reactions
+        .iter()
+        .filter(|(_, count)| *count > 0)
+        .map(|(emoji, count)| format!("{emoji} {count}"))
+        .collect::<Vec<String>>()
+        .join(" · ")
+}
+
+/// The content of a relayed copy with the note on where else it was posted
+/// and the footer with the reactions below it.
+pub fn compose(content: &str, note: Option<&str>, footer: &str) -> String {
+    let footer = (!footer.is_empty()).then(|| format!("-# {footer}"));
+    [Some(content), note, footer.as_deref()]
+        .into_iter()
+        .flatten()
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
+/// Set whether the reactions on source messages are shown on the copies
+/// relayed through the connection.
+pub async fn set_mirroring(db: &SqlitePool, connection: i64, enabled: bool) -> Result<()> {
+    sqlx::query!(
+        "UPDATE Connections SET mirror_reactions = ? WHERE id = ?",
+        enabled,
+        connection
+    )
+    .execute(db)
+    .await
+    .map_err(|e| Error::new(e).context("Failed to update reaction mirroring of the connection"))?;
+
+    Ok(())
+}
+
+/// Update the footer of the copies of a source message after its reactions
+/// changed, for the connections that mirror reactions.
+pub async fn mirror(
+    db: &SqlitePool,
+    sender: &dyn WebhookSender,
+    resolver: &dyn ChannelResolver,
+    limiter: &RateLimiter,
+    channel: ChannelId,
+    message: MessageId,
+) -> Result<()> {
+    let id = message.0 as i64;
+    let copies = sqlx::query!(
+        "
+        SELECT\n\
+        copy as \"copy!: i64\",\n\
+        copy_channel as \"copy_channel!: i64\",\n\
+        copy_content as \"copy_content!\",\n\
+        copy_note,\n\
+        connection,\n\
+        (SELECT rate_limit FROM Channels WHERE Channels.id = Archive.target)\n\
+        as \"webhook_rate_limit: i64\",\n\
+        (SELECT webhook FROM Connections WHERE Connections.id = Archive.connection)\n\
+        as \"webhook!: i64\"\n\
+        FROM Archive\n\
+        WHERE message = ? AND copy IS NOT NULL AND connection IN\n\
+        (SELECT id FROM Connections WHERE mirror_reactions)
+        ",
+        id
+    )
+    .fetch_all(db)
+    .map_err(|e| Error::new(e).context("Failed to retrieve relayed copies from the database"))
+    .await?;
+
+    if copies.is_empty() {
+        return Ok(());
+    }
+
+    let footer = status_footer(&resolver.reactions(channel, message).await?);
+    let stored = match footer.is_empty() {
+        true => None,
+        false => Some(footer.as_str()),
+    };
+    for copy in copies {
+        sqlx::query!(
+            "UPDATE Archive SET copy_footer = ? WHERE copy = ?",
+            stored,
+            copy.copy
+        )
+        .execute(db)
+        .await
+        .map_err(|e| {
+            Error::new(e).context("Failed to archive footer of the copy in the database")
+        })?;
+
+        let content = compose(&copy.copy_content, copy.copy_note.as_deref(), &footer);
+        if content.chars().count() > CONTENT_LIMIT {
+            continue;
+        }
+        let posted = Posted {
+            id: MessageId(copy.copy as u64),
+            channel: ChannelId(copy.copy_channel as u64),
+        };
+        // The embed of the copy is left as is.
+        let edited = OutgoingMessage {
+            content,
+            embed: None,
+        };
+        let webhook = WebhookId(copy.webhook as u64);
+        // Edits only count against the webhook, not the connection.
+        let limits = Limits {
+            connection: None,
+            webhook: copy
+                .webhook_rate_limit
+                .unwrap_or(ratelimit::DEFAULT_WEBHOOK_LIMIT),
+        };
+        limiter.wait(copy.connection, webhook, limits).await;
+        if let Err(e) = sender.edit(webhook, &posted, &edited).await {
+            println!("{:?}", e);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,237 @@
+use anyhow::{anyhow, Context, Result};
+use regex::Regex;
+use serde_json::{Map, Value};
+use serenity::{
+    http::{
+        request::{Request, RequestBuilder},
+        routing::RouteInfo,
+        Http,
+    },
+    model::id::GuildId,
+};
+use std::fmt::Display;
+
+// Fields that make up the definition of a command. Everything else in
+// Discord's copy (ids, versions, localisations, ...) is ignored when diffing.
+const DEFINITION_FIELDS: [&str; 11] = [
+    "name",
+    "description",
+    "type",
+    "options",
+    "choices",
+    "value",
+    "required",
+    "autocomplete",
+    "channel_types",
+    "min_value",
+    "max_value",
+];
+
+// Chat input commands are the default command type.
+const CHAT_INPUT: u64 = 1;
+
+/// Where the commands are registered, set with `COMMAND_SCOPE=guild|global`
+/// in the ".env" file.
+#[derive(Clone, Copy)]
+pub enum CommandScope {
+    /// In every server the bot is in, updates show up immediately.
+    Guild,
+    /// Once for the application, updates can take a while to show up.
+    Global,
+}
+
+impl CommandScope {
+    pub fn parse(s: &str) -> Result<Self> {
+        match s {
+            "guild" => Ok(CommandScope::Guild),
+            "global" => Ok(CommandScope::Global),
+            s => Err(anyhow!("Unknown command scope: {s}")),
+        }
+    }
+
+    /// Read the scope from the ".env" file, commands are registered per
+    /// server unless configured otherwise.
+    pub async fn from_env() -> Self {
+        let content = tokio::fs::read_to_string(".env").await.unwrap_or_default();
+        let re = Regex::new(r"COMMAND_SCOPE=(?P<scope>\w+)").unwrap();
+        match re
+            .captures(&content)
+            .map(|caps| Self::parse(&caps["scope"]))
+        {
+            Some(Ok(scope)) => scope,
+            Some(Err(e)) => {
+                println!("{:?}, registering commands per server", e);
+                CommandScope::Guild
+            }
+            None => CommandScope::Guild,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Target {
+    Global,
+    Guild(GuildId),
+}
+
+impl Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Target::Global => write!(f, "global scope"),
+            Target::Guild(id) => write!(f, "guild {id}"),
+        }
+    }
+}
+
+#[derive(Default)]
+struct CommandDiff {
+    created: Vec<String>,
+    updated: Vec<String>,
+    deleted: Vec<String>,
+    unchanged: usize,
+}
+
+impl Display for CommandDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "created [{}], updated [{}], deleted [{}], {} unchanged",
+            self.created.join(", "),
+            self.updated.join(", "),
+            self.deleted.join(", "),
+            self.unchanged
+        )
+    }
+}
+
+/// Strip a command down to its definition so that our builder output can be
+/// compared with what Discord returns. Unset and default values are dropped
+/// since Discord leaves some of them out and fills in others.
+fn normalize(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .filter(|(k, _)| DEFINITION_FIELDS.contains(&k.as_str()))
+                .map(|(k, v)| (k.clone(), normalize(v)))
+                .filter(|(_, v)| match v {
+                    Value::Null | Value::Bool(false) => false,
+                    Value::Array(a) => !a.is_empty(),
+                    Value::String(s) => !s.is_empty(),
+                    _ => true,
+                })
+                .collect::<Map<String, Value>>(),
+        ),
+        Value::Array(values) => Value::Array(values.iter().map(normalize).collect()),
+        v => v.clone(),
+    }
+}
+
+fn definition(command: &Value) -> Value {
+    let mut def = normalize(command);
+    if let Value::Object(map) = &mut def {
+        map.entry("type").or_insert(Value::from(CHAT_INPUT));
+    }
+    def
+}
+
+fn command_name(command: &Value) -> String {
+    command["name"].as_str().unwrap_or_default().to_owned()
+}
+
+async fn registered_commands(http: &Http, target: Target) -> Result<Vec<Value>> {
+    let application_id = http.application_id;
+    let route = match target {
+        Target::Global => RouteInfo::GetGlobalApplicationCommands { application_id },
+        Target::Guild(id) => RouteInfo::GetGuildApplicationCommands {
+            application_id,
+            guild_id: id.0,
+        },
+    };
+    // Fetched as JSON since the command model doesn't keep every field (e.g. autocomplete).
+    http.fire(Request::new(RequestBuilder::new(route)))
+        .await
+        .context(format!("Failed to retrieve the commands of the {target}"))
+}
+
+/// Bring the commands of the target in line with `desired`, only pushing the
+/// commands that were added, changed or removed.
+async fn sync(http: &Http, target: Target, desired: &[Value]) -> Result<CommandDiff> {
+    let registered = registered_commands(http, target).await?;
+    let mut diff = CommandDiff::default();
+
+    for command in desired {
+        let name = command_name(command);
+        let existing = registered.iter().find(|r| command_name(r) == name);
+        match existing {
+            Some(r) if definition(r) == definition(command) => diff.unchanged += 1,
+            Some(r) => {
+                let id = r["id"]
+                    .as_str()
+                    .and_then(|id| id.parse().ok())
+                    .ok_or(anyhow!("Registered command without an id: {name}"))?;
+                match target {
+                    Target::Global => http.edit_global_application_command(id, command).await,
+                    Target::Guild(guild) => {
+                        http.edit_guild_application_command(guild.0, id, command)
+                            .await
+                    }
+                }
+                .context(format!("Failed to update command in the {target}: {name}"))?;
+                diff.updated.push(name);
+            }
+            None => {
+                match target {
+                    Target::Global => http.create_global_application_command(command).await,
+                    Target::Guild(guild) => {
+                        http.create_guild_application_command(guild.0, command)
+                            .await
+                    }
+                }
+                .context(format!("Failed to create command in the {target}: {name}"))?;
+                diff.created.push(name);
+            }
+        }
+    }
+
+    for command in &registered {
+        let name = command_name(command);
+        if desired.iter().any(|d| command_name(d) == name) {
+            continue;
+        }
+        let id = command["id"]
+            .as_str()
+            .and_then(|id| id.parse().ok())
+            .ok_or(anyhow!("Registered command without an id: {name}"))?;
+        match target {
+            Target::Global => http.delete_global_application_command(id).await,
+            Target::Guild(guild) => http.delete_guild_application_command(guild.0, id).await,
+        }
+        .context(format!("Failed to delete command in the {target}: {name}"))?;
+        diff.deleted.push(name);
+    }
+
+    Ok(diff)
+}
+
+/// Register the commands in the configured scope and remove them from the
+/// other one, so that switching scopes doesn't leave duplicates behind.
+pub async fn register_commands(
+    http: &Http,
+    scope: CommandScope,
+    guilds: &[GuildId],
+    desired: &[Value],
+) {
+    let (global, guild): (&[Value], &[Value]) = match scope {
+        CommandScope::Global => (desired, &[]),
+        CommandScope::Guild => (&[], desired),
+    };
+
+    let targets = std::iter::once((Target::Global, global))
+        .chain(guilds.iter().map(|id| (Target::Guild(*id), guild)));
+    for (target, commands) in targets {
+        match sync(http, target, commands).await {
+            Ok(diff) => println!("Commands in the {target}: {diff}"),
+            Err(e) => println!("{:?}", e),
+        }
+    }
+}
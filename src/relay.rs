@@ -0,0 +1,192 @@
+use anyhow::{bail, Error, Result};
+use futures::TryFutureExt;
+use serenity::{
+    builder::CreateComponents,
+    model::{
+        channel::Message,
+        id::{ChannelId, GuildId, UserId},
+        interactions::message_component::ButtonStyle,
+    },
+};
+use sqlx::SqlitePool;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Name of the message context menu command.
+pub const RELAY_COMMAND: &str = "Relay this message to…";
+
+// The target can be picked for as long as the interaction token is valid.
+const RELAY_TTL: Duration = Duration::from_secs(15 * 60);
+
+// Discord allows at most 25 options in a select menu, longer lists are paged.
+const MAX_OPTIONS: usize = 25;
+
+const SERVER_PREFIX: &str = "relay-server";
+const CHANNEL_PREFIX: &str = "relay-channel";
+
+struct Pending {
+    user: UserId,
+    message: Message,
+    created: Instant,
+}
+
+/// Messages picked with the context menu command that wait for a target
+/// channel, keyed by the id of the command interaction.
+#[derive(Clone, Default)]
+pub struct Relays(Arc<Mutex<HashMap<u64, Pending>>>);
+
+impl Relays {
+    pub fn insert(&self, key: u64, user: UserId, message: Message) {
+        let mut map = self.0.lock().unwrap();
+        map.retain(|_, p| p.created.elapsed() < RELAY_TTL);
+        map.insert(
+            key,
+            Pending {
+                user,
+                message,
+                created: Instant::now(),
+            },
+        );
+    }
+
+    /// Remove and return the message if `user` is the one who picked it.
+    pub fn take(&self, key: u64, user: UserId) -> Result<Message> {
+        let mut map = self.0.lock().unwrap();
+        match map.get(&key) {
+            Some(p) if p.user != user => bail!("Only the user who picked the message can relay it"),
+            Some(p) if p.created.elapsed() < RELAY_TTL => (),
+            _ => {
+                map.remove(&key);
+                bail!("The selection timed out, run the command again")
+            }
+        }
+        Ok(map.remove(&key).unwrap().message)
+    }
+}
+
+pub enum Selection {
+    /// A page of the server menu.
+    Servers(usize),
+    /// A server picked from the menu, or a page of its channel menu.
+    Server(GuildId, usize),
+    Channel(ChannelId),
+}
+
+// Menus use `prefix:key`, their page buttons `prefix:key:page` and for the
+// channel menu also the server, `prefix:key:page:guild`.
+pub fn parse_relay_id(custom_id: &str, values: &[String]) -> Option<(Selection, u64)> {
+    let mut parts = custom_id.split(':');
+    let prefix = parts.next()?;
+    let key = parts.next()?.parse().ok()?;
+    let selection = match (prefix, parts.next(), parts.next()) {
+        (SERVER_PREFIX, None, _) => Selection::Server(GuildId(values.first()?.parse().ok()?), 0),
+        (CHANNEL_PREFIX, None, _) => Selection::Channel(ChannelId(values.first()?.parse().ok()?)),
+        (SERVER_PREFIX, Some(page), None) => Selection::Servers(page.parse().ok()?),
+        (CHANNEL_PREFIX, Some(page), Some(guild)) => {
+            Selection::Server(GuildId(guild.parse().ok()?), page.parse().ok()?)
+        }
+        _ => return None,
+    };
+    Some((selection, key))
+}
+
+/// Name and id of every server known to the bot.
+pub async fn servers(db: &SqlitePool) -> Result<Vec<(String, i64)>> {
+    sqlx::query!("SELECT id, name FROM Guilds ORDER BY name")
+        .fetch_all(db)
+        .and_then(|rows| async {
+            Ok(rows
+                .into_iter()
+                .map(|row| (row.name, row.id))
+                .collect::<Vec<(String, i64)>>())
+        })
+        .map_err(|e| Error::new(e).context("Failed to retrieve servers from the database"))
+        .await
+}
+
+/// Name and id of the channels of a server known to the bot.
+pub async fn channels(db: &SqlitePool, guild: &GuildId) -> Result<Vec<(String, i64)>> {
+    let guild = guild.0 as i64;
+    sqlx::query!(
+        "SELECT id, name FROM Channels WHERE guild = ? ORDER BY name",
+        guild
+    )
+    .fetch_all(db)
+    .and_then(|rows| async {
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.name, row.id))
+            .collect::<Vec<(String, i64)>>())
+    })
+    .map_err(|e| Error::new(e).context("Failed to retrieve channels from the database"))
+    .await
+}
+
+fn select_menu(
+    custom_id: String,
+    placeholder: &str,
+    options: &[(String, i64)],
+    page: usize,
+    page_id: impl Fn(usize) -> String,
+) -> CreateComponents {
+    let count = options.chunks(MAX_OPTIONS).count();
+    let page = page.min(count.saturating_sub(1));
+    let mut components = CreateComponents::default();
+    components.create_action_row(|row| {
+        row.create_select_menu(|menu| {
+            menu.custom_id(custom_id)
+                .placeholder(placeholder)
+                .options(|o| {
+                    for (name, id) in options.iter().skip(page * MAX_OPTIONS).take(MAX_OPTIONS) {
+                        o.create_option(|opt| opt.label(name).value(id));
+                    }
+                    o
+                })
+        })
+    });
+    if count > 1 {
+        components.create_action_row(|row| {
+            row.create_button(|b| {
+                b.style(ButtonStyle::Secondary)
+                    .label("Previous")
+                    .custom_id(page_id(page.saturating_sub(1)))
+                    .disabled(page == 0)
+            })
+            .create_button(|b| {
+                b.style(ButtonStyle::Secondary)
+                    .label("Next")
+                    .custom_id(page_id(page + 1))
+                    .disabled(page + 1 >= count)
+            })
+        });
+    }
+    components
+}
+
+pub fn server_select(key: u64, servers: &[(String, i64)], page: usize) -> CreateComponents {
+    select_menu(
+        format!("{SERVER_PREFIX}:{key}"),
+        "Target server",
+        servers,
+        page,
+        |page| format!("{SERVER_PREFIX}:{key}:{page}"),
+    )
+}
+
+pub fn channel_select(
+    key: u64,
+    guild: GuildId,
+    channels: &[(String, i64)],
+    page: usize,
+) -> CreateComponents {
+    select_menu(
+        format!("{CHANNEL_PREFIX}:{key}"),
+        "Target channel",
+        channels,
+        page,
+        |page| format!("{CHANNEL_PREFIX}:{key}:{page}:{guild}"),
+    )
+}
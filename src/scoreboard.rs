@@ -0,0 +1,257 @@
+use anyhow::{anyhow, Error, Result};
+use futures::TryFutureExt;
+use serenity::model::{channel::Message, id::MessageId};
+use sqlx::SqlitePool;
+
+use crate::search::SearchScope;
+
+// Most authors shown on the scoreboard.
+pub const MAX_AUTHORS: i64 = 25;
+
+const DAY: i64 = 24 * 60 * 60;
+
+/// How far back the scoreboard looks.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Period {
+    Day,
+    Week,
+    Month,
+    All,
+}
+
+impl Period {
+    pub fn parse(s: &str) -> Result<Self> {
+        match s {
+            "day" => Ok(Period::Day),
+            "week" => Ok(Period::Week),
+            "month" => Ok(Period::Month),
+            "all" => Ok(Period::All),
+            s => Err(anyhow!("Unknown period: {s}")),
+        }
+    }
+
+    /// Messages relayed before this time don't count, `None` for all of them.
+    /// Scores are kept per day, so this is rounded down to the day.
+    pub fn since(&self, now: i64) -> Option<i64> {
+        match self {
+            Period::Day => Some(now - DAY),
+            Period::Week => Some(now - 7 * DAY),
+            Period::Month => Some(now - 30 * DAY),
+            Period::All => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Period {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Period::Day => write!(f, "last 24 hours"),
+            Period::Week => write!(f, "last 7 days"),
+            Period::Month => write!(f, "last 30 days"),
+            Period::All => write!(f, "all time"),
+        }
+    }
+}
+
+/// What the authors are ranked by.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ranking {
+    Messages,
+    Reactions,
+    Targets,
+}
+
+impl Ranking {
+    pub fn parse(s: &str) -> Result<Self> {
+        match s {
+            "messages" => Ok(Ranking::Messages),
+            "reactions" => Ok(Ranking::Reactions),
+            "targets" => Ok(Ranking::Targets),
+            s => Err(anyhow!("Unknown ranking: {s}")),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Ranking::Messages => "messages",
+            Ranking::Reactions => "reactions",
+            Ranking::Targets => "targets",
+        }
+    }
+}
+
+/// Relay statistics of an author.
+#[derive(Debug, PartialEq)]
+pub struct Score {
+    pub author: i64,
+    pub author_name: String,
+    /// Messages relayed into at least one target channel.
+    pub messages: i64,
+    /// Reactions received on the relayed copies.
+    pub reactions: i64,
+    /// Distinct target channels the messages reached.
+    pub targets: i64,
+}
+
+impl Score {
+    pub fn line(&self, rank: usize) -> String {
+        format!(
+            "**{}.** <@{}> ({}): {} relayed · {} reactions · {} targets",
+            rank, self.author, self.author_name, self.messages, self.reactions, self.targets
+        )
+    }
+}
+
+/// The authors with the best scores over the relay history within the scope,
+/// optionally only counting messages from one source server.
+pub async fn scoreboard(
+    db: &SqlitePool,
+    scope: &SearchScope,
+    since: Option<i64>,
+    guild: Option<i64>,
+    ranking: Ranking,
+) -> Result<Vec<Score>> {
+    let since = since.unwrap_or(0) / DAY;
+    let ranking = ranking.as_str();
+    let user = scope.user.0 as i64;
+    sqlx::query_as!(
+        Score,
+        "
+        SELECT\n\
+        author as \"author: i64\",\n\
+        MAX(author_name) as \"author_name!: String\",\n\
+        SUM(messages) as \"messages!: i64\",\n\
+        SUM(reactions) as \"reactions!: i64\",\n\
+        COUNT(DISTINCT target) as \"targets!: i64\"\n\
+        FROM Scores\n\
+        WHERE (connection IN (SELECT id FROM Connections WHERE user = ?) OR guild = ?)\n\
+        AND day >= ? AND (? IS NULL OR guild = ?)\n\
+        GROUP BY author\n\
+        ORDER BY CASE ?\n\
+        WHEN 'messages' THEN SUM(messages)\n\
+        WHEN 'targets' THEN COUNT(DISTINCT target)\n\
+        ELSE SUM(reactions) END DESC,\n\
+        SUM(messages) DESC, author\n\
+        LIMIT ?
+        ",
+        user,
+        scope.guild,
+        since,
+        guild,
+        guild,
+        ranking,
+        MAX_AUTHORS
+    )
+    .fetch_all(db)
+    .map_err(|e| Error::new(e).context("Failed to retrieve the scoreboard from the database"))
+    .await
+}
+
+/// Count the message that was just archived as relayed (or buffered for a
+/// digest) through the connection.
+pub async fn record(db: &SqlitePool, msg: &Message, connection: i64) -> Result<()> {
+    let message = msg.id.0 as i64;
+    sqlx::query!(
+        "
+        INSERT INTO Scores (day, connection, author, author_name, guild, target, messages)\n\
+        SELECT\n\
+        relayed / 86400, connection, author, author_name, COALESCE(guild, 0), target,\n\
+        (SELECT COUNT(1) FROM Archive other WHERE other.message = Archive.message\n\
+        AND other.status IN ('delivered', 'buffered')) <= 1\n\
+        FROM Archive\n\
+        WHERE id = (SELECT MAX(id) FROM Archive WHERE message = ? AND connection = ?)\n\
+        ON CONFLICT (day, connection, author, guild) DO UPDATE SET\n\
+        messages = messages + excluded.messages, author_name = excluded.author_name
+        ",
+        message,
+        connection
+    )
+    .execute(db)
+    .await
+    .map_err(|e| Error::new(e).context("Failed to record the score in the database"))?;
+
+    Ok(())
+}
+
+/// Add the change in reactions on the relayed copy to the score it counts for.
+async fn add_reactions(db: &SqlitePool, copy: i64, delta: i64) -> Result<()> {
+    sqlx::query!(
+        "
+        UPDATE Scores SET reactions = MAX(reactions + ?, 0)\n\
+        WHERE EXISTS (SELECT 1 FROM Archive WHERE copy = ?\n\
+        AND Archive.connection = Scores.connection AND Archive.author = Scores.author\n\
+        AND COALESCE(Archive.guild, 0) = Scores.guild\n\
+        AND Archive.relayed / 86400 = Scores.day)
+        ",
+        delta,
+        copy
+    )
+    .execute(db)
+    .await
+    .map_err(|e| Error::new(e).context("Failed to count reaction in the database"))?;
+
+    Ok(())
+}
+
+/// Count a reaction added (1) or removed (-1) on a relayed copy, reactions
+/// on other messages are ignored.
+pub async fn count_reaction(db: &SqlitePool, copy: &MessageId, delta: i64) -> Result<()> {
+    let copy = copy.0 as i64;
+    let row = sqlx::query!("SELECT reactions FROM Archive WHERE copy = ?", copy)
+        .fetch_optional(db)
+        .await
+        .map_err(|e| Error::new(e).context("Failed to retrieve reactions from the database"))?;
+    let reactions = match row {
+        Some(row) => row.reactions,
+        None => return Ok(()),
+    };
+    // Reactions that were already gone aren't taken off again.
+    add_reactions(db, copy, std::cmp::max(delta, -reactions)).await?;
+    sqlx::query!(
+        "UPDATE Archive SET reactions = MAX(reactions + ?, 0) WHERE copy = ?",
+        delta,
+        copy
+    )
+    .execute(db)
+    .await
+    .map_err(|e| Error::new(e).context("Failed to count reaction in the database"))?;
+
+    Ok(())
+}
+
+pub async fn clear_reactions(db: &SqlitePool, copy: &MessageId) -> Result<()> {
+    let copy = copy.0 as i64;
+    let row = sqlx::query!("SELECT reactions FROM Archive WHERE copy = ?", copy)
+        .fetch_optional(db)
+        .await
+        .map_err(|e| Error::new(e).context("Failed to retrieve reactions from the database"))?;
+    if let Some(row) = row {
+        add_reactions(db, copy, -row.reactions).await?;
+    }
+    sqlx::query!("UPDATE Archive SET reactions = 0 WHERE copy = ?", copy)
+        .execute(db)
+        .await
+        .map_err(|e| Error::new(e).context("Failed to clear reactions in the database"))?;
+
+    Ok(())
+}
+
+/// Names of the source servers of the scores within the scope.
+pub async fn servers(db: &SqlitePool, scope: &SearchScope) -> Result<Vec<String>> {
+    let user = scope.user.0 as i64;
+    sqlx::query!(
+        "
+        SELECT DISTINCT Guilds.name\n\
+        FROM Scores\n\
+        JOIN Guilds ON Guilds.id = Scores.guild\n\
+        WHERE Scores.connection IN (SELECT id FROM Connections WHERE user = ?)\n\
+        OR Scores.guild = ?
+        ",
+        user,
+        scope.guild
+    )
+    .fetch_all(db)
+    .await
+    .map(|rows| rows.into_iter().map(|row| row.name).collect())
+    .map_err(|e| Error::new(e).context("Failed to retrieve scoreboard servers from the database"))
+}
@@ -0,0 +1,167 @@
+use anyhow::{anyhow, Error, Result};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serenity::model::id::UserId;
+use sqlx::SqlitePool;
+
+use crate::output::truncate;
+
+// Most messages a single search returns, the newest ones are kept.
+pub const MAX_RESULTS: i64 = 200;
+
+// Maximum number of characters of a message shown in the results.
+const EXCERPT_LEN: usize = 200;
+
+#[derive(Default)]
+pub struct SearchQuery {
+    /// Words that all have to appear in the message.
+    pub keywords: Option<String>,
+    pub author: Option<UserId>,
+    pub guild: Option<i64>,
+    /// Unix timestamps, both inclusive.
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+}
+
+impl SearchQuery {
+    pub fn is_empty(&self) -> bool {
+        self.keywords.is_none()
+            && self.author.is_none()
+            && self.guild.is_none()
+            && self.from.is_none()
+            && self.to.is_none()
+    }
+}
+
+/// Whose messages a search sees: those handled for the caller's connections
+/// and, when run in a server, that server's messages.
+pub struct SearchScope {
+    pub user: UserId,
+    pub guild: Option<i64>,
+}
+
+#[derive(sqlx::FromRow)]
+pub struct SearchResult {
+    pub message: i64,
+    pub author: String,
+    pub guild: Option<i64>,
+    pub guild_name: Option<String>,
+    pub source: i64,
+    pub content: String,
+    pub created: i64,
+}
+
+impl SearchResult {
+    pub fn link(&self) -> String {
+        let guild = self
+            .guild
+            .map(|g| g.to_string())
+            .unwrap_or_else(|| "@me".to_owned());
+        format!(
+            "https://discord.com/channels/{}/{}/{}",
+            guild, self.source, self.message
+        )
+    }
+}
+
+impl From<&SearchResult> for String {
+    fn from(r: &SearchResult) -> Self {
+        let excerpt = truncate(&r.content, EXCERPT_LEN).replace('\n', " ");
+        let guild = r.guild_name.as_deref().unwrap_or("Unknown server");
+        format!(
+            "**{}** <t:{}:f> <#{}> ({}) [Jump]({})\n> {}",
+            r.author,
+            r.created,
+            r.source,
+            guild,
+            r.link(),
+            excerpt
+        )
+    }
+}
+
+/// Start of the day in the `YYYY-MM-DD` format, `end_of_day` moves it to the
+/// last second of that day so that date ranges include their last day.
+pub fn parse_date(s: &str, end_of_day: bool) -> Result<DateTime<Utc>> {
+    let date = NaiveDate::parse_from_str(s.trim(), "%Y-%m-%d")
+        .map_err(|_| anyhow!("Invalid date (expected YYYY-MM-DD): {s}"))?;
+    let start = DateTime::from_utc(date.and_hms(0, 0, 0), Utc);
+    Ok(match end_of_day {
+        true => start + Duration::days(1) - Duration::seconds(1),
+        false => start,
+    })
+}
+
+/// `None` for blank keywords, they would match every message.
+pub fn parse_keywords(s: &str) -> Option<String> {
+    match s.trim() {
+        "" => None,
+        keywords => Some(keywords.to_owned()),
+    }
+}
+
+/// Every word quoted so that the keywords are matched literally instead of
+/// being read as FTS5 query syntax (AND, NEAR, column filters, ...).
+fn match_expression(keywords: &str) -> Option<String> {
+    let terms: Vec<String> = keywords
+        .split_whitespace()
+        .map(|w| format!("\"{}\"", w.replace('"', "\"\"")))
+        .collect();
+    match terms.is_empty() {
+        true => None,
+        false => Some(terms.join(" ")),
+    }
+}
+
+/// Archived messages within the scope matching the query, newest first. A
+/// message relayed to several targets is only listed once.
+pub async fn search(
+    db: &SqlitePool,
+    scope: &SearchScope,
+    query: &SearchQuery,
+) -> Result<Vec<SearchResult>> {
+    let keywords = query.keywords.as_deref().and_then(match_expression);
+    let author = query.author.map(|a| a.0 as i64);
+    let user = scope.user.0 as i64;
+    // Checked at runtime, the compile time checks can't analyse the FTS5 table.
+    sqlx::query_as::<_, SearchResult>(
+        "
+        SELECT\n\
+        Archive.message as message,\n\
+        Archive.author_name as author,\n\
+        Archive.guild as guild,\n\
+        Guilds.name as guild_name,\n\
+        Archive.source as source,\n\
+        Archive.content as content,\n\
+        Archive.created as created\n\
+        FROM Archive\n\
+        LEFT JOIN Guilds ON Guilds.id = Archive.guild\n\
+        WHERE (Archive.connection IN (SELECT id FROM Connections WHERE user = ?)\n\
+        OR Archive.guild = ?)\n\
+        AND (? IS NULL OR Archive.id IN\n\
+        (SELECT rowid FROM ArchiveSearch WHERE ArchiveSearch MATCH ?))\n\
+        AND (? IS NULL OR Archive.author = ?)\n\
+        AND (? IS NULL OR Archive.guild = ?)\n\
+        AND (? IS NULL OR Archive.created >= ?)\n\
+        AND (? IS NULL OR Archive.created <= ?)\n\
+        GROUP BY Archive.message\n\
+        ORDER BY Archive.created DESC\n\
+        LIMIT ?
+        ",
+    )
+    .bind(user)
+    .bind(scope.guild)
+    .bind(&keywords)
+    .bind(&keywords)
+    .bind(author)
+    .bind(author)
+    .bind(query.guild)
+    .bind(query.guild)
+    .bind(query.from)
+    .bind(query.from)
+    .bind(query.to)
+    .bind(query.to)
+    .bind(MAX_RESULTS)
+    .fetch_all(db)
+    .await
+    .map_err(|e| Error::new(e).context("Failed to search the archive in the database"))
+}
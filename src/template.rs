@@ -0,0 +1,319 @@
+use anyhow::{anyhow, Error, Result};
+use chrono::{DateTime, Utc};
+use futures::TryFutureExt;
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+use serenity::{builder::CreateEmbed, model::id::ChannelId, utils::Color};
+use sqlx::SqlitePool;
+
+use crate::output::{
+    self, OutgoingMessage, EMBED_DESCRIPTION_LIMIT, EMBED_FIELD_LIMIT, EMBED_FOOTER_LIMIT,
+    EMBED_TITLE_LIMIT,
+};
+
+static PLACEHOLDER: Lazy<Regex> = Lazy::new(|| Regex::new(r"\{(?P<name>[a-z_]+)\}").unwrap());
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum MentionPlacement {
+    /// Before the relayed content (in the message content for embeds).
+    Above,
+    /// After the relayed content (in a follow-up message for embeds).
+    Below,
+    /// Only where the `{mentions}` placeholder is used.
+    Inline,
+}
+
+impl MentionPlacement {
+    pub fn parse(s: &str) -> Result<Self> {
+        match s {
+            "above" => Ok(MentionPlacement::Above),
+            "below" => Ok(MentionPlacement::Below),
+            "inline" => Ok(MentionPlacement::Inline),
+            s => Err(anyhow!("Unknown mention placement: {s}")),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MentionPlacement::Above => "above",
+            MentionPlacement::Below => "below",
+            MentionPlacement::Inline => "inline",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Template {
+    pub embed: bool,
+    pub color: u32,
+    pub title: Option<String>,
+    pub body: String,
+    pub footer: Option<String>,
+    pub timestamp: bool,
+    pub mentions: MentionPlacement,
+    /// Link back to the original message and name its server and channel.
+    pub provenance: bool,
+}
+
+impl Default for Template {
+    fn default() -> Self {
+        Template {
+            embed: true,
+            color: Color::GOLD.0,
+            title: None,
+            body: "{content}".to_owned(),
+            footer: None,
+            timestamp: true,
+            mentions: MentionPlacement::Above,
+            provenance: true,
+        }
+    }
+}
+
+/// Values substituted for the `{...}` placeholders of a template.
+pub struct Placeholders {
+    pub author: String,
+    pub source_channel: String,
+    pub source_guild: String,
+    pub content: String,
+    pub jump_url: String,
+    pub mentions: Vec<String>,
+    /// Symbols found in the content, shown as tags on the relayed message.
+    pub tickers: Vec<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// A relayed message ready to be sent through a webhook.
+#[derive(Clone)]
+pub struct RenderedMessage {
+    pub content: String,
+    /// Long content is continued over several embeds.
+    pub embeds: Vec<CreateEmbed>,
+    /// Sent as a separate message after the main one.
+    pub followup: Option<String>,
+}
+
+impl RenderedMessage {
+    /// The messages to send, split to fit within Discord's limits.
+    pub fn messages(&self) -> Vec<OutgoingMessage> {
+        let mut messages = output::split_message(&self.content, self.embeds.clone());
+        if let Some(followup) = &self.followup {
+            messages.extend(output::split_message(followup, Vec::new()));
+        }
+        messages
+    }
+}
+
+fn substitute(text: &str, p: &Placeholders) -> String {
+    PLACEHOLDER
+        .replace_all(text, |caps: &Captures| match &caps["name"] {
+            "author" => p.author.clone(),
+            "source_channel" => p.source_channel.clone(),
+            "source_guild" => p.source_guild.clone(),
+            "content" => p.content.clone(),
+            "jump_url" => p.jump_url.clone(),
+            "mentions" => p.mentions.join(" "),
+            _ => caps[0].to_owned(),
+        })
+        .into_owned()
+}
+
+fn non_empty(s: String) -> Option<String> {
+    match s.trim().is_empty() {
+        true => None,
+        false => Some(s),
+    }
+}
+
+impl Template {
+    pub fn render(&self, p: &Placeholders) -> RenderedMessage {
+        let mentions = p.mentions.join("\n");
+        let body = substitute(&self.body, p);
+        let title = self.title.as_ref().map(|t| substitute(t, p));
+        let footer = self.footer.as_ref().map(|f| substitute(f, p));
+        let tags = p
+            .tickers
+            .iter()
+            .map(|t| format!("`{t}`"))
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        if self.embed {
+            let mut chunks = output::split_text(&body, EMBED_DESCRIPTION_LIMIT);
+            if chunks.is_empty() {
+                chunks.push(String::new());
+            }
+            let last = chunks.len() - 1;
+            let mut embeds = Vec::new();
+            for (i, chunk) in chunks.into_iter().enumerate() {
+                let mut embed = CreateEmbed::default();
+                embed.description(chunk).color(self.color);
+                // The header goes on the first embed and the footer on the last one.
+                if i == 0 {
+                    if let Some(title) = &title {
+                        embed.title(output::truncate(title, EMBED_TITLE_LIMIT));
+                    }
+                    if self.provenance {
+                        let name = format!("{} • {}", p.source_guild, p.source_channel);
+                        embed.author(|a| {
+                            a.name(output::truncate(&name, EMBED_TITLE_LIMIT))
+                                .url(&p.jump_url)
+                        });
+                    }
+                }
+                if i == last {
+                    if !tags.is_empty() {
+                        embed.field("Tickers", output::truncate(&tags, EMBED_FIELD_LIMIT), false);
+                    }
+                    if let Some(footer) = &footer {
+                        embed.footer(|f| f.text(output::truncate(footer, EMBED_FOOTER_LIMIT)));
+                    }
+                    if self.timestamp {
+                        embed.timestamp(p.timestamp);
+                    }
+                }
+                embeds.push(embed);
+            }
+            let (content, followup) = match self.mentions {
+                MentionPlacement::Above => (mentions, None),
+                MentionPlacement::Below => (String::new(), non_empty(mentions)),
+                // Mentions in an embed don't ping, so they go in the content as well.
+                MentionPlacement::Inline => (mentions, None),
+            };
+            RenderedMessage {
+                content,
+                embeds,
+                followup,
+            }
+        } else {
+            let mut lines: Vec<String> = Vec::new();
+            if let Some(title) = title {
+                lines.push(format!("**{title}**"));
+            }
+            if self.mentions == MentionPlacement::Above {
+                lines.push(mentions.clone());
+            }
+            lines.push(body);
+            if self.mentions == MentionPlacement::Below {
+                lines.push(mentions);
+            }
+            if !tags.is_empty() {
+                lines.push(format!("Tickers: {tags}"));
+            }
+            if self.provenance {
+                // The angle brackets suppress the link preview of the original message.
+                lines.push(format!(
+                    "[{} • {}](<{}>)",
+                    p.source_guild, p.source_channel, p.jump_url
+                ));
+            }
+            match (footer, self.timestamp) {
+                (Some(footer), true) => {
+                    lines.push(format!("*{footer}* • <t:{}:f>", p.timestamp.timestamp()))
+                }
+                (Some(footer), false) => lines.push(format!("*{footer}*")),
+                (None, true) => lines.push(format!("<t:{}:f>", p.timestamp.timestamp())),
+                (None, false) => (),
+            }
+            RenderedMessage {
+                content: lines
+                    .into_iter()
+                    .filter_map(non_empty)
+                    .collect::<Vec<String>>()
+                    .join("\n"),
+                embeds: Vec::new(),
+                followup: None,
+            }
+        }
+    }
+}
+
+pub async fn get_template(db: &SqlitePool, connection: i64) -> Result<Template> {
+    let row = sqlx::query!(
+        "
+        SELECT embed, color, title, body, footer, timestamp, mentions, provenance\n\
+        FROM Templates\n\
+        WHERE connection = ?
+        ",
+        connection
+    )
+    .fetch_optional(db)
+    .map_err(|e| Error::new(e).context("Failed to retrieve template from the database"))
+    .await?;
+
+    match row {
+        Some(row) => Ok(Template {
+            embed: row.embed,
+            color: row.color as u32,
+            title: row.title,
+            body: row.body,
+            footer: row.footer,
+            timestamp: row.timestamp,
+            mentions: MentionPlacement::parse(&row.mentions)?,
+            provenance: row.provenance,
+        }),
+        None => Ok(Template::default()),
+    }
+}
+
+pub async fn set_template(db: &SqlitePool, connection: i64, template: &Template) -> Result<()> {
+    let color = template.color as i64;
+    let mentions = template.mentions.as_str();
+    sqlx::query!(
+        "
+        INSERT OR REPLACE INTO Templates\n\
+        (connection, embed, color, title, body, footer, timestamp, mentions, provenance)\n\
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+        ",
+        connection,
+        template.embed,
+        color,
+        template.title,
+        template.body,
+        template.footer,
+        template.timestamp,
+        mentions,
+        template.provenance
+    )
+    .execute(db)
+    .await
+    .map_err(|e| Error::new(e).context("Failed to store template in the database"))?;
+
+    Ok(())
+}
+
+/// Server and channel names of a channel known to the bot, used for the
+/// `{source_guild}` and `{source_channel}` placeholders.
+pub async fn channel_names(db: &SqlitePool, channel: &ChannelId) -> Result<(String, String)> {
+    let id = channel.0 as i64;
+    let row = sqlx::query!(
+        "
+        SELECT\n\
+        Guilds.name as guild_name,\n\
+        Channels.name as channel_name\n\
+        FROM Channels\n\
+        JOIN Guilds\n\
+        ON Channels.guild = Guilds.id\n\
+        WHERE Channels.id = ?
+        ",
+        id
+    )
+    .fetch_optional(db)
+    .map_err(|e| Error::new(e).context("Failed to retrieve channel names from the database"))
+    .await?;
+
+    Ok(match row {
+        Some(row) => (row.guild_name, row.channel_name),
+        None => ("Unknown server".to_owned(), format!("<#{}>", channel)),
+    })
+}
+
+/// Parse a colour in the `#RRGGBB` format.
+pub fn parse_color(s: &str) -> Result<u32> {
+    let hex = s.trim().trim_start_matches('#');
+    match hex.len() {
+        6 => u32::from_str_radix(hex, 16)
+            .map_err(|_| anyhow!("Invalid colour (expected #RRGGBB): {s}")),
+        _ => Err(anyhow!("Invalid colour (expected #RRGGBB): {s}")),
+    }
+}
@@ -0,0 +1,1879 @@
+//! Relay, connection and mention logic exercised against an in-memory
+//! database and the in-memory Discord fake.
+
+use serde_json::json;
+use serenity::{
+    builder::CreateApplicationCommands,
+    model::{
+        channel::Message,
+        id::{ChannelId, GuildId, UserId, WebhookId},
+        interactions::{autocomplete::AutocompleteInteraction, InteractionResponseType},
+    },
+};
+use sqlx::{sqlite::SqlitePoolOptions, SqlitePool};
+use std::{
+    collections::HashSet,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
+
+use crate::{
+    alert,
+    archive::{self, Retention},
+    backfill_messages, command_response,
+    commands::{opt, CommandTiming, Registry},
+    condition::{MentionCondition, Subject},
+    confirm::{Confirmations, PendingAction},
+    create_server_mapping, dedup, digest, get_channel_webhook, get_mentions, get_relay_connection,
+    handle_message, handle_relay_select, maybe_add_connection, message_source,
+    output::Paginator,
+    ratelimit::{self, Overflow, RateLimiter},
+    reaction,
+    relay::{self, Relays, Selection},
+    relay_message, restore_queued, run_command,
+    scoreboard::{self, Period, Ranking},
+    search::{self, SearchQuery, SearchScope},
+    send_alerts,
+    template::{self, MentionPlacement},
+    thread::{self, ChannelKind},
+    ticker, transform,
+    transport::fake::FakeDiscord,
+    trash, CommandResponse, RelayContext, RelayState,
+};
+
+const SOURCE_GUILD: u64 = 1;
+const TARGET_GUILD: u64 = 2;
+const SOURCE: u64 = 11;
+const OTHER_SOURCE: u64 = 12;
+const TARGET: u64 = 21;
+const USER: u64 = 100;
+const OTHER_USER: u64 = 101;
+
+async fn test_db() -> SqlitePool {
+    // Every connection gets its own in-memory database, so there can only be one.
+    let db = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    sqlx::migrate!("./migrations").run(&db).await.unwrap();
+    db
+}
+
+/// A database with a source and a target server, mapped through the fake.
+async fn setup() -> (SqlitePool, FakeDiscord) {
+    let db = test_db().await;
+    let discord = FakeDiscord::default();
+    discord.add_guild(
+        SOURCE_GUILD,
+        "Source Server",
+        &[(SOURCE, "general"), (OTHER_SOURCE, "news")],
+    );
+    discord.add_guild(TARGET_GUILD, "Target Server", &[(TARGET, "relay")]);
+    for guild in [SOURCE_GUILD, TARGET_GUILD] {
+        create_server_mapping(&db, &discord, &GuildId(guild))
+            .await
+            .unwrap();
+    }
+    (db, discord)
+}
+
+async fn connect(db: &SqlitePool, source: u64, target: u64, user: u64) -> WebhookId {
+    let webhook = get_channel_webhook(db, &ChannelId(target)).await.unwrap();
+    let created = maybe_add_connection(
+        db,
+        &ChannelId(source),
+        &ChannelId(target),
+        &UserId(user),
+        &webhook,
+        None,
+    )
+    .await
+    .unwrap();
+    assert!(created);
+    webhook
+}
+
+async fn add_mention(db: &SqlitePool, source: Option<u64>, target: u64, mention: &str) {
+    add_conditional_mention(db, source, target, mention, None).await
+}
+
+async fn add_conditional_mention(
+    db: &SqlitePool,
+    source: Option<u64>,
+    target: u64,
+    mention: &str,
+    condition: Option<MentionCondition>,
+) {
+    let source = source.map(|s| s as i64);
+    let target = target as i64;
+    let user = USER as i64;
+    sqlx::query(
+        "INSERT INTO Mentions (source, target, mention, user, condition_kind, condition) \
+        VALUES (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(source)
+    .bind(target)
+    .bind(mention)
+    .bind(user)
+    .bind(condition.as_ref().map(|c| c.kind()))
+    .bind(condition.as_ref().map(|c| c.value()))
+    .execute(db)
+    .await
+    .unwrap();
+}
+
+/// Handle the message like the bot does, with alert and rate limits of its own.
+async fn relay(db: &SqlitePool, discord: &FakeDiscord, msg: &Message) -> anyhow::Result<()> {
+    handle_message(db, discord, discord, &RelayState::default(), msg).await
+}
+
+/// Relay the messages over the connection from `source` to `target` the way
+/// /connect backfills them.
+async fn backfill(db: &SqlitePool, discord: &FakeDiscord, target: u64, messages: &[Message]) {
+    let (id,): (i64,) = sqlx::query_as("SELECT id FROM Connections WHERE target = ?")
+        .bind(target as i64)
+        .fetch_one(db)
+        .await
+        .unwrap();
+    let connection = get_relay_connection(db, id).await.unwrap();
+    let source = message_source(db, messages[0].channel_id, None)
+        .await
+        .unwrap();
+    let state = RelayState::default();
+    let cx = RelayContext {
+        db,
+        sender: discord,
+        resolver: discord,
+        state: &state,
+    };
+    backfill_messages(&cx, &source, &connection, messages).await;
+}
+
+fn message(channel: u64, author: u64, bot: bool, content: &str) -> Message {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(500);
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    serde_json::from_value(json!({
+        "id": id.to_string(),
+        "channel_id": channel.to_string(),
+        "guild_id": SOURCE_GUILD.to_string(),
+        "author": {
+            "id": author.to_string(),
+            "username": "trader",
+            "discriminator": "0001",
+            "avatar": null,
+            "bot": bot,
+        },
+        "content": content,
+        "timestamp": "2026-10-18T12:00:00+00:00",
+        "edited_timestamp": null,
+        "tts": false,
+        "mention_everyone": false,
+        "mentions": [],
+        "mention_roles": [],
+        "attachments": [],
+        "embeds": [],
+        "pinned": false,
+        "type": 0,
+    }))
+    .unwrap()
+}
+
+#[tokio::test]
+async fn server_mapping_creates_a_webhook_per_channel() {
+    let (db, _discord) = setup().await;
+    let channels: Vec<(i64, String, i64)> =
+        sqlx::query_as("SELECT id, name, guild FROM Channels ORDER BY id")
+            .fetch_all(&db)
+            .await
+            .unwrap();
+    assert_eq!(
+        channels,
+        vec![
+            (SOURCE as i64, "#general".to_owned(), SOURCE_GUILD as i64),
+            (OTHER_SOURCE as i64, "#news".to_owned(), SOURCE_GUILD as i64),
+            (TARGET as i64, "#relay".to_owned(), TARGET_GUILD as i64),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn connection_is_only_created_once() {
+    let (db, _discord) = setup().await;
+    let webhook = connect(&db, SOURCE, TARGET, USER).await;
+    let created = maybe_add_connection(
+        &db,
+        &ChannelId(SOURCE),
+        &ChannelId(TARGET),
+        &UserId(USER),
+        &webhook,
+        None,
+    )
+    .await
+    .unwrap();
+    assert!(!created);
+}
+
+#[tokio::test]
+async fn message_is_relayed_through_the_target_webhook() {
+    let (db, discord) = setup().await;
+    let webhook = connect(&db, SOURCE, TARGET, USER).await;
+
+    relay(
+        &db,
+        &discord,
+        &message(SOURCE, USER, false, "BTC breaking out"),
+    )
+    .await
+    .unwrap();
+
+    let sent = discord.sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].webhook, webhook);
+    assert_eq!(sent[0].channel, ChannelId(TARGET));
+    assert_eq!(sent[0].author, "trader");
+    assert_eq!(sent[0].embed.as_deref(), Some("BTC breaking out"));
+}
+
+#[tokio::test]
+async fn only_messages_of_the_connection_user_are_relayed() {
+    let (db, discord) = setup().await;
+    connect(&db, SOURCE, TARGET, USER).await;
+
+    relay(
+        &db,
+        &discord,
+        &message(SOURCE, OTHER_USER, false, "not mine"),
+    )
+    .await
+    .unwrap();
+    relay(&db, &discord, &message(SOURCE, USER, true, "from a bot"))
+        .await
+        .unwrap();
+    relay(
+        &db,
+        &discord,
+        &message(OTHER_SOURCE, USER, false, "elsewhere"),
+    )
+    .await
+    .unwrap();
+
+    assert!(discord.sent().is_empty());
+}
+
+#[tokio::test]
+async fn mentions_match_the_source_or_any_source() {
+    let (db, _discord) = setup().await;
+    add_mention(&db, Some(SOURCE), TARGET, "<@&1>").await;
+    add_mention(&db, None, TARGET, "<@&2>").await;
+    add_mention(&db, Some(OTHER_SOURCE), TARGET, "<@&3>").await;
+    add_mention(&db, None, TARGET, "<@&2>").await;
+
+    let subject = Subject::new("", UserId(USER));
+    let mentions = get_mentions(
+        &db,
+        &ChannelId(TARGET),
+        &ChannelId(SOURCE),
+        &UserId(USER),
+        &subject,
+    )
+    .await
+    .unwrap();
+    assert_eq!(mentions, vec!["<@&1>", "<@&2>"]);
+
+    let other_user = get_mentions(
+        &db,
+        &ChannelId(TARGET),
+        &ChannelId(SOURCE),
+        &UserId(OTHER_USER),
+        &subject,
+    )
+    .await
+    .unwrap();
+    assert!(other_user.is_empty());
+}
+
+#[tokio::test]
+async fn relayed_message_carries_the_mentions() {
+    let (db, discord) = setup().await;
+    connect(&db, SOURCE, TARGET, USER).await;
+    add_mention(&db, Some(SOURCE), TARGET, "<@&1>").await;
+    add_mention(&db, None, TARGET, "<@&2>").await;
+
+    relay(&db, &discord, &message(SOURCE, USER, false, "ETH update"))
+        .await
+        .unwrap();
+
+    let sent = discord.sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].content, "<@&1>\n<@&2>");
+}
+
+#[tokio::test]
+async fn conditional_mentions_only_apply_to_matching_messages() {
+    let (db, _discord) = setup().await;
+    let conditions = [
+        (
+            "<@&1>",
+            MentionCondition::parse("keyword", "Breakout").unwrap(),
+        ),
+        (
+            "<@&2>",
+            MentionCondition::parse("regex", r"\bpt\s*\d+").unwrap(),
+        ),
+        ("<@&3>", MentionCondition::parse("ticker", "$btc").unwrap()),
+        ("<@&4>", MentionCondition::Author(UserId(OTHER_USER))),
+    ];
+    for (mention, condition) in conditions {
+        add_conditional_mention(&db, Some(SOURCE), TARGET, mention, Some(condition)).await;
+    }
+    add_mention(&db, None, TARGET, "<@&5>").await;
+
+    let mentions = |content: &'static str, author: u64| {
+        let db = db.clone();
+        async move {
+            get_mentions(
+                &db,
+                &ChannelId(TARGET),
+                &ChannelId(SOURCE),
+                &UserId(USER),
+                &Subject::new(content, UserId(author)),
+            )
+            .await
+            .unwrap()
+        }
+    };
+    assert_eq!(mentions("quiet day", USER).await, vec!["<@&5>"]);
+    assert_eq!(
+        mentions("BREAKOUT on BTC/USDT, PT 70000", USER).await,
+        vec!["<@&1>", "<@&2>", "<@&3>", "<@&5>"]
+    );
+    assert_eq!(
+        mentions("quiet day", OTHER_USER).await,
+        vec!["<@&4>", "<@&5>"]
+    );
+
+    // Loaded rules keep their compiled pattern and compare by its source.
+    let regex = MentionCondition::parse("regex", r" \bpt\s*\d+ ").unwrap();
+    assert_eq!(
+        regex,
+        MentionCondition::parse("regex", r"\bpt\s*\d+").unwrap()
+    );
+    assert_eq!(regex.value(), r"\bpt\s*\d+");
+    assert_eq!(regex.to_string(), r"matches `\bpt\s*\d+`");
+    assert!(MentionCondition::parse("regex", "(unclosed").is_err());
+    assert!(MentionCondition::parse("colour", "red").is_err());
+}
+
+#[tokio::test]
+async fn alerts_are_sent_once_and_rate_limited() {
+    let (db, discord) = setup().await;
+    connect(&db, SOURCE, TARGET, USER).await;
+    let subscriber = UserId(OTHER_USER);
+    let guild = GuildId(TARGET_GUILD);
+    let keyword = MentionCondition::parse("keyword", "btc").unwrap();
+    let ticker = MentionCondition::parse("ticker", "BTC").unwrap();
+    assert!(alert::add_alert(&db, &subscriber, &guild, &keyword)
+        .await
+        .unwrap());
+    assert!(!alert::add_alert(&db, &subscriber, &guild, &keyword)
+        .await
+        .unwrap());
+    assert!(alert::add_alert(&db, &subscriber, &guild, &ticker)
+        .await
+        .unwrap());
+    // Users don't get alerts for their own messages.
+    alert::add_alert(&db, &UserId(USER), &guild, &keyword)
+        .await
+        .unwrap();
+    // Nor for messages of servers their alert isn't in.
+    alert::add_alert(&db, &UserId(99), &GuildId(9), &keyword)
+        .await
+        .unwrap();
+
+    let state = RelayState::default();
+    let handle = |content: String| {
+        let (db, discord, state) = (&db, &discord, &state);
+        async move {
+            let msg = message(SOURCE, USER, false, &content);
+            handle_message(db, discord, discord, state, &msg)
+                .await
+                .unwrap();
+            send_alerts(db, discord, &state.alerts, &msg).await.unwrap()
+        }
+    };
+    handle("$BTC to the moon".to_owned()).await;
+    handle("$BTC   to the MOON".to_owned()).await;
+    handle("ETH only".to_owned()).await;
+    let dms = discord.dms();
+    assert_eq!(dms.len(), 1);
+    assert_eq!(dms[0].user, subscriber);
+    assert!(dms[0].content.contains("keyword: btc"));
+    assert_eq!(dms[0].embed.as_deref(), Some("$BTC to the moon"));
+
+    for i in 0..10 {
+        handle(format!("BTC update {i}")).await;
+    }
+    assert_eq!(discord.dms().len(), 5);
+    assert_eq!(discord.sent().len(), 13);
+
+    let removed = alert::parse_alert("keyword: btc").unwrap();
+    alert::remove_alert(&db, &subscriber, &guild, &removed)
+        .await
+        .unwrap();
+    assert_eq!(
+        alert::get_alerts(&db, &subscriber, &guild).await.unwrap(),
+        vec![ticker]
+    );
+    assert!(alert::remove_alert(&db, &subscriber, &guild, &removed)
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn inline_mentions_still_ping_in_embed_templates() {
+    let (db, discord) = setup().await;
+    connect(&db, SOURCE, TARGET, USER).await;
+    add_mention(&db, None, TARGET, "<@&42>").await;
+    let (id,): (i64,) = sqlx::query_as("SELECT id FROM Connections")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    let template = template::Template {
+        body: "{mentions} {content}".to_owned(),
+        mentions: MentionPlacement::Inline,
+        ..Default::default()
+    };
+    template::set_template(&db, id, &template).await.unwrap();
+
+    relay(&db, &discord, &message(SOURCE, USER, false, "BTC long"))
+        .await
+        .unwrap();
+    let sent = discord.sent();
+    assert_eq!(sent[0].embed.as_deref(), Some("<@&42> BTC long"));
+    assert_eq!(sent[0].content, "<@&42>");
+}
+
+#[tokio::test]
+async fn digest_connection_buffers_until_due() {
+    let (db, discord) = setup().await;
+    connect(&db, SOURCE, TARGET, USER).await;
+    sqlx::query("UPDATE Connections SET digest_interval = 3600, next_digest = ?")
+        .bind(digest::unix_now() + 3600)
+        .execute(&db)
+        .await
+        .unwrap();
+
+    relay(&db, &discord, &message(SOURCE, USER, false, "first"))
+        .await
+        .unwrap();
+    relay(&db, &discord, &message(SOURCE, USER, false, "second"))
+        .await
+        .unwrap();
+    digest::post_due_digests(&db, &discord, &RateLimiter::default())
+        .await
+        .unwrap();
+    assert!(discord.sent().is_empty());
+
+    sqlx::query("UPDATE Connections SET next_digest = 0")
+        .execute(&db)
+        .await
+        .unwrap();
+    digest::post_due_digests(&db, &discord, &RateLimiter::default())
+        .await
+        .unwrap();
+
+    let sent = discord.sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].author, "Analyst Bot Digest");
+    let digest = sent[0].embed.clone().unwrap();
+    assert!(digest.contains("first") && digest.contains("second"));
+
+    let (buffered,): (i32,) = sqlx::query_as("SELECT COUNT(1) FROM DigestBuffer")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_eq!(buffered, 0);
+}
+
+#[tokio::test]
+async fn digest_pages_that_were_posted_are_not_posted_again() {
+    let (db, discord) = setup().await;
+    connect(&db, SOURCE, TARGET, USER).await;
+    sqlx::query("UPDATE Connections SET digest_interval = 3600, next_digest = 0")
+        .execute(&db)
+        .await
+        .unwrap();
+    // About 250 characters per entry, well over a page of them.
+    for i in 0..30 {
+        let content = format!("call {i} {}", "x".repeat(180));
+        relay(&db, &discord, &message(SOURCE, USER, false, &content))
+            .await
+            .unwrap();
+    }
+
+    discord.fail_after(Some(1));
+    digest::post_due_digests(&db, &discord, &RateLimiter::default())
+        .await
+        .unwrap();
+    assert_eq!(discord.sent().len(), 1);
+    let first_page = discord.sent()[0].embed.clone().unwrap();
+    assert!(first_page.contains("call 0 "));
+
+    discord.fail_after(None);
+    sqlx::query("UPDATE Connections SET next_digest = 0")
+        .execute(&db)
+        .await
+        .unwrap();
+    digest::post_due_digests(&db, &discord, &RateLimiter::default())
+        .await
+        .unwrap();
+    let sent = discord.sent();
+    assert!(sent.len() > 1);
+    assert!(sent[1..]
+        .iter()
+        .all(|m| !m.embed.as_deref().unwrap().contains("call 0 ")));
+    assert!(sent[sent.len() - 1]
+        .embed
+        .as_deref()
+        .unwrap()
+        .contains("call 29 "));
+
+    assert!(digest::DigestSchedule::parse("hourly", Some(9)).is_err());
+}
+
+#[tokio::test]
+async fn handled_messages_are_archived_until_past_retention() {
+    let (db, discord) = setup().await;
+    connect(&db, SOURCE, TARGET, USER).await;
+
+    relay(&db, &discord, &message(SOURCE, USER, false, "SOL long"))
+        .await
+        .unwrap();
+    let archived: Vec<(String, i64, String)> =
+        sqlx::query_as("SELECT content, target, status FROM Archive")
+            .fetch_all(&db)
+            .await
+            .unwrap();
+    assert_eq!(
+        archived,
+        vec![("SOL long".to_owned(), TARGET as i64, "delivered".to_owned())]
+    );
+
+    let now = digest::unix_now();
+    let retention = Retention::parse("30").unwrap();
+    assert_eq!(archive::prune(&db, retention, now).await.unwrap(), 0);
+    let forever = Retention::parse("0").unwrap();
+    let later = now + 31 * 24 * 60 * 60;
+    assert_eq!(archive::prune(&db, forever, later).await.unwrap(), 0);
+    assert_eq!(archive::prune(&db, retention, later).await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn search_matches_keywords_author_and_dates() {
+    let (db, discord) = setup().await;
+    connect(&db, SOURCE, TARGET, USER).await;
+    connect(&db, SOURCE, TARGET, OTHER_USER).await;
+    for (user, content) in [
+        (USER, "BTC breakout above resistance"),
+        (USER, "ETH looks weak"),
+        (OTHER_USER, "BTC \"breakout\" OR NOT"),
+    ] {
+        relay(&db, &discord, &message(SOURCE, user, false, content))
+            .await
+            .unwrap();
+    }
+
+    let contents = |results: Vec<search::SearchResult>| -> Vec<String> {
+        results.into_iter().map(|r| r.content).collect()
+    };
+    let everything = SearchScope {
+        user: UserId(USER),
+        guild: Some(SOURCE_GUILD as i64),
+    };
+    let query = SearchQuery {
+        keywords: Some("btc breakout".to_owned()),
+        ..Default::default()
+    };
+    let results = search::search(&db, &everything, &query).await.unwrap();
+    assert_eq!(results.len(), 2);
+    assert!(results[0]
+        .link()
+        .ends_with(&format!("/{SOURCE}/{}", results[0].message)));
+
+    let query = SearchQuery {
+        keywords: Some("breakout".to_owned()),
+        author: Some(UserId(USER)),
+        ..Default::default()
+    };
+    let results = search::search(&db, &everything, &query).await.unwrap();
+    assert_eq!(contents(results), vec!["BTC breakout above resistance"]);
+
+    let query = SearchQuery {
+        author: Some(UserId(USER)),
+        guild: Some(SOURCE_GUILD as i64),
+        from: Some(search::parse_date("2026-10-18", false).unwrap().timestamp()),
+        to: Some(search::parse_date("2026-10-18", true).unwrap().timestamp()),
+        ..Default::default()
+    };
+    assert_eq!(
+        search::search(&db, &everything, &query)
+            .await
+            .unwrap()
+            .len(),
+        2
+    );
+
+    let query = SearchQuery {
+        from: Some(search::parse_date("2026-10-19", false).unwrap().timestamp()),
+        ..Default::default()
+    };
+    assert!(search::search(&db, &everything, &query)
+        .await
+        .unwrap()
+        .is_empty());
+
+    let query = SearchQuery {
+        keywords: search::parse_keywords("btc"),
+        ..Default::default()
+    };
+    let own = SearchScope {
+        user: UserId(OTHER_USER),
+        guild: None,
+    };
+    let results = search::search(&db, &own, &query).await.unwrap();
+    assert_eq!(contents(results), vec!["BTC \"breakout\" OR NOT"]);
+    let outsider = SearchScope {
+        user: UserId(999),
+        guild: Some(TARGET_GUILD as i64),
+    };
+    assert!(search::search(&db, &outsider, &query)
+        .await
+        .unwrap()
+        .is_empty());
+
+    assert_eq!(search::parse_keywords("  \t "), None);
+    assert_eq!(search::parse_keywords(" btc "), Some("btc".to_owned()));
+}
+
+#[test]
+fn tickers_are_extracted_in_order_without_duplicates() {
+    let tickers = ticker::extract(
+        "Long $aapl and BTC/USDT, hedge with AAPL240119C00150000 or SPY 450P 1/19. $AAPL again, $100 isn't one",
+    );
+    let symbols: Vec<&str> = tickers.iter().map(|t| t.symbol.as_str()).collect();
+    assert_eq!(
+        symbols,
+        vec!["AAPL", "BTC/USDT", "AAPL240119C00150000", "SPY 450P 1/19"]
+    );
+    let underlying: Vec<&str> = tickers.iter().map(|t| t.underlying.as_str()).collect();
+    assert_eq!(underlying, vec!["AAPL", "BTC", "AAPL", "SPY"]);
+
+    assert_eq!(
+        ticker::parse_symbols("$btc eth").unwrap(),
+        vec!["BTC", "ETH"]
+    );
+    assert_eq!(
+        ticker::parse_symbols("SPY 450P 1/19, $aapl").unwrap(),
+        vec!["SPY 450P 1/19", "AAPL"]
+    );
+    assert!(ticker::parse_symbols("BTC; DROP TABLE").is_err());
+}
+
+#[tokio::test]
+async fn watchlist_only_lets_matching_messages_through() {
+    let (db, discord) = setup().await;
+    connect(&db, SOURCE, TARGET, USER).await;
+    let (id,): (i64,) = sqlx::query_as("SELECT id FROM Connections")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    let added = ticker::add_to_watchlist(&db, id, &["BTC".to_owned(), "TSLA".to_owned()])
+        .await
+        .unwrap();
+    assert_eq!(added.len(), 2);
+
+    for content in [
+        "$AAPL earnings",
+        "BTC/USDT reclaiming the range",
+        "no tickers",
+    ] {
+        relay(&db, &discord, &message(SOURCE, USER, false, content))
+            .await
+            .unwrap();
+    }
+    let sent = discord.sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(
+        sent[0].embed.as_deref(),
+        Some("BTC/USDT reclaiming the range")
+    );
+
+    let (tickers,): (String,) = sqlx::query_as("SELECT tickers FROM Archive")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_eq!(tickers, "BTC/USDT");
+
+    let removed = ticker::remove_from_watchlist(&db, id, &["BTC".to_owned(), "TSLA".to_owned()])
+        .await
+        .unwrap();
+    assert_eq!(removed.len(), 2);
+    relay(&db, &discord, &message(SOURCE, USER, false, "no tickers"))
+        .await
+        .unwrap();
+    assert_eq!(discord.sent().len(), 2);
+}
+
+#[tokio::test]
+async fn messages_over_the_rate_limit_are_queued_or_collapsed() {
+    let (db, discord) = setup().await;
+    connect(&db, SOURCE, TARGET, USER).await;
+    connect(&db, OTHER_SOURCE, TARGET, USER).await;
+    let ids: Vec<(i64,)> = sqlx::query_as("SELECT id FROM Connections ORDER BY source")
+        .fetch_all(&db)
+        .await
+        .unwrap();
+    ratelimit::set_connection_limit(&db, ids[0].0, Some(1), None)
+        .await
+        .unwrap();
+    ratelimit::set_connection_limit(&db, ids[1].0, Some(1), Some(Overflow::Collapse))
+        .await
+        .unwrap();
+
+    let state = RelayState::default();
+    for i in 0..3 {
+        for source in [SOURCE, OTHER_SOURCE] {
+            let msg = message(source, USER, false, &format!("update {i}"));
+            handle_message(&db, &discord, &discord, &state, &msg)
+                .await
+                .unwrap();
+        }
+    }
+    assert_eq!(discord.sent().len(), 2);
+    let statuses: Vec<(String, i32)> =
+        sqlx::query_as("SELECT status, COUNT(1) FROM Archive GROUP BY status ORDER BY status")
+            .fetch_all(&db)
+            .await
+            .unwrap();
+    assert_eq!(
+        statuses,
+        vec![
+            ("collapsed".to_owned(), 2),
+            ("delivered".to_owned(), 2),
+            ("queued".to_owned(), 2)
+        ]
+    );
+
+    // A new limit starts with a full bucket, the queue is sent in order.
+    ratelimit::set_connection_limit(&db, ids[0].0, Some(10), None)
+        .await
+        .unwrap();
+    let msg = message(SOURCE, USER, false, "update 3");
+    handle_message(&db, &discord, &discord, &state, &msg)
+        .await
+        .unwrap();
+    ratelimit::send_due(&db, &discord, &state).await;
+    let sent: Vec<Option<String>> = discord.sent().into_iter().map(|m| m.embed).collect();
+    assert_eq!(sent.len(), 5);
+    assert_eq!(sent[2].as_deref(), Some("update 1"));
+    assert_eq!(sent[4].as_deref(), Some("update 3"));
+}
+
+#[tokio::test]
+async fn duplicates_are_relayed_once_and_annotated() {
+    let (db, discord) = setup().await;
+    connect(&db, SOURCE, TARGET, USER).await;
+    connect(&db, OTHER_SOURCE, TARGET, USER).await;
+    let settings = dedup::Settings {
+        window: Duration::from_secs(600),
+        annotate: true,
+    };
+    dedup::set_settings(&db, &ChannelId(TARGET), Some(settings))
+        .await
+        .unwrap();
+    assert_eq!(
+        dedup::get_settings(&db, &ChannelId(TARGET)).await.unwrap(),
+        Some(settings)
+    );
+
+    let state = RelayState::default();
+    for (source, content) in [
+        (SOURCE, "BTC long @everyone"),
+        (OTHER_SOURCE, "btc   LONG <@&555>"),
+        (OTHER_SOURCE, "BTC long"),
+        (OTHER_SOURCE, "ETH short"),
+    ] {
+        let msg = message(source, USER, false, content);
+        handle_message(&db, &discord, &discord, &state, &msg)
+            .await
+            .unwrap();
+    }
+    let sent = discord.sent();
+    assert_eq!(sent.len(), 2);
+    assert_eq!(sent[0].edits, 1);
+    assert!(sent[0]
+        .content
+        .ends_with("*Also posted in **Source Server** #news*"));
+    assert_eq!(sent[0].embed.as_deref(), Some("BTC long @\u{200b}everyone"));
+
+    let (duplicates,): (i32,) =
+        sqlx::query_as("SELECT COUNT(1) FROM Archive WHERE status = 'duplicate'")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+    assert_eq!(duplicates, 2);
+}
+
+#[tokio::test]
+async fn messages_that_are_not_posted_do_not_count_as_relayed() {
+    let (db, discord) = setup().await;
+    connect(&db, SOURCE, TARGET, USER).await;
+    let settings = dedup::Settings {
+        window: Duration::from_secs(600),
+        annotate: false,
+    };
+    dedup::set_settings(&db, &ChannelId(TARGET), Some(settings))
+        .await
+        .unwrap();
+
+    // The post fails, the retry is relayed.
+    let state = RelayState::default();
+    discord.fail_after(Some(0));
+    let msg = message(SOURCE, USER, false, "BTC long");
+    handle_message(&db, &discord, &discord, &state, &msg)
+        .await
+        .unwrap();
+    discord.fail_after(None);
+    handle_message(&db, &discord, &discord, &state, &msg)
+        .await
+        .unwrap();
+    assert_eq!(discord.sent().len(), 1);
+
+    // Dropped by the rate limit, relayed once it allows it again.
+    let (id,): (i64,) = sqlx::query_as("SELECT id FROM Connections")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    ratelimit::set_connection_limit(&db, id, Some(1), Some(Overflow::Drop))
+        .await
+        .unwrap();
+    let msg = message(SOURCE, USER, false, "ETH short");
+    handle_message(&db, &discord, &discord, &state, &msg)
+        .await
+        .unwrap();
+    ratelimit::set_connection_limit(&db, id, None, None)
+        .await
+        .unwrap();
+    handle_message(&db, &discord, &discord, &state, &msg)
+        .await
+        .unwrap();
+    assert_eq!(discord.sent().len(), 2);
+}
+
+#[tokio::test]
+async fn queued_messages_are_annotated_with_their_duplicates() {
+    let (db, discord) = setup().await;
+    connect(&db, SOURCE, TARGET, USER).await;
+    connect(&db, OTHER_SOURCE, TARGET, USER).await;
+    let settings = dedup::Settings {
+        window: Duration::from_secs(600),
+        annotate: true,
+    };
+    dedup::set_settings(&db, &ChannelId(TARGET), Some(settings))
+        .await
+        .unwrap();
+    let (id,): (i64,) = sqlx::query_as("SELECT id FROM Connections WHERE source = ?")
+        .bind(SOURCE as i64)
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    ratelimit::set_connection_limit(&db, id, Some(1), Some(Overflow::Queue))
+        .await
+        .unwrap();
+
+    let state = RelayState::default();
+    for (source, content) in [
+        (SOURCE, "ETH short"),
+        (SOURCE, "BTC long"),
+        (OTHER_SOURCE, "BTC long"),
+    ] {
+        let msg = message(source, USER, false, content);
+        handle_message(&db, &discord, &discord, &state, &msg)
+            .await
+            .unwrap();
+    }
+    assert_eq!(discord.sent().len(), 1);
+
+    // The raised limit applies from the next message on.
+    ratelimit::set_connection_limit(&db, id, Some(10), None)
+        .await
+        .unwrap();
+    let msg = message(SOURCE, USER, false, "ETH target hit");
+    handle_message(&db, &discord, &discord, &state, &msg)
+        .await
+        .unwrap();
+    ratelimit::send_due(&db, &discord, &state).await;
+    let sent = discord.sent();
+    assert_eq!(sent.len(), 3);
+    assert_eq!(sent[1].edits, 1);
+    assert!(sent[1]
+        .content
+        .ends_with("*Also posted in **Source Server** #news*"));
+}
+
+#[tokio::test]
+async fn relayed_content_is_transformed() {
+    let (db, discord) = setup().await;
+    connect(&db, SOURCE, TARGET, USER).await;
+    let (id,): (i64,) = sqlx::query_as("SELECT id FROM Connections")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    let rule = transform::Rule::new(r"ref=\w+", "ref=***").unwrap();
+    transform::add_rule(&db, id, &rule).await.unwrap();
+    assert!(transform::Rule::new("(unclosed", "").is_err());
+
+    let content = "Join discord.gg/abc123 now, see <#12> and <@999> ref=xyz @everyone";
+    relay(&db, &discord, &message(SOURCE, USER, false, content))
+        .await
+        .unwrap();
+    assert_eq!(
+        discord.sent()[0].embed.as_deref(),
+        Some("Join now, see #news and @unknown-user ref=*** @\u{200b}everyone")
+    );
+
+    // So is what /relay posts and what goes into a digest.
+    let msg = message(SOURCE, USER, false, content);
+    relay_message(&db, &discord, &msg, &ChannelId(TARGET), &UserId(USER))
+        .await
+        .unwrap();
+    assert_eq!(discord.sent()[1].embed, discord.sent()[0].embed);
+    digest::buffer_message(&db, id, &msg).await.unwrap();
+    let (buffered,): (String,) = sqlx::query_as("SELECT content FROM DigestBuffer")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_eq!(
+        buffered,
+        "Join now, see #news and @unknown-user ref=*** @\u{200b}everyone"
+    );
+
+    transform::remove_rule(&db, id, r"ref=\w+").await.unwrap();
+    assert!(transform::get_rules(&db, id).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn source_threads_are_mirrored_into_target_threads_and_forum_posts() {
+    let (db, discord) = setup().await;
+    discord.add_guild(3, "Forum Server", &[(31, "ideas")]);
+    discord.set_kind(31, ChannelKind::Forum);
+    create_server_mapping(&db, &discord, &GuildId(3))
+        .await
+        .unwrap();
+    connect(&db, SOURCE, TARGET, USER).await;
+    let forum_webhook = connect(&db, SOURCE, 31, USER).await;
+    discord.add_thread(SOURCE_GUILD, 13, "btc-calls", SOURCE);
+    thread::sync_threads(&db, &discord, GuildId(SOURCE_GUILD))
+        .await
+        .unwrap();
+
+    for content in ["BTC long", "BTC target hit"] {
+        relay(&db, &discord, &message(13, USER, false, content))
+            .await
+            .unwrap();
+    }
+    let mirrors = discord.threads(TARGET);
+    assert_eq!(mirrors.len(), 1);
+    assert_eq!(mirrors[0].name, "btc-calls");
+    let (to_forum, to_target): (Vec<_>, Vec<_>) = discord
+        .sent()
+        .into_iter()
+        .partition(|m| m.webhook == forum_webhook);
+    assert!(to_target.iter().all(|m| m.thread == Some(mirrors[0].id)));
+    let post = to_forum[0].thread;
+    assert_eq!(to_forum[0].post_name.as_deref(), Some("btc-calls"));
+    assert_eq!(to_forum[1].thread, post);
+    assert_eq!(to_forum[1].post_name, None);
+
+    // Outside of threads every message starts a post of its own in a forum.
+    relay(
+        &db,
+        &discord,
+        &message(SOURCE, USER, false, "ETH short\nentry 3000"),
+    )
+    .await
+    .unwrap();
+    let sent = discord.sent();
+    assert_eq!(sent[4].thread, None);
+    assert_eq!(sent[5].post_name.as_deref(), Some("ETH short"));
+}
+
+#[tokio::test]
+async fn messages_relayed_into_news_channels_are_published() {
+    let (db, discord) = setup().await;
+    discord.add_guild(3, "News Server", &[(31, "announcements")]);
+    discord.set_kind(31, ChannelKind::News);
+    create_server_mapping(&db, &discord, &GuildId(3))
+        .await
+        .unwrap();
+    let news_webhook = connect(&db, SOURCE, 31, USER).await;
+    connect(&db, SOURCE, TARGET, USER).await;
+    let connections: Vec<(i64,)> = sqlx::query_as("SELECT id FROM Connections")
+        .fetch_all(&db)
+        .await
+        .unwrap();
+    for (id,) in connections {
+        thread::set_publish(&db, id, true).await.unwrap();
+    }
+
+    relay(&db, &discord, &message(SOURCE, USER, false, "BTC long"))
+        .await
+        .unwrap();
+    let sent = discord.sent();
+    assert_eq!(sent.len(), 2);
+    // Text channels have no followers to publish to.
+    assert!(sent
+        .iter()
+        .all(|m| m.published == (m.webhook == news_webhook)));
+    // Past the crosspost limit messages are still relayed, just not published.
+    for i in 0..10 {
+        relay(
+            &db,
+            &discord,
+            &message(SOURCE, USER, false, &format!("BTC {i}")),
+        )
+        .await
+        .unwrap();
+    }
+    let news: Vec<_> = discord
+        .sent()
+        .into_iter()
+        .filter(|m| m.webhook == news_webhook)
+        .collect();
+    assert_eq!(news.len(), 11);
+    assert_eq!(news.iter().filter(|m| m.published).count(), 10);
+    assert!(!news[10].published);
+}
+
+#[tokio::test]
+async fn reactions_are_mirrored_on_the_relayed_copies() {
+    let (db, discord) = setup().await;
+    let webhook = connect(&db, SOURCE, TARGET, USER).await;
+    connect(&db, SOURCE, OTHER_SOURCE, USER).await;
+    let (id,): (i64,) = sqlx::query_as("SELECT id FROM Connections WHERE target = ?")
+        .bind(TARGET as i64)
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    reaction::set_mirroring(&db, id, true).await.unwrap();
+
+    let limiter = RateLimiter::default();
+    let msg = message(SOURCE, USER, false, "BTC long");
+    relay(&db, &discord, &msg).await.unwrap();
+    discord.set_reactions(msg.id.0, &[("✅", 2), ("🎯", 1)]);
+    reaction::mirror(&db, &discord, &discord, &limiter, msg.channel_id, msg.id)
+        .await
+        .unwrap();
+    let sent = discord.sent();
+    let (mirrored, other): (Vec<_>, Vec<_>) = sent.iter().partition(|m| m.webhook == webhook);
+    assert!(mirrored[0].content.ends_with("-# ✅ 2 · 🎯 1"));
+    assert_eq!(mirrored[0].embed.as_deref(), Some("BTC long"));
+    assert_eq!(other[0].edits, 0);
+
+    // Once the reactions are gone so is the footer.
+    discord.set_reactions(msg.id.0, &[]);
+    reaction::mirror(&db, &discord, &discord, &limiter, msg.channel_id, msg.id)
+        .await
+        .unwrap();
+    let copy = discord
+        .sent()
+        .into_iter()
+        .find(|m| m.webhook == webhook)
+        .unwrap();
+    assert!(!copy.content.contains("-#"));
+    assert_eq!(copy.edits, 2);
+}
+
+#[tokio::test]
+async fn reaction_footer_and_duplicate_note_are_kept_together() {
+    let (db, discord) = setup().await;
+    connect(&db, SOURCE, TARGET, USER).await;
+    connect(&db, OTHER_SOURCE, TARGET, USER).await;
+    let settings = dedup::Settings {
+        window: Duration::from_secs(600),
+        annotate: true,
+    };
+    dedup::set_settings(&db, &ChannelId(TARGET), Some(settings))
+        .await
+        .unwrap();
+    let (id,): (i64,) = sqlx::query_as("SELECT id FROM Connections WHERE source = ?")
+        .bind(SOURCE as i64)
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    reaction::set_mirroring(&db, id, true).await.unwrap();
+
+    let state = RelayState::default();
+    let limiter = RateLimiter::default();
+    let msg = message(SOURCE, USER, false, "BTC long");
+    handle_message(&db, &discord, &discord, &state, &msg)
+        .await
+        .unwrap();
+    discord.set_reactions(msg.id.0, &[("✅", 2)]);
+    reaction::mirror(&db, &discord, &discord, &limiter, msg.channel_id, msg.id)
+        .await
+        .unwrap();
+
+    // The note on the duplicate keeps the footer, and the other way round.
+    let duplicate = message(OTHER_SOURCE, USER, false, "BTC long");
+    handle_message(&db, &discord, &discord, &state, &duplicate)
+        .await
+        .unwrap();
+    let note = "*Also posted in **Source Server** #news*";
+    assert!(discord.sent()[0]
+        .content
+        .ends_with(&format!("{note}\n-# ✅ 2")));
+    discord.set_reactions(msg.id.0, &[("✅", 3)]);
+    reaction::mirror(&db, &discord, &discord, &limiter, msg.channel_id, msg.id)
+        .await
+        .unwrap();
+    let copy = &discord.sent()[0];
+    assert!(copy.content.ends_with(&format!("{note}\n-# ✅ 3")));
+    assert_eq!(copy.edits, 3);
+
+    // Reactions in a burst are mirrored with a single edit.
+    let pending = reaction::PendingMirrors::default();
+    assert!(pending.schedule(msg.id));
+    assert!(!pending.schedule(msg.id));
+    pending.start(msg.id);
+    assert!(pending.schedule(msg.id));
+}
+
+#[tokio::test]
+async fn scoreboard_ranks_authors_by_their_relayed_messages() {
+    let (db, discord) = setup().await;
+    connect(&db, SOURCE, TARGET, USER).await;
+    connect(&db, SOURCE, TARGET, OTHER_USER).await;
+    connect(&db, SOURCE, OTHER_SOURCE, OTHER_USER).await;
+    relay(&db, &discord, &message(SOURCE, USER, false, "BTC long"))
+        .await
+        .unwrap();
+    relay(
+        &db,
+        &discord,
+        &message(SOURCE, OTHER_USER, false, "ETH short"),
+    )
+    .await
+    .unwrap();
+    relay(
+        &db,
+        &discord,
+        &message(SOURCE, OTHER_USER, false, "SOL long"),
+    )
+    .await
+    .unwrap();
+
+    let copy = discord.sent()[0].id;
+    for delta in [1, 1, -1, 1] {
+        scoreboard::count_reaction(&db, &copy, delta).await.unwrap();
+    }
+    let since = Period::Day.since(digest::unix_now());
+    let everything = SearchScope {
+        user: UserId(USER),
+        guild: Some(SOURCE_GUILD as i64),
+    };
+    let by_reactions = scoreboard::scoreboard(&db, &everything, since, None, Ranking::Reactions)
+        .await
+        .unwrap();
+    let scores: Vec<_> = by_reactions
+        .iter()
+        .map(|s| (s.author as u64, s.messages, s.reactions, s.targets))
+        .collect();
+    assert_eq!(scores, vec![(USER, 1, 2, 1), (OTHER_USER, 2, 0, 2)]);
+
+    let by_messages = scoreboard::scoreboard(&db, &everything, since, None, Ranking::Messages)
+        .await
+        .unwrap();
+    assert_eq!(by_messages[0].author as u64, OTHER_USER);
+    let other_server = Some(TARGET_GUILD as i64);
+    assert!(
+        scoreboard::scoreboard(&db, &everything, since, other_server, Ranking::Messages)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    // Only the scores of the caller's connections count outside the source server.
+    let own = SearchScope {
+        user: UserId(USER),
+        guild: Some(TARGET_GUILD as i64),
+    };
+    let scores: Vec<_> = scoreboard::scoreboard(&db, &own, since, None, Ranking::Messages)
+        .await
+        .unwrap()
+        .iter()
+        .map(|s| (s.author as u64, s.messages))
+        .collect();
+    assert_eq!(scores, vec![(USER, 1)]);
+    let outsider = SearchScope {
+        user: UserId(999),
+        guild: None,
+    };
+    assert!(
+        scoreboard::scoreboard(&db, &outsider, since, None, Ranking::Messages)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    assert_eq!(
+        scoreboard::servers(&db, &own).await.unwrap(),
+        vec!["Source Server"]
+    );
+    assert!(scoreboard::servers(&db, &outsider)
+        .await
+        .unwrap()
+        .is_empty());
+
+    // Pruning the archive leaves the scores, backfilled messages and those
+    // buffered for a digest count as well.
+    let later = digest::unix_now() + 60 * 24 * 60 * 60;
+    let retention = Retention::parse("30").unwrap();
+    assert_eq!(archive::prune(&db, retention, later).await.unwrap(), 5);
+    backfill(
+        &db,
+        &discord,
+        OTHER_SOURCE,
+        &[message(SOURCE, OTHER_USER, false, "ADA long")],
+    )
+    .await;
+    sqlx::query("UPDATE Connections SET digest_interval = 3600 WHERE target = ?")
+        .bind(TARGET as i64)
+        .execute(&db)
+        .await
+        .unwrap();
+    relay(&db, &discord, &message(SOURCE, USER, false, "DOGE long"))
+        .await
+        .unwrap();
+    let scores: Vec<_> = scoreboard::scoreboard(&db, &everything, since, None, Ranking::Reactions)
+        .await
+        .unwrap()
+        .iter()
+        .map(|s| (s.author as u64, s.messages, s.reactions, s.targets))
+        .collect();
+    assert_eq!(scores, vec![(USER, 2, 2, 1), (OTHER_USER, 3, 0, 2)]);
+}
+
+#[tokio::test]
+async fn deleted_connection_is_restored_by_undo() {
+    let (db, discord) = setup().await;
+    connect(&db, SOURCE, TARGET, USER).await;
+    let (id,): (i64,) = sqlx::query_as("SELECT id FROM Connections")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+
+    ticker::add_to_watchlist(&db, id, &["BTC".to_owned()])
+        .await
+        .unwrap();
+
+    let user = UserId(USER);
+    trash::delete_connections(&db, &user, "disconnect", &[id])
+        .await
+        .unwrap();
+    relay(&db, &discord, &message(SOURCE, USER, false, "gone"))
+        .await
+        .unwrap();
+    assert!(discord.sent().is_empty());
+
+    let restored = trash::undo(&db, &user).await.unwrap();
+    assert_eq!(restored.connections, 1);
+    let (id,): (i64,) = sqlx::query_as("SELECT id FROM Connections")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_eq!(ticker::get_watchlist(&db, id).await.unwrap(), vec!["BTC"]);
+    relay(&db, &discord, &message(SOURCE, USER, false, "$BTC back"))
+        .await
+        .unwrap();
+    assert_eq!(discord.sent().len(), 1);
+}
+
+#[tokio::test]
+async fn undo_does_not_attach_to_a_connection_that_reused_the_id() {
+    let (db, discord) = setup().await;
+    connect(&db, SOURCE, TARGET, USER).await;
+    let (id,): (i64,) = sqlx::query_as("SELECT id FROM Connections")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    ticker::add_to_watchlist(&db, id, &["BTC".to_owned()])
+        .await
+        .unwrap();
+
+    let user = UserId(USER);
+    trash::delete_connections(&db, &user, "disconnect", &[id])
+        .await
+        .unwrap();
+    connect(&db, OTHER_SOURCE, TARGET, USER).await;
+
+    let restored = trash::undo(&db, &user).await.unwrap();
+    assert_eq!(restored.connections, 1);
+    let connections: Vec<(i64, i64)> =
+        sqlx::query_as("SELECT id, source FROM Connections ORDER BY id")
+            .fetch_all(&db)
+            .await
+            .unwrap();
+    assert_eq!(connections.len(), 2);
+    for (id, source) in connections {
+        let watchlist = ticker::get_watchlist(&db, id).await.unwrap();
+        if source == SOURCE as i64 {
+            assert_eq!(watchlist, vec!["BTC"]);
+        } else {
+            assert!(watchlist.is_empty());
+        }
+    }
+
+    relay(
+        &db,
+        &discord,
+        &message(OTHER_SOURCE, USER, false, "no tickers"),
+    )
+    .await
+    .unwrap();
+    assert_eq!(discord.sent().len(), 1);
+}
+
+#[tokio::test]
+async fn undo_does_not_duplicate_mentions_that_were_added_again() {
+    let (db, _discord) = setup().await;
+    add_mention(&db, Some(SOURCE), TARGET, "<@&1>").await;
+    let (id,): (i64,) = sqlx::query_as("SELECT id FROM Mentions")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+
+    let user = UserId(USER);
+    trash::delete_mentions(&db, &user, "remove-mention", &[id])
+        .await
+        .unwrap();
+    add_mention(&db, Some(SOURCE), TARGET, "<@&1>").await;
+
+    let restored = trash::undo(&db, &user).await.unwrap();
+    assert_eq!(restored.mentions, 0);
+    let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM Mentions")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_eq!(count, 1);
+}
+
+#[tokio::test]
+async fn long_command_responses_are_paginated() {
+    let discord = FakeDiscord::default();
+    let pages = Paginator::default();
+    let rsp = CommandResponse {
+        title: "Connection List".to_owned(),
+        msg: "> line\n".repeat(1000),
+        ..Default::default()
+    };
+
+    command_response(
+        Ok(rsp),
+        false,
+        &pages,
+        &Confirmations::default(),
+        &discord,
+        1,
+        UserId(USER),
+    )
+    .await;
+
+    let responses = discord.responses();
+    assert_eq!(responses.len(), 1);
+    let first = &responses[0];
+    assert_eq!(
+        first.kind,
+        Some(InteractionResponseType::ChannelMessageWithSource)
+    );
+    assert_eq!(first.title, "Connection List");
+    assert_eq!(first.index, 0);
+    assert!(first.count > 1);
+    assert!(!first.public);
+    let (_, second, count) = pages.get(1, 1).unwrap();
+    assert_eq!(count, first.count);
+    assert!(second.starts_with("> line"));
+}
+
+#[test]
+fn every_command_is_declared_once_and_found_by_name() {
+    let commands = Registry::default();
+    let mut declared = CreateApplicationCommands::default();
+    commands.create_commands(&mut declared);
+
+    let mut names = HashSet::new();
+    for command in &declared.0 {
+        let name = command["name"].as_str().unwrap();
+        assert!(names.insert(name), "{name} is declared twice");
+        assert_eq!(commands.get(name).unwrap().name(), name);
+
+        let options = command["options"].as_array().cloned().unwrap_or_default();
+        let mut option_names = HashSet::new();
+        for option in &options {
+            let option_name = option["name"].as_str().unwrap();
+            assert!(
+                option_names.insert(option_name),
+                "{name} repeats {option_name}"
+            );
+        }
+    }
+    assert!(names.contains(relay::RELAY_COMMAND));
+    assert!(commands.get("unknown").is_none());
+}
+
+#[test]
+fn autocompleted_options_share_their_names() {
+    let mut declared = CreateApplicationCommands::default();
+    Registry::default().create_commands(&mut declared);
+
+    let autocompleted: HashSet<&str> = declared
+        .0
+        .iter()
+        .filter_map(|command| command["options"].as_array())
+        .flatten()
+        .filter(|option| option["autocomplete"] == json!(true))
+        .map(|option| option["name"].as_str().unwrap())
+        .collect();
+    let shared = [
+        opt::TARGET_SERVER,
+        opt::TARGET_CHANNEL,
+        opt::SERVER,
+        opt::THREAD,
+        opt::ALERT,
+    ];
+    assert_eq!(autocompleted, shared.into_iter().collect());
+}
+
+fn autocomplete(command: &str, options: serde_json::Value) -> AutocompleteInteraction {
+    serde_json::from_value(json!({
+        "id": "1",
+        "application_id": "2",
+        "type": 4,
+        "data": {
+            "id": "3",
+            "name": command,
+            "type": 1,
+            "options": options,
+        },
+        "guild_id": TARGET_GUILD.to_string(),
+        "channel_id": TARGET.to_string(),
+        "user": {
+            "id": USER.to_string(),
+            "username": "trader",
+            "discriminator": "0001",
+            "avatar": null,
+        },
+        "token": "token",
+        "version": 1,
+        "locale": "en-US",
+    }))
+    .unwrap()
+}
+
+#[tokio::test]
+async fn connect_autocompletes_the_server_and_needs_a_target_channel() {
+    let (db, _discord) = setup().await;
+    let commands = Registry::default();
+    let connect = commands.get("connect").unwrap();
+
+    let server =
+        json!([{ "name": opt::TARGET_SERVER, "type": 3, "value": "Tar", "focused": true }]);
+    let rsp = connect
+        .autocomplete(&db, &autocomplete("connect", server))
+        .await
+        .unwrap();
+    assert_eq!(rsp.options[0], "Target Server");
+
+    let no_channel = json!([{ "name": opt::TARGET_SERVER, "type": 3, "value": "Target Server" }]);
+    let e = connect
+        .autocomplete(&db, &autocomplete("connect", no_channel))
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(e.to_string(), "No target channel");
+}
+
+#[tokio::test]
+async fn slow_commands_are_deferred_and_left_to_finish() {
+    let discord = FakeDiscord::default();
+    let finished = AtomicBool::new(false);
+    let run = async {
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        finished.store(true, Ordering::SeqCst);
+        Ok(CommandResponse {
+            title: "Done".to_owned(),
+            ..Default::default()
+        })
+    };
+
+    run_command(
+        "slow",
+        CommandTiming::Immediate,
+        true,
+        run,
+        &Paginator::default(),
+        &Confirmations::default(),
+        &discord,
+        1,
+        UserId(USER),
+    )
+    .await;
+
+    assert!(finished.load(Ordering::SeqCst));
+    let responses = discord.responses();
+    assert_eq!(responses.len(), 2);
+    assert_eq!(
+        responses[0].kind,
+        Some(InteractionResponseType::DeferredChannelMessageWithSource)
+    );
+    assert!(responses[0].public);
+    assert_eq!(responses[1].kind, None);
+    assert_eq!(responses[1].title, "Done");
+}
+
+#[tokio::test]
+async fn confirmations_are_kept_to_one_page() {
+    let discord = FakeDiscord::default();
+    let pages = Paginator::default();
+    let confirmations = Confirmations::default();
+    let rsp = CommandResponse {
+        title: "Wipe Connections?".to_owned(),
+        msg: "> [Server] #channel: **1** connection(s)\n".repeat(500),
+        confirm: Some(PendingAction::WipeConnections {
+            server_name: "Server".to_owned(),
+        }),
+        ..Default::default()
+    };
+
+    command_response(
+        Ok(rsp),
+        false,
+        &pages,
+        &confirmations,
+        &discord,
+        1,
+        UserId(USER),
+    )
+    .await;
+
+    let responses = discord.responses();
+    assert_eq!(responses[0].count, 1);
+    assert!(responses[0].msg.ends_with('…'));
+    assert!(pages.get(1, 1).is_none());
+    assert!(confirmations.take(1, UserId(USER)).is_ok());
+}
+
+#[tokio::test]
+async fn errors_edit_the_deferred_response() {
+    let discord = FakeDiscord::default();
+
+    command_response(
+        Err(anyhow::anyhow!("Connection already exists")),
+        true,
+        &Paginator::default(),
+        &Confirmations::default(),
+        &discord,
+        1,
+        UserId(USER),
+    )
+    .await;
+
+    let responses = discord.responses();
+    assert_eq!(responses.len(), 1);
+    assert_eq!(responses[0].kind, None);
+    assert!(responses[0].error);
+    assert_eq!(responses[0].title, "Error");
+    assert_eq!(responses[0].msg, "Connection already exists");
+}
+
+#[test]
+fn relay_menus_are_paged() {
+    let channels: Vec<(String, i64)> = (0..30).map(|i| (format!("channel-{i}"), i)).collect();
+    let components = relay::channel_select(7, GuildId(SOURCE_GUILD), &channels, 1);
+    assert_eq!(components.0.len(), 2);
+    let options = components.0[0]["components"][0]["options"]
+        .as_array()
+        .unwrap();
+    assert_eq!(options.len(), 5);
+
+    let previous = components.0[1]["components"][0]["custom_id"]
+        .as_str()
+        .unwrap();
+    match relay::parse_relay_id(previous, &[]) {
+        Some((Selection::Server(guild, 0), 7)) => assert_eq!(guild, GuildId(SOURCE_GUILD)),
+        _ => panic!("Unexpected page button: {previous}"),
+    }
+
+    let short = relay::channel_select(7, GuildId(SOURCE_GUILD), &channels[..3], 0);
+    assert_eq!(short.0.len(), 1);
+}
+
+#[tokio::test]
+async fn relay_menu_selection_is_deferred_before_relaying() {
+    let (db, discord) = setup().await;
+    discord.add_guild(3, "Empty Server", &[]);
+    create_server_mapping(&db, &discord, &GuildId(3))
+        .await
+        .unwrap();
+    let relays = Relays::default();
+    let user = UserId(USER);
+
+    let empty = Selection::Server(GuildId(3), 0);
+    handle_relay_select(&db, &relays, &discord, &discord, &user, empty, 1).await;
+    assert!(discord.responses()[0].error);
+
+    relays.insert(1, user, message(SOURCE, USER, false, "picked"));
+    let target = Selection::Channel(ChannelId(TARGET));
+    handle_relay_select(&db, &relays, &discord, &discord, &user, target, 1).await;
+    let responses = discord.responses();
+    assert_eq!(
+        responses[1].kind,
+        Some(InteractionResponseType::DeferredUpdateMessage)
+    );
+    assert_eq!(responses[2].kind, None);
+    assert_eq!(responses[2].title, "Message relayed");
+    assert_eq!(discord.sent().len(), 1);
+}
+
+#[tokio::test]
+async fn a_failing_connection_does_not_stop_the_others() {
+    let (db, discord) = setup().await;
+    // The webhook of this connection is gone.
+    sqlx::query("INSERT INTO Connections (source, target, user, webhook) VALUES (?, ?, ?, 999)")
+        .bind(SOURCE as i64)
+        .bind(OTHER_SOURCE as i64)
+        .bind(USER as i64)
+        .execute(&db)
+        .await
+        .unwrap();
+    connect(&db, SOURCE, TARGET, USER).await;
+
+    relay(
+        &db,
+        &discord,
+        &message(SOURCE, USER, false, "still relayed"),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(discord.sent().len(), 1);
+    let statuses: Vec<(i64, String)> =
+        sqlx::query_as("SELECT target, status FROM Archive ORDER BY target")
+            .fetch_all(&db)
+            .await
+            .unwrap();
+    assert_eq!(
+        statuses,
+        vec![
+            (OTHER_SOURCE as i64, "failed".to_owned()),
+            (TARGET as i64, "delivered".to_owned())
+        ]
+    );
+}
+
+#[tokio::test]
+async fn backfill_continues_past_a_failed_message() {
+    let (db, discord) = setup().await;
+    connect(&db, SOURCE, TARGET, USER).await;
+    let messages: Vec<Message> = (0..3)
+        .map(|i| message(SOURCE, USER, false, &format!("old {i}")))
+        .collect();
+
+    discord.fail_after(Some(1));
+    backfill(&db, &discord, TARGET, &messages).await;
+
+    assert_eq!(discord.sent().len(), 1);
+    let statuses: Vec<(String, i32)> =
+        sqlx::query_as("SELECT status, COUNT(1) FROM Archive GROUP BY status ORDER BY status")
+            .fetch_all(&db)
+            .await
+            .unwrap();
+    assert_eq!(
+        statuses,
+        vec![("delivered".to_owned(), 1), ("failed".to_owned(), 2)]
+    );
+}
+
+#[tokio::test]
+async fn backfill_only_relays_messages_on_the_watchlist() {
+    let (db, discord) = setup().await;
+    connect(&db, SOURCE, TARGET, USER).await;
+    let (id,): (i64,) = sqlx::query_as("SELECT id FROM Connections")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    ticker::add_to_watchlist(&db, id, &["BTC".to_owned()])
+        .await
+        .unwrap();
+    let messages = [
+        message(SOURCE, USER, false, "$ETH is moving"),
+        message(SOURCE, USER, false, "$BTC is moving"),
+    ];
+
+    backfill(&db, &discord, TARGET, &messages).await;
+
+    let sent = discord.sent();
+    assert_eq!(sent.len(), 1);
+    assert!(sent[0].embed.as_ref().unwrap().contains("$BTC"));
+}
+
+#[tokio::test]
+async fn backfill_goes_into_the_target_thread() {
+    let (db, discord) = setup().await;
+    connect(&db, SOURCE, TARGET, USER).await;
+    let (id,): (i64,) = sqlx::query_as("SELECT id FROM Connections")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    discord.add_thread(TARGET_GUILD, 22, "calls", TARGET);
+    thread::set_target_thread(&db, id, Some(&ChannelId(22)))
+        .await
+        .unwrap();
+
+    backfill(
+        &db,
+        &discord,
+        TARGET,
+        &[message(SOURCE, USER, false, "old")],
+    )
+    .await;
+
+    assert_eq!(discord.sent()[0].thread, Some(ChannelId(22)));
+}
+
+#[tokio::test]
+async fn queued_messages_of_a_thread_share_one_forum_post() {
+    let (db, discord) = setup().await;
+    discord.add_guild(3, "Forum Server", &[(31, "ideas")]);
+    discord.set_kind(31, ChannelKind::Forum);
+    create_server_mapping(&db, &discord, &GuildId(3))
+        .await
+        .unwrap();
+    connect(&db, SOURCE, 31, USER).await;
+    let (id,): (i64,) = sqlx::query_as("SELECT id FROM Connections")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    ratelimit::set_connection_limit(&db, id, Some(1), Some(Overflow::Queue))
+        .await
+        .unwrap();
+    discord.add_thread(SOURCE_GUILD, 13, "btc-calls", SOURCE);
+    thread::sync_threads(&db, &discord, GuildId(SOURCE_GUILD))
+        .await
+        .unwrap();
+
+    // The first message takes the only slot, the thread has to wait.
+    let state = RelayState::default();
+    let messages = [
+        message(SOURCE, USER, false, "ETH short"),
+        message(13, USER, false, "BTC long"),
+        message(13, USER, false, "BTC target hit"),
+    ];
+    for msg in &messages {
+        handle_message(&db, &discord, &discord, &state, msg)
+            .await
+            .unwrap();
+    }
+    assert_eq!(discord.sent().len(), 1);
+
+    ratelimit::set_connection_limit(&db, id, Some(10), None)
+        .await
+        .unwrap();
+    let msg = message(SOURCE, USER, false, "ETH target hit");
+    handle_message(&db, &discord, &discord, &state, &msg)
+        .await
+        .unwrap();
+    ratelimit::send_due(&db, &discord, &state).await;
+
+    let sent = discord.sent();
+    assert_eq!(sent.len(), 4);
+    assert_eq!(sent[1].post_name.as_deref(), Some("btc-calls"));
+    assert_eq!(sent[2].post_name, None);
+    assert_eq!(sent[2].thread, sent[1].thread);
+}
+
+#[tokio::test]
+async fn queued_messages_are_relayed_after_a_restart() {
+    let (db, discord) = setup().await;
+    connect(&db, SOURCE, TARGET, USER).await;
+    let (id,): (i64,) = sqlx::query_as("SELECT id FROM Connections")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    ratelimit::set_connection_limit(&db, id, Some(1), Some(Overflow::Queue))
+        .await
+        .unwrap();
+
+    let state = RelayState::default();
+    let messages = [
+        message(SOURCE, USER, false, "ETH short"),
+        message(SOURCE, USER, false, "BTC long"),
+    ];
+    for msg in &messages {
+        handle_message(&db, &discord, &discord, &state, msg)
+            .await
+            .unwrap();
+    }
+    assert_eq!(discord.sent().len(), 1);
+
+    // The held back message outlives the limiter it was queued in.
+    ratelimit::set_connection_limit(&db, id, Some(10), None)
+        .await
+        .unwrap();
+    let state = RelayState::default();
+    let cx = RelayContext {
+        db: &db,
+        sender: &discord,
+        resolver: &discord,
+        state: &state,
+    };
+    restore_queued(&cx).await.unwrap();
+
+    let sent = discord.sent();
+    assert_eq!(sent.len(), 2);
+    assert_eq!(sent[1].embed.as_deref(), Some("BTC long"));
+    let (left,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM RelayQueue")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_eq!(left, 0);
+}
+
+#[tokio::test]
+async fn concurrent_thread_messages_are_mirrored_once() {
+    let (db, discord) = setup().await;
+    connect(&db, SOURCE, TARGET, USER).await;
+    discord.add_thread(SOURCE_GUILD, 13, "btc-calls", SOURCE);
+    thread::sync_threads(&db, &discord, GuildId(SOURCE_GUILD))
+        .await
+        .unwrap();
+
+    let state = RelayState::default();
+    let (first, second) = (
+        message(13, USER, false, "BTC long"),
+        message(13, USER, false, "BTC target hit"),
+    );
+    let (a, b) = tokio::join!(
+        handle_message(&db, &discord, &discord, &state, &first),
+        handle_message(&db, &discord, &discord, &state, &second),
+    );
+    a.unwrap();
+    b.unwrap();
+
+    let mirrors = discord.threads(TARGET);
+    assert_eq!(mirrors.len(), 1);
+    assert!(discord
+        .sent()
+        .iter()
+        .all(|m| m.thread == Some(mirrors[0].id)));
+}
@@ -0,0 +1,415 @@
+use anyhow::{Error, Result};
+use futures::TryFutureExt;
+use serenity::model::{
+    channel::Message,
+    id::{ChannelId, GuildId},
+};
+use sqlx::SqlitePool;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+
+use crate::transport::{ChannelResolver, Destination, Posted};
+
+// Discord's limit for the name of a thread.
+const THREAD_NAME_LIMIT: usize = 100;
+
+/// The kinds of channels that can be connected.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChannelKind {
+    Text,
+    /// Announcement channel.
+    News,
+    /// Every message is posted in a thread, called a post.
+    Forum,
+}
+
+impl ChannelKind {
+    /// `None` for channels that can't be connected, like voice channels.
+    /// serenity 0.10 predates forum channels, so this goes by Discord's raw type.
+    pub fn from_type(kind: u8) -> Option<Self> {
+        match kind {
+            0 => Some(ChannelKind::Text),
+            5 => Some(ChannelKind::News),
+            15 => Some(ChannelKind::Forum),
+            _ => None,
+        }
+    }
+
+    /// Unknown kinds are taken as text channels.
+    pub fn parse(s: &str) -> Self {
+        match s {
+            "news" => ChannelKind::News,
+            "forum" => ChannelKind::Forum,
+            _ => ChannelKind::Text,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChannelKind::Text => "text",
+            ChannelKind::News => "news",
+            ChannelKind::Forum => "forum",
+        }
+    }
+}
+
+/// A thread (or forum post) in one of the mapped channels.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Thread {
+    pub id: ChannelId,
+    pub name: String,
+    pub parent: ChannelId,
+}
+
+pub async fn channel_kind(db: &SqlitePool, channel: &ChannelId) -> Result<ChannelKind> {
+    let id = channel.0 as i64;
+    let row = sqlx::query!("SELECT kind FROM Channels WHERE id = ?", id)
+        .fetch_optional(db)
+        .map_err(|e| Error::new(e).context("Failed to retrieve channel kind from the database"))
+        .await?;
+    Ok(row.map_or(ChannelKind::Text, |row| ChannelKind::parse(&row.kind)))
+}
+
+/// Record the thread, threads of channels that aren't mapped are ignored.
+pub async fn save_thread(db: &SqlitePool, thread: &Thread) -> Result<()> {
+    let id = thread.id.0 as i64;
+    let parent = thread.parent.0 as i64;
+    sqlx::query!(
+        "
+        INSERT INTO Threads (id, name, parent)\n\
+        SELECT ?, ?, id FROM Channels WHERE id = ?\n\
+        ON CONFLICT (id) DO UPDATE SET name = excluded.name, parent = excluded.parent
+        ",
+        id,
+        thread.name,
+        parent
+    )
+    .execute(db)
+    .await
+    .map_err(|e| Error::new(e).context("Failed to insert thread into the database"))?;
+
+    Ok(())
+}
+
+pub async fn delete_thread(db: &SqlitePool, thread: &ChannelId) -> Result<()> {
+    let id = thread.0 as i64;
+    sqlx::query!("DELETE FROM Threads WHERE id = ?", id)
+        .execute(db)
+        .await
+        .map_err(|e| Error::new(e).context("Failed to delete thread in the database"))?;
+
+    Ok(())
+}
+
+/// Record the active threads of the server, they're kept up to date by the
+/// thread events afterwards.
+pub async fn sync_threads(
+    db: &SqlitePool,
+    resolver: &dyn ChannelResolver,
+    guild: GuildId,
+) -> Result<()> {
+    for thread in resolver.active_threads(guild).await? {
+        save_thread(db, &thread).await?;
+    }
+    Ok(())
+}
+
+pub async fn get_thread(db: &SqlitePool, thread: &ChannelId) -> Result<Option<Thread>> {
+    let id = thread.0 as i64;
+    sqlx::query!(
+        "SELECT id as \"id: i64\", name, parent FROM Threads WHERE id = ?",
+        id
+    )
+    .fetch_optional(db)
+    .and_then(|row| async move {
+        Ok(row.map(|row| Thread {
+            id: ChannelId(row.id as u64),
+            name: row.name,
+            parent: ChannelId(row.parent as u64),
+        }))
+    })
+    .map_err(|e| Error::new(e).context("Failed to retrieve thread from the database"))
+    .await
+}
+
+pub async fn get_threads(db: &SqlitePool, parent: &ChannelId) -> Result<Vec<Thread>> {
+    let id = parent.0 as i64;
+    sqlx::query!(
+        "SELECT id as \"id: i64\", name, parent FROM Threads WHERE parent = ? ORDER BY name",
+        id
+    )
+    .fetch_all(db)
+    .and_then(|rows| async move {
+        Ok(rows
+            .into_iter()
+            .map(|row| Thread {
+                id: ChannelId(row.id as u64),
+                name: row.name,
+                parent: ChannelId(row.parent as u64),
+            })
+            .collect())
+    })
+    .map_err(|e| Error::new(e).context("Failed to retrieve threads from the database"))
+    .await
+}
+
+/// Set the thread a connection posts in, `None` posts in the target channel.
+pub async fn set_target_thread(
+    db: &SqlitePool,
+    connection: i64,
+    thread: Option<&ChannelId>,
+) -> Result<()> {
+    let thread = thread.map(|t| t.0 as i64);
+    sqlx::query!(
+        "UPDATE Connections SET thread = ? WHERE id = ?",
+        thread,
+        connection
+    )
+    .execute(db)
+    .await
+    .map_err(|e| Error::new(e).context("Failed to update the thread of the connection"))?;
+
+    Ok(())
+}
+
+async fn get_mirror(
+    db: &SqlitePool,
+    connection: i64,
+    source: &ChannelId,
+) -> Result<Option<ChannelId>> {
+    let source = source.0 as i64;
+    let row = sqlx::query!(
+        "SELECT target FROM ThreadMirrors WHERE connection = ? AND source = ?",
+        connection,
+        source
+    )
+    .fetch_optional(db)
+    .map_err(|e| Error::new(e).context("Failed to retrieve mirrored thread from the database"))
+    .await?;
+    Ok(row.map(|row| ChannelId(row.target as u64)))
+}
+
+async fn save_mirror(
+    db: &SqlitePool,
+    connection: i64,
+    source: &ChannelId,
+    target: &ChannelId,
+) -> Result<()> {
+    let source = source.0 as i64;
+    let target = target.0 as i64;
+    sqlx::query!(
+        "INSERT OR REPLACE INTO ThreadMirrors (connection, source, target) VALUES (?, ?, ?)",
+        connection,
+        source,
+        target
+    )
+    .execute(db)
+    .await
+    .map_err(|e| Error::new(e).context("Failed to insert mirrored thread into the database"))?;
+
+    Ok(())
+}
+
+/// Name of a forum post for a message that isn't in a thread: the first line
+/// of the content, or who posted it where.
+pub fn post_name(msg: &Message, source_channel: &str) -> String {
+    let first_line = msg.content.lines().map(str::trim).find(|l| !l.is_empty());
+    let name = match first_line {
+        Some(line) => line.to_owned(),
+        None => format!("{} in {}", msg.author.name, source_channel),
+    };
+    name.chars().take(THREAD_NAME_LIMIT).collect()
+}
+
+/// Only messages posted in a news channel itself can be published, not
+/// those in its threads.
+pub async fn publishable(
+    db: &SqlitePool,
+    target: &ChannelId,
+    destination: &Destination,
+) -> Result<bool> {
+    Ok(
+        destination == &Destination::Channel
+            && channel_kind(db, target).await? == ChannelKind::News,
+    )
+}
+
+// Discord allows about 10 crossposts per hour in a channel, past that it
+// makes the request wait for the rest of the hour.
+const CROSSPOSTS_PER_HOUR: usize = 10;
+const CROSSPOST_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// Crossposts recently made in each news channel. Messages past the budget
+/// aren't published rather than holding up the relay.
+#[derive(Clone, Default)]
+pub struct PublishBudget(Arc<Mutex<HashMap<ChannelId, VecDeque<Instant>>>>);
+
+impl PublishBudget {
+    /// Whether a message can be published in the channel now, and if so
+    /// count it.
+    pub fn take(&self, channel: ChannelId) -> bool {
+        let mut map = self.0.lock().unwrap();
+        let recent = map.entry(channel).or_default();
+        recent.retain(|t| t.elapsed() < CROSSPOST_WINDOW);
+        if recent.len() >= CROSSPOSTS_PER_HOUR {
+            return false;
+        }
+        recent.push_back(Instant::now());
+        true
+    }
+}
+
+/// Set whether the connection publishes the messages it relays.
+pub async fn set_publish(db: &SqlitePool, connection: i64, publish: bool) -> Result<()> {
+    sqlx::query!(
+        "UPDATE Connections SET publish = ? WHERE id = ?",
+        publish,
+        connection
+    )
+    .execute(db)
+    .await
+    .map_err(|e| Error::new(e).context("Failed to update auto-publish of the connection"))?;
+
+    Ok(())
+}
+
+/// Where a message from outside a thread is posted: in the thread of the
+/// connection if it has one, otherwise in the target channel, or in a new post
+/// with the name if that's a forum.
+pub async fn channel_destination(
+    db: &SqlitePool,
+    target: &ChannelId,
+    target_thread: Option<ChannelId>,
+    post_name: String,
+) -> Result<Destination> {
+    Ok(match target_thread {
+        Some(thread) => Destination::Thread(thread),
+        None => match channel_kind(db, target).await? {
+            ChannelKind::Forum => Destination::NewPost(post_name),
+            _ => Destination::Channel,
+        },
+    })
+}
+
+/// Where the message is posted for the connection. Messages in a source
+/// thread go into a target thread of the same name, which is started if
+/// needed, unless the connection has a thread of its own.
+#[allow(clippy::too_many_arguments)]
+pub async fn destination(
+    db: &SqlitePool,
+    resolver: &dyn ChannelResolver,
+    connection: i64,
+    target: &ChannelId,
+    target_thread: Option<ChannelId>,
+    source_thread: Option<&Thread>,
+    msg: &Message,
+    source_channel: &str,
+) -> Result<Destination> {
+    let source_thread = match (target_thread, source_thread) {
+        (None, Some(thread)) => thread,
+        _ => {
+            let name = post_name(msg, source_channel);
+            return channel_destination(db, target, target_thread, name).await;
+        }
+    };
+
+    if let Some(mirror) = get_mirror(db, connection, &source_thread.id).await? {
+        return Ok(Destination::Thread(mirror));
+    }
+    let kind = channel_kind(db, target).await?;
+    if kind == ChannelKind::Forum {
+        // The post is started by the message, it's recorded once posted.
+        return Ok(Destination::NewPost(source_thread.name.clone()));
+    }
+    let mirror = resolver
+        .create_thread(*target, kind, &source_thread.name)
+        .await?;
+    save_mirror(db, connection, &source_thread.id, &mirror).await?;
+    save_thread(
+        db,
+        &Thread {
+            id: mirror,
+            name: source_thread.name.clone(),
+            parent: *target,
+        },
+    )
+    .await?;
+    Ok(Destination::Thread(mirror))
+}
+
+/// A forum post may have been started for the source thread while the message
+/// was held back by the rate limit.
+pub async fn queued_destination(
+    db: &SqlitePool,
+    connection: i64,
+    destination: &Destination,
+    source_thread: Option<&Thread>,
+) -> Result<Destination> {
+    if let (Destination::NewPost(_), Some(thread)) = (destination, source_thread) {
+        if let Some(mirror) = get_mirror(db, connection, &thread.id).await? {
+            return Ok(Destination::Thread(mirror));
+        }
+    }
+    Ok(destination.clone())
+}
+
+/// Messages of a source thread are relayed over a connection one at a time,
+/// otherwise each of them could start a thread or post of its own.
+#[derive(Clone, Default)]
+pub struct ThreadLocks(Arc<Mutex<HashMap<ThreadKey, Arc<AsyncMutex<()>>>>>);
+
+// A source thread and the connection it is relayed over.
+type ThreadKey = (i64, ChannelId);
+
+impl ThreadLocks {
+    /// `None` for messages outside of threads, they don't need to wait.
+    pub async fn lock(
+        &self,
+        connection: i64,
+        thread: Option<&Thread>,
+    ) -> Option<OwnedMutexGuard<()>> {
+        let thread = thread?;
+        let lock = {
+            let mut locks = self.0.lock().unwrap();
+            // Locks that nobody holds or waits for can go.
+            locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+            locks.entry((connection, thread.id)).or_default().clone()
+        };
+        Some(lock.lock_owned().await)
+    }
+}
+
+/// Where the rest of a message goes once its first part was posted, a new
+/// forum post is continued in the post.
+pub fn continued(destination: &Destination, posted: &Posted) -> Destination {
+    match destination {
+        Destination::NewPost(_) => Destination::Thread(posted.channel),
+        d => d.clone(),
+    }
+}
+
+/// Remember the forum post started for a source thread, so the rest of the
+/// thread follows it there.
+pub async fn record_post(
+    db: &SqlitePool,
+    connection: i64,
+    destination: &Destination,
+    source_thread: Option<&Thread>,
+    posted: &Posted,
+    target: &ChannelId,
+) -> Result<()> {
+    if let (Destination::NewPost(name), Some(source_thread)) = (destination, source_thread) {
+        save_mirror(db, connection, &source_thread.id, &posted.channel).await?;
+        let thread = Thread {
+            id: posted.channel,
+            name: name.clone(),
+            parent: *target,
+        };
+        save_thread(db, &thread).await?;
+    }
+    Ok(())
+}
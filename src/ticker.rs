@@ -0,0 +1,193 @@
+use anyhow::{bail, Error, Result};
+use futures::TryFutureExt;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use sqlx::SqlitePool;
+
+// Most symbols a connection's watchlist can hold.
+pub const MAX_WATCHLIST: usize = 100;
+
+// Longest symbol accepted in a watchlist.
+const MAX_SYMBOL_LEN: usize = 32;
+
+static CASHTAG: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\$([A-Za-z][A-Za-z0-9]{0,9}(?:\.[A-Za-z]{1,2})?)\b").unwrap());
+
+static PAIR: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\b([A-Z][A-Z0-9]{1,9})/([A-Z]{2,10})\b").unwrap());
+
+static OCC: Lazy<Regex> = Lazy::new(|| Regex::new(r"\b([A-Z]{1,6})\d{6}[CP]\d{8}\b").unwrap());
+
+static OPTION: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"\b([A-Z]{1,5}) \d+(?:\.\d+)?[CcPp] \d{1,2}/\d{1,2}(?:/\d{2,4})?\b").unwrap()
+});
+
+static SYMBOL: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[A-Z0-9][A-Z0-9./ -]*$").unwrap());
+
+/// A symbol found in a message.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ticker {
+    /// The symbol as it is shown, e.g. `AAPL`, `BTC/USDT` or `AAPL 150C 1/19`.
+    pub symbol: String,
+    /// What the symbol trades on: the stock of an option contract or the base
+    /// currency of a pair. The symbol itself for plain tickers.
+    pub underlying: String,
+}
+
+impl Ticker {
+    /// Whether the ticker is covered by a watchlist entry, either directly or
+    /// through its underlying (`BTC` covers `BTC/USDT`).
+    pub fn matches(&self, symbol: &str) -> bool {
+        self.symbol == symbol || self.underlying == symbol
+    }
+}
+
+/// Find the tickers in a message, in order of appearance and without duplicates.
+///
+/// Recognises cashtags (`$AAPL`, `$BRK.B`), pairs (`BTC/USDT`) and option
+/// contracts, both in the OCC format (`AAPL240119C00150000`) and written out
+/// (`AAPL 150C 1/19`).
+pub fn extract(content: &str) -> Vec<Ticker> {
+    let mut found: Vec<(usize, Ticker)> = Vec::new();
+    for caps in CASHTAG.captures_iter(content) {
+        let symbol = caps[1].to_uppercase();
+        found.push((
+            caps.get(0).unwrap().start(),
+            Ticker {
+                underlying: symbol.clone(),
+                symbol,
+            },
+        ));
+    }
+    for re in [&PAIR, &OCC, &OPTION] {
+        for caps in re.captures_iter(content) {
+            let m = caps.get(0).unwrap();
+            found.push((
+                m.start(),
+                Ticker {
+                    symbol: m.as_str().to_uppercase(),
+                    underlying: caps[1].to_owned(),
+                },
+            ));
+        }
+    }
+
+    found.sort_by_key(|(start, _)| *start);
+    let mut tickers: Vec<Ticker> = Vec::new();
+    for (_, ticker) in found {
+        if !tickers.iter().any(|t| t.symbol == ticker.symbol) {
+            tickers.push(ticker);
+        }
+    }
+    tickers
+}
+
+/// Parse a symbol for a watchlist, the `$` of a cashtag is optional.
+pub fn parse_symbol(s: &str) -> Result<String> {
+    let symbol = s.trim().trim_start_matches('$').to_uppercase();
+    if symbol.len() > MAX_SYMBOL_LEN || !SYMBOL.is_match(&symbol) {
+        bail!("Invalid symbol: {s}");
+    }
+    Ok(symbol)
+}
+
+/// Split a list of symbols separated by commas or whitespace. Written out
+/// option contracts contain spaces and have to be separated by commas.
+pub fn parse_symbols(s: &str) -> Result<Vec<String>> {
+    let parts: Vec<&str> = match s.contains(',') {
+        true => s.split(',').collect(),
+        false => s.split_whitespace().collect(),
+    };
+    let mut symbols = Vec::new();
+    for part in parts.into_iter().filter(|p| !p.trim().is_empty()) {
+        let symbol = parse_symbol(part)?;
+        if !symbols.contains(&symbol) {
+            symbols.push(symbol);
+        }
+    }
+    if symbols.is_empty() {
+        bail!("No symbols given");
+    }
+    Ok(symbols)
+}
+
+/// Whether a message with these tickers passes the watchlist of a connection,
+/// an empty watchlist lets every message through.
+pub fn on_watchlist(tickers: &[Ticker], watchlist: &[String]) -> bool {
+    watchlist.is_empty()
+        || tickers
+            .iter()
+            .any(|t| watchlist.iter().any(|symbol| t.matches(symbol)))
+}
+
+pub async fn get_watchlist(db: &SqlitePool, connection: i64) -> Result<Vec<String>> {
+    sqlx::query!(
+        "SELECT symbol FROM Watchlists WHERE connection = ? ORDER BY symbol",
+        connection
+    )
+    .fetch_all(db)
+    .and_then(|rows| async move { Ok(rows.into_iter().map(|row| row.symbol).collect()) })
+    .map_err(|e| Error::new(e).context("Failed to retrieve watchlist from the database"))
+    .await
+}
+
+/// Add the symbols to the watchlist, returns the ones that weren't on it yet.
+pub async fn add_to_watchlist(
+    db: &SqlitePool,
+    connection: i64,
+    symbols: &[String],
+) -> Result<Vec<String>> {
+    let existing = get_watchlist(db, connection).await?;
+    let added: Vec<String> = symbols
+        .iter()
+        .filter(|s| !existing.contains(s))
+        .cloned()
+        .collect();
+    if existing.len() + added.len() > MAX_WATCHLIST {
+        bail!("A watchlist can hold at most {MAX_WATCHLIST} symbols");
+    }
+
+    let mut tx = db.begin().await?;
+    for symbol in &added {
+        sqlx::query!(
+            "INSERT INTO Watchlists (connection, symbol) VALUES (?, ?)",
+            connection,
+            symbol
+        )
+        .execute(&mut tx)
+        .await
+        .map_err(|e| {
+            Error::new(e).context("Failed to insert watchlist symbol into the database")
+        })?;
+    }
+    tx.commit().await?;
+
+    Ok(added)
+}
+
+/// Remove the symbols from the watchlist, returns the ones that were on it.
+pub async fn remove_from_watchlist(
+    db: &SqlitePool,
+    connection: i64,
+    symbols: &[String],
+) -> Result<Vec<String>> {
+    let mut removed = Vec::new();
+    let mut tx = db.begin().await?;
+    for symbol in symbols {
+        let rows = sqlx::query!(
+            "DELETE FROM Watchlists WHERE connection = ? AND symbol = ?",
+            connection,
+            symbol
+        )
+        .execute(&mut tx)
+        .await
+        .map_err(|e| Error::new(e).context("Failed to delete watchlist symbol in the database"))?
+        .rows_affected();
+        if rows > 0 {
+            removed.push(symbol.clone());
+        }
+    }
+    tx.commit().await?;
+
+    Ok(removed)
+}
@@ -0,0 +1,195 @@
+use anyhow::{anyhow, bail, Error, Result};
+use futures::TryFutureExt;
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+use serenity::model::{channel::Message, id::ChannelId};
+use sqlx::SqlitePool;
+
+use crate::condition::compile_pattern;
+
+// Most replace rules a single connection can have.
+pub const MAX_RULES: usize = 20;
+
+static MENTION: Lazy<Regex> = Lazy::new(|| Regex::new(r"<(@!?|@&|#)(\d+)>").unwrap());
+
+static INVITE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"(?i)[ \t]*(https?://)?(www\.)?(discord\.gg|discord(app)?\.com/invite)/[A-Za-z0-9-]+/?",
+    )
+    .unwrap()
+});
+
+/// A user-defined regex replace rule, `$1` etc. in the replacement refer to
+/// the groups of the pattern.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule {
+    pub pattern: String,
+    pub replacement: String,
+}
+
+impl Rule {
+    pub fn new(pattern: &str, replacement: &str) -> Result<Self> {
+        compile(pattern)?;
+        Ok(Rule {
+            pattern: pattern.to_owned(),
+            replacement: replacement.to_owned(),
+        })
+    }
+}
+
+fn compile(pattern: &str) -> Result<Regex> {
+    compile_pattern(pattern, false)
+}
+
+/// Mentions of users, roles and channels of the source server render as
+/// "unknown" in the target server, so they are replaced by their names where
+/// known and stripped otherwise. `@everyone` and `@here` are defused.
+pub async fn resolve_mentions(db: &SqlitePool, msg: &Message) -> Result<String> {
+    let re = &*MENTION;
+    let mut channels = Vec::new();
+    for caps in re.captures_iter(&msg.content) {
+        if &caps[1] == "#" {
+            let id: i64 = caps[2].parse().unwrap_or_default();
+            let name = sqlx::query!("SELECT name FROM Channels WHERE id = ?", id)
+                .fetch_optional(db)
+                .map_err(|e| Error::new(e).context("Failed to retrieve channel name from database"))
+                .await?;
+            channels.push((caps[2].to_owned(), name.map(|row| row.name)));
+        }
+    }
+
+    let resolved = re.replace_all(&msg.content, |caps: &Captures| match &caps[1] {
+        "#" => channels
+            .iter()
+            .find(|(id, _)| id == &caps[2])
+            .and_then(|(_, name)| name.clone())
+            .unwrap_or_else(|| "#unknown-channel".to_owned()),
+        "@&" => "@unknown-role".to_owned(),
+        _ => msg
+            .mentions
+            .iter()
+            .find(|user| user.id.0.to_string() == caps[2])
+            .map(|user| format!("@{}", user.name))
+            .unwrap_or_else(|| "@unknown-user".to_owned()),
+    });
+    Ok(resolved
+        .replace("@everyone", "@\u{200b}everyone")
+        .replace("@here", "@\u{200b}here"))
+}
+
+/// Discord invites, with or without the scheme, along with the space before
+/// them so no gap is left in the middle of a line.
+pub fn remove_invites(content: &str) -> String {
+    let re = &*INVITE;
+    match re.is_match(content) {
+        true => re.replace_all(content, "").trim().to_owned(),
+        false => content.to_owned(),
+    }
+}
+
+pub fn apply_rules(content: &str, rules: &[Rule]) -> String {
+    let mut content = content.to_owned();
+    for rule in rules {
+        match compile(&rule.pattern) {
+            Ok(re) => {
+                content = re
+                    .replace_all(&content, rule.replacement.as_str())
+                    .into_owned()
+            }
+            Err(e) => println!("Skipping broken transform rule: {:?}", e),
+        }
+    }
+    content
+}
+
+/// The content of the message as it is relayed through the connection, the
+/// rules only apply to messages relayed through one.
+pub async fn transform(db: &SqlitePool, msg: &Message, connection: Option<i64>) -> Result<String> {
+    let content = resolve_mentions(db, msg).await?;
+    let content = remove_invites(&content);
+    let rules = match connection {
+        Some(connection) => get_rules(db, connection).await?,
+        None => Vec::new(),
+    };
+    Ok(apply_rules(&content, &rules))
+}
+
+/// The connection from the source into the target, if there is one.
+pub async fn find_connection(
+    db: &SqlitePool,
+    source: &ChannelId,
+    target: &ChannelId,
+) -> Result<Option<i64>> {
+    let source = source.0 as i64;
+    let target = target.0 as i64;
+    sqlx::query!(
+        "SELECT id FROM Connections WHERE source = ? AND target = ? ORDER BY id LIMIT 1",
+        source,
+        target
+    )
+    .fetch_optional(db)
+    .await
+    .map(|row| row.map(|row| row.id))
+    .map_err(|e| Error::new(e).context("Failed to retrieve connection from the database"))
+}
+
+pub async fn get_rules(db: &SqlitePool, connection: i64) -> Result<Vec<Rule>> {
+    sqlx::query!(
+        "SELECT pattern, replacement FROM Transforms WHERE connection = ? ORDER BY id",
+        connection
+    )
+    .fetch_all(db)
+    .and_then(|rows| async move {
+        Ok(rows
+            .into_iter()
+            .map(|row| Rule {
+                pattern: row.pattern,
+                replacement: row.replacement,
+            })
+            .collect())
+    })
+    .map_err(|e| Error::new(e).context("Failed to retrieve transform rules from the database"))
+    .await
+}
+
+/// Add the rule after the existing ones, a rule with the same pattern is
+/// replaced in place.
+pub async fn add_rule(db: &SqlitePool, connection: i64, rule: &Rule) -> Result<()> {
+    let existing = get_rules(db, connection).await?;
+    let replaces = existing.iter().any(|r| r.pattern == rule.pattern);
+    if !replaces && existing.len() >= MAX_RULES {
+        bail!("A connection can have at most {MAX_RULES} transform rules");
+    }
+
+    sqlx::query!(
+        "
+        INSERT INTO Transforms (connection, pattern, replacement) VALUES (?, ?, ?)\n\
+        ON CONFLICT (connection, pattern) DO UPDATE SET replacement = excluded.replacement
+        ",
+        connection,
+        rule.pattern,
+        rule.replacement
+    )
+    .execute(db)
+    .await
+    .map_err(|e| Error::new(e).context("Failed to insert transform rule into the database"))?;
+
+    Ok(())
+}
+
+pub async fn remove_rule(db: &SqlitePool, connection: i64, pattern: &str) -> Result<()> {
+    let rows = sqlx::query!(
+        "DELETE FROM Transforms WHERE connection = ? AND pattern = ?",
+        connection,
+        pattern
+    )
+    .execute(db)
+    .await
+    .map_err(|e| Error::new(e).context("Failed to delete transform rule in the database"))?
+    .rows_affected();
+
+    match rows {
+        0 => Err(anyhow!("No such transform rule: `{pattern}`")),
+        _ => Ok(()),
+    }
+}
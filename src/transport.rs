@@ -0,0 +1,454 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use serde_json::json;
+use serenity::{
+    async_trait,
+    http::{request::RequestBuilder, routing::RouteInfo, Http},
+    model::{
+        channel::{ChannelType, Embed},
+        id::{ChannelId, GuildId, MessageId, UserId, WebhookId},
+        interactions::{
+            application_command::ApplicationCommandInteraction,
+            message_component::MessageComponentInteraction, InteractionResponseType,
+        },
+        webhook::Webhook,
+    },
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    build_response, edit_response,
+    output::OutgoingMessage,
+    reaction,
+    thread::{ChannelKind, PublishBudget, Thread},
+    CommandResponse,
+};
+
+#[cfg(test)]
+pub mod fake;
+
+/// Name and avatar a relayed message is posted under.
+pub struct Author {
+    pub name: String,
+    pub avatar_url: String,
+}
+
+/// Where in the channel of a webhook a message is posted.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Destination {
+    Channel,
+    /// An existing thread or forum post.
+    Thread(ChannelId),
+    /// A new forum post with the name.
+    NewPost(String),
+}
+
+/// A message posted through a webhook.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Posted {
+    pub id: MessageId,
+    /// The thread the message was posted in, or the channel of the webhook.
+    pub channel: ChannelId,
+}
+
+/// Posting messages through the webhooks of the target channels.
+#[async_trait]
+pub trait WebhookSender: Send + Sync {
+    /// The channel the webhook posts in.
+    async fn webhook_channel(&self, webhook: WebhookId) -> Result<ChannelId>;
+
+    /// Post the message, returns the id of the posted message and where.
+    async fn execute(
+        &self,
+        webhook: WebhookId,
+        destination: &Destination,
+        author: &Author,
+        message: &OutgoingMessage,
+    ) -> Result<Posted>;
+
+    /// Replace a message posted through the webhook.
+    async fn edit(
+        &self,
+        webhook: WebhookId,
+        posted: &Posted,
+        edited: &OutgoingMessage,
+    ) -> Result<()>;
+
+    /// Crosspost a message posted in a news channel to the channels following it.
+    async fn publish(&self, posted: &Posted) -> Result<()>;
+}
+
+/// Sending direct messages to users.
+#[async_trait]
+pub trait DirectMessenger: Send + Sync {
+    async fn send_dm(&self, user: UserId, message: &OutgoingMessage) -> Result<()>;
+}
+
+/// Looking up the servers and channels the bot is in.
+#[async_trait]
+pub trait ChannelResolver: Send + Sync {
+    async fn guild_name(&self, guild: GuildId) -> Result<String>;
+
+    /// Id, name and kind of the channels of the server that can be connected.
+    async fn channels(&self, guild: GuildId) -> Result<Vec<(ChannelId, String, ChannelKind)>>;
+
+    /// The threads and forum posts of the server that aren't archived.
+    async fn active_threads(&self, guild: GuildId) -> Result<Vec<Thread>>;
+
+    async fn create_webhook(&self, channel: ChannelId) -> Result<WebhookId>;
+
+    /// The reactions on a message and how many users reacted with each.
+    async fn reactions(&self, channel: ChannelId, message: MessageId)
+        -> Result<Vec<(String, u64)>>;
+
+    /// Start a thread without a message in a text or news channel.
+    async fn create_thread(
+        &self,
+        channel: ChannelId,
+        kind: ChannelKind,
+        name: &str,
+    ) -> Result<ChannelId>;
+}
+
+/// Answering a command or component interaction.
+#[async_trait]
+pub trait InteractionResponder: Send + Sync {
+    /// Send the response of the interaction, `index` and `count` place
+    /// `rsp.msg` among the pages of the response.
+    async fn respond(
+        &self,
+        kind: InteractionResponseType,
+        rsp: &CommandResponse,
+        key: u64,
+        index: usize,
+        count: usize,
+    ) -> Result<()>;
+
+    /// Replace the original response of a deferred interaction.
+    async fn edit_original(&self, rsp: &CommandResponse, key: u64, count: usize) -> Result<()>;
+}
+
+/// A channel of a server as Discord sends it.
+#[derive(Deserialize)]
+struct RawChannel {
+    id: ChannelId,
+    #[serde(default)]
+    name: String,
+    #[serde(rename = "type")]
+    kind: u8,
+}
+
+/// The real thing, talking to Discord over HTTP.
+pub struct DiscordTransport {
+    http: Arc<Http>,
+    // Webhooks are looked up once per transport, executing one needs its token.
+    webhooks: Mutex<HashMap<WebhookId, Webhook>>,
+    crossposts: PublishBudget,
+}
+
+impl DiscordTransport {
+    pub fn new(http: Arc<Http>) -> Self {
+        DiscordTransport {
+            http,
+            webhooks: Mutex::default(),
+            crossposts: PublishBudget::default(),
+        }
+    }
+
+    async fn webhook(&self, id: WebhookId) -> Result<Webhook> {
+        if let Some(webhook) = self.webhooks.lock().unwrap().get(&id) {
+            return Ok(webhook.clone());
+        }
+        let webhook = id
+            .to_webhook(&self.http)
+            .await
+            .context(format!("Failed to retrieve webhook from Discord: {id}"))?;
+        self.webhooks.lock().unwrap().insert(id, webhook.clone());
+        Ok(webhook)
+    }
+}
+
+/// serenity 0.10 doesn't know the `thread_id` query parameter, it's added to
+/// the token which ends up in the path right before the query string. The
+/// rest of the path is moved behind the parameter into one Discord ignores.
+fn thread_token(webhook: &Webhook, thread: ChannelId, rest: &str) -> Result<Webhook> {
+    let token = webhook.token.as_ref().context("Webhook has no token")?;
+    let mut webhook = webhook.clone();
+    webhook.token = Some(format!("{token}{rest}?thread_id={thread}&_"));
+    Ok(webhook)
+}
+
+fn fake_embed(message: &OutgoingMessage) -> Option<serde_json::Value> {
+    message.embed.as_ref().map(|embed| {
+        Embed::fake(|e| {
+            *e = embed.clone();
+            e
+        })
+    })
+}
+
+#[async_trait]
+impl WebhookSender for DiscordTransport {
+    async fn webhook_channel(&self, webhook: WebhookId) -> Result<ChannelId> {
+        Ok(self.webhook(webhook).await?.channel_id)
+    }
+
+    async fn execute(
+        &self,
+        webhook: WebhookId,
+        destination: &Destination,
+        author: &Author,
+        message: &OutgoingMessage,
+    ) -> Result<Posted> {
+        let mut webhook = self.webhook(webhook).await?;
+        if let Destination::Thread(thread) = destination {
+            webhook = thread_token(&webhook, *thread, "")?;
+        }
+        let posted = webhook
+            .execute(&self.http, true, |w| {
+                if let Some(embed) = fake_embed(message) {
+                    w.embeds(vec![embed]);
+                }
+                if let Destination::NewPost(name) = destination {
+                    w.0.insert("thread_name", json!(name));
+                }
+                w.username(&author.name)
+                    .avatar_url(&author.avatar_url)
+                    .content(&message.content)
+            })
+            .await
+            .context(format!("Failed to execute webhook:\n{:#?}", webhook))?
+            .context("Discord didn't return the posted message")?;
+        Ok(Posted {
+            id: posted.id,
+            channel: posted.channel_id,
+        })
+    }
+
+    async fn edit(
+        &self,
+        webhook: WebhookId,
+        posted: &Posted,
+        edited: &OutgoingMessage,
+    ) -> Result<()> {
+        let mut webhook = self.webhook(webhook).await?;
+        if posted.channel != webhook.channel_id {
+            webhook = thread_token(
+                &webhook,
+                posted.channel,
+                &format!("/messages/{}", posted.id),
+            )?;
+        }
+        webhook
+            .edit_message(&self.http, posted.id, |m| {
+                if let Some(embed) = fake_embed(edited) {
+                    m.embeds(vec![embed]);
+                }
+                m.content(&edited.content)
+            })
+            .await
+            .context(format!("Failed to edit webhook message: {}", posted.id))?;
+        Ok(())
+    }
+
+    async fn publish(&self, posted: &Posted) -> Result<()> {
+        if !self.crossposts.take(posted.channel) {
+            bail!(
+                "Not publishing message {}, the crosspost limit of {} is used up",
+                posted.id,
+                posted.channel
+            );
+        }
+        self.http
+            .crosspost_message(posted.channel.0, posted.id.0)
+            .await
+            .context(format!("Failed to publish message: {}", posted.id))?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DirectMessenger for DiscordTransport {
+    async fn send_dm(&self, user: UserId, message: &OutgoingMessage) -> Result<()> {
+        let channel = user.create_dm_channel(&*self.http).await.context(format!(
+            "Failed to open a direct message channel with: {user}"
+        ))?;
+        channel
+            .send_message(&self.http, |m| {
+                if let Some(embed) = &message.embed {
+                    m.set_embed(embed.clone());
+                }
+                m.content(&message.content)
+            })
+            .await
+            .context(format!("Failed to send direct message to: {user}"))?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ChannelResolver for DiscordTransport {
+    async fn guild_name(&self, guild: GuildId) -> Result<String> {
+        Ok(self
+            .http
+            .get_guild(guild.0)
+            .await
+            .context(format!("Failed to get name from guild id: {guild}"))?
+            .name)
+    }
+
+    async fn channels(&self, guild: GuildId) -> Result<Vec<(ChannelId, String, ChannelKind)>> {
+        // serenity 0.10 can't tell forum channels from other new kinds, so the
+        // raw type is read.
+        let route = RouteInfo::GetChannels { guild_id: guild.0 };
+        let channels: Vec<RawChannel> = self
+            .http
+            .fire(RequestBuilder::new(route).build())
+            .await
+            .context(format!("Failed to get channels of guild: {guild}"))?;
+        Ok(channels
+            .into_iter()
+            .filter_map(|ch| ChannelKind::from_type(ch.kind).map(|kind| (ch.id, ch.name, kind)))
+            .collect())
+    }
+
+    async fn active_threads(&self, guild: GuildId) -> Result<Vec<Thread>> {
+        Ok(self
+            .http
+            .get_guild_active_threads(guild.0)
+            .await
+            .context(format!("Failed to get active threads of guild: {guild}"))?
+            .threads
+            .into_iter()
+            .filter_map(|ch| {
+                // The parent of a thread is deserialized as its category.
+                ch.category_id.map(|parent| Thread {
+                    id: ch.id,
+                    name: ch.name,
+                    parent,
+                })
+            })
+            .collect())
+    }
+
+    async fn create_webhook(&self, channel: ChannelId) -> Result<WebhookId> {
+        Ok(channel
+            .create_webhook(&self.http, "Analyst Bot")
+            .await
+            .context(format!("Failed to create webhook in channel: {channel}"))?
+            .id)
+    }
+
+    async fn reactions(
+        &self,
+        channel: ChannelId,
+        message: MessageId,
+    ) -> Result<Vec<(String, u64)>> {
+        Ok(channel
+            .message(&self.http, message)
+            .await
+            .context(format!("Failed to get message: {message}"))?
+            .reactions
+            .iter()
+            .map(|r| (reaction::emoji_name(&r.reaction_type), r.count))
+            .collect())
+    }
+
+    async fn create_thread(
+        &self,
+        channel: ChannelId,
+        kind: ChannelKind,
+        name: &str,
+    ) -> Result<ChannelId> {
+        // Despite its name this is the route for threads without a message,
+        // the type decides whether the thread is public.
+        let thread_type = match kind {
+            ChannelKind::News => ChannelType::NewsThread,
+            _ => ChannelType::PublicThread,
+        };
+        let map = json!({
+            "name": name,
+            "type": thread_type as u8,
+            "auto_archive_duration": 1440,
+        });
+        Ok(self
+            .http
+            .create_private_thread(channel.0, map.as_object().unwrap())
+            .await
+            .context(format!("Failed to create thread in channel: {channel}"))?
+            .id)
+    }
+}
+
+/// Responds to a slash or context menu command.
+pub struct CommandResponder<'a> {
+    pub http: &'a Http,
+    pub command: &'a ApplicationCommandInteraction,
+}
+
+#[async_trait]
+impl InteractionResponder for CommandResponder<'_> {
+    async fn respond(
+        &self,
+        kind: InteractionResponseType,
+        rsp: &CommandResponse,
+        key: u64,
+        index: usize,
+        count: usize,
+    ) -> Result<()> {
+        self.command
+            .create_interaction_response(self.http, |response| {
+                build_response(response, kind, rsp, key, index, count)
+            })
+            .await
+            .context("Cannot respond to slash command")
+    }
+
+    async fn edit_original(&self, rsp: &CommandResponse, key: u64, count: usize) -> Result<()> {
+        self.command
+            .edit_original_interaction_response(self.http, |edit| {
+                edit_response(edit, rsp, key, count)
+            })
+            .await
+            .context("Cannot edit slash command response")?;
+        Ok(())
+    }
+}
+
+/// Responds to a button press or menu selection.
+pub struct ComponentResponder<'a> {
+    pub http: &'a Http,
+    pub component: &'a MessageComponentInteraction,
+}
+
+#[async_trait]
+impl InteractionResponder for ComponentResponder<'_> {
+    async fn respond(
+        &self,
+        kind: InteractionResponseType,
+        rsp: &CommandResponse,
+        key: u64,
+        index: usize,
+        count: usize,
+    ) -> Result<()> {
+        self.component
+            .create_interaction_response(self.http, |response| {
+                build_response(response, kind, rsp, key, index, count)
+            })
+            .await
+            .context("Cannot respond to message component")
+    }
+
+    async fn edit_original(&self, rsp: &CommandResponse, key: u64, count: usize) -> Result<()> {
+        self.component
+            .edit_original_interaction_response(self.http, |edit| {
+                edit_response(edit, rsp, key, count)
+            })
+            .await
+            .context("Cannot edit message component response")?;
+        Ok(())
+    }
+}
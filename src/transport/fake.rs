@@ -0,0 +1,386 @@
+use anyhow::{anyhow, bail, Result};
+use serenity::{
+    async_trait,
+    model::{
+        id::{ChannelId, GuildId, MessageId, UserId, WebhookId},
+        interactions::InteractionResponseType,
+    },
+};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use super::{
+    Author, ChannelResolver, Destination, DirectMessenger, InteractionResponder, Posted,
+    WebhookSender,
+};
+use crate::{
+    output::OutgoingMessage,
+    thread::{ChannelKind, PublishBudget, Thread},
+    CommandResponse,
+};
+
+// Ids handed out by the fake start here to stay clear of the ones used in tests.
+const FIRST_ID: u64 = 1_000_000;
+
+#[derive(Clone, Debug)]
+pub struct SentMessage {
+    pub id: MessageId,
+    pub webhook: WebhookId,
+    pub channel: ChannelId,
+    /// The thread or forum post it was posted in.
+    pub thread: Option<ChannelId>,
+    /// Name of the forum post it started.
+    pub post_name: Option<String>,
+    pub author: String,
+    pub content: String,
+    /// Description of the embed, if any.
+    pub embed: Option<String>,
+    /// Number of times the message was edited.
+    pub edits: usize,
+    /// Crossposted to the followers of the channel.
+    pub published: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct SentDm {
+    pub user: UserId,
+    pub content: String,
+    /// Description of the embed, if any.
+    pub embed: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SentResponse {
+    /// `None` for an edit of the original response.
+    pub kind: Option<InteractionResponseType>,
+    pub title: String,
+    pub msg: String,
+    pub error: bool,
+    pub public: bool,
+    pub index: usize,
+    pub count: usize,
+}
+
+struct FakeGuild {
+    name: String,
+    channels: Vec<(ChannelId, String, ChannelKind)>,
+    threads: Vec<Thread>,
+}
+
+/// An in-memory stand-in for Discord that records everything sent to it.
+#[derive(Default)]
+pub struct FakeDiscord {
+    guilds: Mutex<HashMap<GuildId, FakeGuild>>,
+    webhooks: Mutex<HashMap<WebhookId, ChannelId>>,
+    reactions: Mutex<HashMap<MessageId, Vec<(String, u64)>>>,
+    next_id: AtomicU64,
+    // Sends left before executing a webhook fails, `None` never fails.
+    send_budget: Mutex<Option<usize>>,
+    sent: Mutex<Vec<SentMessage>>,
+    dms: Mutex<Vec<SentDm>>,
+    responses: Mutex<Vec<SentResponse>>,
+    crossposts: PublishBudget,
+}
+
+impl FakeDiscord {
+    pub fn add_guild(&self, id: u64, name: &str, channels: &[(u64, &str)]) {
+        self.guilds.lock().unwrap().insert(
+            GuildId(id),
+            FakeGuild {
+                name: name.to_owned(),
+                channels: channels
+                    .iter()
+                    .map(|(id, name)| (ChannelId(*id), name.to_string(), ChannelKind::Text))
+                    .collect(),
+                threads: Vec::new(),
+            },
+        );
+    }
+
+    /// Change the kind of a channel added with its guild.
+    pub fn set_kind(&self, channel: u64, kind: ChannelKind) {
+        for guild in self.guilds.lock().unwrap().values_mut() {
+            for (id, _, k) in guild.channels.iter_mut() {
+                if id.0 == channel {
+                    *k = kind;
+                }
+            }
+        }
+    }
+
+    pub fn add_thread(&self, guild: u64, id: u64, name: &str, parent: u64) {
+        let mut guilds = self.guilds.lock().unwrap();
+        let guild = guilds.get_mut(&GuildId(guild)).unwrap();
+        guild.threads.push(Thread {
+            id: ChannelId(id),
+            name: name.to_owned(),
+            parent: ChannelId(parent),
+        });
+    }
+
+    /// Threads started in the channel, including those started by the bot.
+    pub fn threads(&self, parent: u64) -> Vec<Thread> {
+        self.guilds
+            .lock()
+            .unwrap()
+            .values()
+            .flat_map(|g| g.threads.iter())
+            .filter(|t| t.parent.0 == parent)
+            .cloned()
+            .collect()
+    }
+
+    fn next_id(&self) -> u64 {
+        FIRST_ID + self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn start_thread(&self, channel: ChannelId, name: &str) -> ChannelId {
+        let id = ChannelId(self.next_id());
+        for guild in self.guilds.lock().unwrap().values_mut() {
+            if guild.channels.iter().any(|(c, _, _)| *c == channel) {
+                guild.threads.push(Thread {
+                    id,
+                    name: name.to_owned(),
+                    parent: channel,
+                });
+            }
+        }
+        id
+    }
+
+    pub fn set_reactions(&self, message: u64, reactions: &[(&str, u64)]) {
+        self.reactions.lock().unwrap().insert(
+            MessageId(message),
+            reactions
+                .iter()
+                .map(|(emoji, count)| (emoji.to_string(), *count))
+                .collect(),
+        );
+    }
+
+    /// Let `sends` more messages through and fail the ones after, `None`
+    /// goes back to sending everything.
+    pub fn fail_after(&self, sends: Option<usize>) {
+        *self.send_budget.lock().unwrap() = sends;
+    }
+
+    pub fn sent(&self) -> Vec<SentMessage> {
+        self.sent.lock().unwrap().clone()
+    }
+
+    pub fn dms(&self) -> Vec<SentDm> {
+        self.dms.lock().unwrap().clone()
+    }
+
+    pub fn responses(&self) -> Vec<SentResponse> {
+        self.responses.lock().unwrap().clone()
+    }
+
+    fn record_response(
+        &self,
+        kind: Option<InteractionResponseType>,
+        rsp: &CommandResponse,
+        index: usize,
+        count: usize,
+    ) {
+        self.responses.lock().unwrap().push(SentResponse {
+            kind,
+            title: rsp.title.clone(),
+            msg: rsp.msg.clone(),
+            error: rsp.error,
+            public: rsp.public,
+            index,
+            count,
+        });
+    }
+}
+
+fn embed_description(message: &OutgoingMessage) -> Option<String> {
+    message.embed.as_ref().map(|e| {
+        e.0.get("description")
+            .and_then(|d| d.as_str())
+            .unwrap_or_default()
+            .to_owned()
+    })
+}
+
+#[async_trait]
+impl WebhookSender for FakeDiscord {
+    async fn webhook_channel(&self, webhook: WebhookId) -> Result<ChannelId> {
+        self.webhooks
+            .lock()
+            .unwrap()
+            .get(&webhook)
+            .copied()
+            .ok_or(anyhow!("Unknown webhook: {webhook}"))
+    }
+
+    async fn execute(
+        &self,
+        webhook: WebhookId,
+        destination: &Destination,
+        author: &Author,
+        message: &OutgoingMessage,
+    ) -> Result<Posted> {
+        if let Some(budget) = self.send_budget.lock().unwrap().as_mut() {
+            match budget.checked_sub(1) {
+                Some(left) => *budget = left,
+                None => return Err(anyhow!("Failed to execute webhook: {webhook}")),
+            }
+        }
+        let channel = self.webhook_channel(webhook).await?;
+        let (thread, post_name) = match destination {
+            Destination::Channel => (None, None),
+            Destination::Thread(thread) => (Some(*thread), None),
+            Destination::NewPost(name) => {
+                (Some(self.start_thread(channel, name)), Some(name.clone()))
+            }
+        };
+        let id = MessageId(self.next_id());
+        self.sent.lock().unwrap().push(SentMessage {
+            id,
+            webhook,
+            channel,
+            thread,
+            post_name,
+            author: author.name.clone(),
+            content: message.content.clone(),
+            embed: embed_description(message),
+            edits: 0,
+            published: false,
+        });
+        Ok(Posted {
+            id,
+            channel: thread.unwrap_or(channel),
+        })
+    }
+
+    async fn edit(
+        &self,
+        webhook: WebhookId,
+        posted: &Posted,
+        edited: &OutgoingMessage,
+    ) -> Result<()> {
+        let mut sent = self.sent.lock().unwrap();
+        let posted = sent
+            .iter_mut()
+            .find(|m| m.id == posted.id && m.webhook == webhook)
+            .ok_or(anyhow!("Unknown webhook message: {}", posted.id))?;
+        posted.content = edited.content.clone();
+        // Like on Discord the embed is kept unless it's replaced.
+        if edited.embed.is_some() {
+            posted.embed = embed_description(edited);
+        }
+        posted.edits += 1;
+        Ok(())
+    }
+
+    async fn publish(&self, posted: &Posted) -> Result<()> {
+        if !self.crossposts.take(posted.channel) {
+            bail!("Crosspost limit reached in {}", posted.channel);
+        }
+        let mut sent = self.sent.lock().unwrap();
+        let message = sent
+            .iter_mut()
+            .find(|m| m.id == posted.id)
+            .ok_or(anyhow!("Unknown message: {}", posted.id))?;
+        message.published = true;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DirectMessenger for FakeDiscord {
+    async fn send_dm(&self, user: UserId, message: &OutgoingMessage) -> Result<()> {
+        self.dms.lock().unwrap().push(SentDm {
+            user,
+            content: message.content.clone(),
+            embed: embed_description(message),
+        });
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ChannelResolver for FakeDiscord {
+    async fn guild_name(&self, guild: GuildId) -> Result<String> {
+        self.guilds
+            .lock()
+            .unwrap()
+            .get(&guild)
+            .map(|g| g.name.clone())
+            .ok_or(anyhow!("Unknown guild: {guild}"))
+    }
+
+    async fn channels(&self, guild: GuildId) -> Result<Vec<(ChannelId, String, ChannelKind)>> {
+        self.guilds
+            .lock()
+            .unwrap()
+            .get(&guild)
+            .map(|g| g.channels.clone())
+            .ok_or(anyhow!("Unknown guild: {guild}"))
+    }
+
+    async fn active_threads(&self, guild: GuildId) -> Result<Vec<Thread>> {
+        self.guilds
+            .lock()
+            .unwrap()
+            .get(&guild)
+            .map(|g| g.threads.clone())
+            .ok_or(anyhow!("Unknown guild: {guild}"))
+    }
+
+    async fn create_webhook(&self, channel: ChannelId) -> Result<WebhookId> {
+        let id = WebhookId(self.next_id());
+        self.webhooks.lock().unwrap().insert(id, channel);
+        Ok(id)
+    }
+
+    async fn reactions(
+        &self,
+        _channel: ChannelId,
+        message: MessageId,
+    ) -> Result<Vec<(String, u64)>> {
+        Ok(self
+            .reactions
+            .lock()
+            .unwrap()
+            .get(&message)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn create_thread(
+        &self,
+        channel: ChannelId,
+        _kind: ChannelKind,
+        name: &str,
+    ) -> Result<ChannelId> {
+        Ok(self.start_thread(channel, name))
+    }
+}
+
+#[async_trait]
+impl InteractionResponder for FakeDiscord {
+    async fn respond(
+        &self,
+        kind: InteractionResponseType,
+        rsp: &CommandResponse,
+        _key: u64,
+        index: usize,
+        count: usize,
+    ) -> Result<()> {
+        self.record_response(Some(kind), rsp, index, count);
+        Ok(())
+    }
+
+    async fn edit_original(&self, rsp: &CommandResponse, _key: u64, count: usize) -> Result<()> {
+        self.record_response(None, rsp, 0, count);
+        Ok(())
+    }
+}
@@ -0,0 +1,326 @@
+use anyhow::{anyhow, Error, Result};
+use serenity::model::id::UserId;
+use sqlx::{Sqlite, SqlitePool, Transaction};
+
+use crate::digest::unix_now;
+
+// Destructive commands can be undone for this long (in seconds).
+pub const RETENTION: i64 = 24 * 60 * 60;
+
+pub struct Restored {
+    pub command: String,
+    pub created: i64,
+    pub connections: u64,
+    pub mentions: u64,
+}
+
+/// Record a destructive command and drop the ones that can no longer be undone.
+async fn begin_action(tx: &mut Transaction<'_, Sqlite>, user: i64, command: &str) -> Result<i64> {
+    let now = unix_now();
+    let expired = now - RETENTION;
+    sqlx::query!("DELETE FROM TrashActions WHERE created < ?", expired)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| Error::new(e).context("Failed to prune the trash in the database"))?;
+
+    let action = sqlx::query!(
+        "INSERT INTO TrashActions (user, command, created) VALUES (?, ?, ?)",
+        user,
+        command,
+        now
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| Error::new(e).context("Failed to insert trash action into the database"))?
+    .last_insert_rowid();
+
+    Ok(action)
+}
+
+/// Move the connections (and their templates, watchlists, transform rules and
+/// mirrored threads) to the trash.
+pub async fn delete_connections(
+    db: &SqlitePool,
+    user: &UserId,
+    command: &str,
+    ids: &[i64],
+) -> Result<u64> {
+    if ids.is_empty() {
+        return Ok(0);
+    }
+
+    let mut tx = db.begin().await?;
+    let action = begin_action(&mut tx, user.0 as i64, command).await?;
+    for id in ids {
+        sqlx::query!(
+            "
+            INSERT INTO TrashConnections\n\
+            (action, id, source, target, user, webhook, digest_interval, next_digest, rate_limit, overflow, thread, publish, mirror_reactions)\n\
+            SELECT ?, id, source, target, user, webhook, digest_interval, next_digest, rate_limit, overflow, thread, publish, mirror_reactions\n\
+            FROM Connections WHERE id = ?
+            ",
+            action,
+            id
+        )
+        .execute(&mut tx)
+        .await
+        .map_err(|e| Error::new(e).context("Failed to move connection to the trash"))?;
+
+        sqlx::query!(
+            "
+            INSERT INTO TrashTemplates\n\
+            (action, connection, embed, color, title, body, footer, timestamp, mentions, provenance)\n\
+            SELECT ?, connection, embed, color, title, body, footer, timestamp, mentions, provenance\n\
+            FROM Templates WHERE connection = ?
+            ",
+            action,
+            id
+        )
+        .execute(&mut tx)
+        .await
+        .map_err(|e| Error::new(e).context("Failed to move template to the trash"))?;
+
+        sqlx::query!(
+            "
+            INSERT INTO TrashWatchlists (action, connection, symbol)\n\
+            SELECT ?, connection, symbol\n\
+            FROM Watchlists WHERE connection = ?
+            ",
+            action,
+            id
+        )
+        .execute(&mut tx)
+        .await
+        .map_err(|e| Error::new(e).context("Failed to move watchlist to the trash"))?;
+
+        sqlx::query!(
+            "
+            INSERT INTO TrashTransforms (action, connection, id, pattern, replacement)\n\
+            SELECT ?, connection, id, pattern, replacement\n\
+            FROM Transforms WHERE connection = ?
+            ",
+            action,
+            id
+        )
+        .execute(&mut tx)
+        .await
+        .map_err(|e| Error::new(e).context("Failed to move transform rules to the trash"))?;
+
+        sqlx::query!(
+            "
+            INSERT INTO TrashThreadMirrors (action, connection, source, target)\n\
+            SELECT ?, connection, source, target\n\
+            FROM ThreadMirrors WHERE connection = ?
+            ",
+            action,
+            id
+        )
+        .execute(&mut tx)
+        .await
+        .map_err(|e| Error::new(e).context("Failed to move mirrored threads to the trash"))?;
+
+        sqlx::query!("DELETE FROM Connections WHERE id = ?", id)
+            .execute(&mut tx)
+            .await
+            .map_err(|e| Error::new(e).context("Failed to delete connection in the database"))?;
+    }
+    tx.commit().await?;
+
+    Ok(ids.len() as u64)
+}
+
+/// Move the mentions to the trash.
+pub async fn delete_mentions(
+    db: &SqlitePool,
+    user: &UserId,
+    command: &str,
+    ids: &[i64],
+) -> Result<u64> {
+    if ids.is_empty() {
+        return Ok(0);
+    }
+
+    let mut tx = db.begin().await?;
+    let action = begin_action(&mut tx, user.0 as i64, command).await?;
+    for id in ids {
+        sqlx::query!(
+            "
+            INSERT INTO TrashMentions\n\
+            (action, id, source, target, mention, user, condition_kind, condition)\n\
+            SELECT ?, id, source, target, mention, user, condition_kind, condition\n\
+            FROM Mentions WHERE id = ?
+            ",
+            action,
+            id
+        )
+        .execute(&mut tx)
+        .await
+        .map_err(|e| Error::new(e).context("Failed to move mention to the trash"))?;
+
+        sqlx::query!("DELETE FROM Mentions WHERE id = ?", id)
+            .execute(&mut tx)
+            .await
+            .map_err(|e| Error::new(e).context("Failed to delete mention in the database"))?;
+    }
+    tx.commit().await?;
+
+    Ok(ids.len() as u64)
+}
+
+/// Restore everything removed by the user's most recent destructive command.
+pub async fn undo(db: &SqlitePool, user: &UserId) -> Result<Restored> {
+    let user = user.0 as i64;
+    let oldest = unix_now() - RETENTION;
+    let action = sqlx::query!(
+        "
+        SELECT id, command, created\n\
+        FROM TrashActions\n\
+        WHERE user = ? AND created >= ?\n\
+        ORDER BY created DESC, id DESC\n\
+        LIMIT 1
+        ",
+        user,
+        oldest
+    )
+    .fetch_optional(db)
+    .await
+    .map_err(|e| Error::new(e).context("Failed to retrieve trash from the database"))?
+    .ok_or(anyhow!("Nothing to undo"))?;
+
+    let mut tx = db.begin().await?;
+
+    // Connections get a new id, since SQLite may have handed the old one to a
+    // connection created in the meantime. Their rows in the other tables are
+    // moved over to the new id. Connections that were created again in the
+    // meantime are skipped.
+    let trashed = sqlx::query!(
+        "
+        SELECT id, source, target, user, webhook, digest_interval, next_digest, rate_limit, overflow, thread, publish, mirror_reactions\n\
+        FROM TrashConnections WHERE action = ?
+        ",
+        action.id
+    )
+    .fetch_all(&mut tx)
+    .await
+    .map_err(|e| Error::new(e).context("Failed to retrieve connections from the trash"))?;
+
+    let mut connections = 0;
+    for c in trashed {
+        let inserted = sqlx::query!(
+            "
+            INSERT OR IGNORE INTO Connections\n\
+            (source, target, user, webhook, digest_interval, next_digest, rate_limit, overflow, thread, publish, mirror_reactions)\n\
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ",
+            c.source,
+            c.target,
+            c.user,
+            c.webhook,
+            c.digest_interval,
+            c.next_digest,
+            c.rate_limit,
+            c.overflow,
+            c.thread,
+            c.publish,
+            c.mirror_reactions
+        )
+        .execute(&mut tx)
+        .await
+        .map_err(|e| Error::new(e).context("Failed to restore connection from the trash"))?;
+        if inserted.rows_affected() == 0 {
+            continue;
+        }
+        connections += 1;
+        let id = inserted.last_insert_rowid();
+
+        sqlx::query!(
+            "
+            INSERT OR IGNORE INTO Templates\n\
+            (connection, embed, color, title, body, footer, timestamp, mentions, provenance)\n\
+            SELECT ?, embed, color, title, body, footer, timestamp, mentions, provenance\n\
+            FROM TrashTemplates WHERE action = ? AND connection = ?
+            ",
+            id,
+            action.id,
+            c.id
+        )
+        .execute(&mut tx)
+        .await
+        .map_err(|e| Error::new(e).context("Failed to restore template from the trash"))?;
+
+        sqlx::query!(
+            "
+            INSERT OR IGNORE INTO Watchlists (connection, symbol)\n\
+            SELECT ?, symbol\n\
+            FROM TrashWatchlists WHERE action = ? AND connection = ?
+            ",
+            id,
+            action.id,
+            c.id
+        )
+        .execute(&mut tx)
+        .await
+        .map_err(|e| Error::new(e).context("Failed to restore watchlist from the trash"))?;
+
+        sqlx::query!(
+            "
+            INSERT OR IGNORE INTO Transforms (connection, pattern, replacement)\n\
+            SELECT ?, pattern, replacement\n\
+            FROM TrashTransforms WHERE action = ? AND connection = ?\n\
+            ORDER BY id
+            ",
+            id,
+            action.id,
+            c.id
+        )
+        .execute(&mut tx)
+        .await
+        .map_err(|e| Error::new(e).context("Failed to restore transform rules from the trash"))?;
+
+        sqlx::query!(
+            "
+            INSERT OR IGNORE INTO ThreadMirrors (connection, source, target)\n\
+            SELECT ?, source, target\n\
+            FROM TrashThreadMirrors WHERE action = ? AND connection = ?
+            ",
+            id,
+            action.id,
+            c.id
+        )
+        .execute(&mut tx)
+        .await
+        .map_err(|e| Error::new(e).context("Failed to restore mirrored threads from the trash"))?;
+    }
+
+    let mentions = sqlx::query!(
+        "
+        INSERT INTO Mentions (source, target, mention, user, condition_kind, condition)\n\
+        SELECT source, target, mention, user, condition_kind, condition\n\
+        FROM TrashMentions AS t\n\
+        WHERE action = ? AND NOT EXISTS (\n\
+            SELECT 1 FROM Mentions AS m\n\
+            WHERE m.source IS t.source AND m.target = t.target AND m.mention = t.mention\n\
+            AND m.user = t.user AND m.condition_kind IS t.condition_kind AND m.condition IS t.condition\n\
+        )
+        ",
+        action.id
+    )
+    .execute(&mut tx)
+    .await
+    .map_err(|e| Error::new(e).context("Failed to restore mentions from the trash"))?
+    .rows_affected();
+
+    sqlx::query!("DELETE FROM TrashActions WHERE id = ?", action.id)
+        .execute(&mut tx)
+        .await
+        .map_err(|e| Error::new(e).context("Failed to empty the trash in the database"))?;
+
+    tx.commit().await?;
+
+    Ok(Restored {
+        command: action.command,
+        created: action.created,
+        connections,
+        mentions,
+    })
+}